@@ -0,0 +1,19 @@
+use std::path::Path;
+use ndarray::{Array, Dimension, IxDyn, ShapeBuilder};
+use num_traits::FromPrimitive;
+use crate::header_defs::NRRDType;
+use crate::{read_nrrd_to, NRRD};
+
+/// reads the nrrd and converts the data to T as in 'read_nrrd_to', returning it as an array with
+/// the shape of the header. NRRD stores the first axis fastest, so the array is laid out in
+/// column-major (Fortran) order with the axes in header order: 'a[[x, y, z]]' is the same sample
+/// as teem's 'x + sx*(y + sy*z)'. Use 'D = IxDyn' for an 'ArrayD', or a fixed dimension such as
+/// 'Ix3'. This panics if the dimension of the file doesn't match D
+pub fn read_nrrd_to_array<T:NRRDType + FromPrimitive, D:Dimension>(filepath:impl AsRef<Path>) -> (Array<T,D>, NRRD) {
+    let (x,h) = read_nrrd_to::<T>(filepath);
+    let a = Array::from_shape_vec(IxDyn(h.shape()).f(), x)
+        .expect("data length matches the header sizes")
+        .into_dimensionality::<D>()
+        .unwrap_or_else(|_| panic!("expected a {:?}-dimensional array but the nrrd has {} axes", D::NDIM, h.shape().len()));
+    (a,h)
+}
@@ -0,0 +1,96 @@
+use std::io::{Cursor, SeekFrom};
+use std::path::Path;
+use num_traits::FromPrimitive;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader};
+use crate::header_defs::{Encoding, NRRDType};
+use crate::{check_read_len, decode_payload, io, read_header_from, read_payload_cancellable, NrrdError, NRRD};
+
+/// number of bytes read at a time while looking for the end of the header
+const HEADER_CHUNK:usize = 4096;
+
+/// reads the nrrd header and all associated data bytes into a single vector without blocking the
+/// executor. Raw data is read with 'tokio::fs'. Compressed and text data, and data located with a
+/// byte skip of -1, are read by 'read_payload' on the blocking thread pool
+pub async fn read_payload_async(filepath:impl AsRef<Path>) -> (Vec<u8>, NRRD) {
+    try_read_payload_async(filepath.as_ref()).await.unwrap()
+}
+
+/// reads the nrrd and converts the data to T as in 'read_nrrd_to', reading the file as in
+/// 'read_payload_async'
+pub async fn read_nrrd_to_async<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> (Vec<T>, NRRD) {
+    let (bytes,h) = read_payload_async(filepath).await;
+    let x = decode_payload(bytes, &h);
+    (x,h)
+}
+
+async fn try_read_payload_async(filepath:&Path) -> Result<(Vec<u8>, NRRD),NrrdError> {
+
+    let mut f = File::open(filepath).await?;
+    let (h,data_start) = read_header_async(&mut f).await?;
+
+    let read_tail = h.byte_skip.as_ref().is_some_and(|bs| bs.read_tail());
+    if h.encoding != Encoding::raw || read_tail {
+        let filepath = filepath.to_path_buf();
+        return tokio::task::spawn_blocking(move || read_payload_cancellable(filepath, None)).await
+            .unwrap_or_else(|_| Err(std::io::Error::other("the blocking read of the nrrd panicked").into()));
+    }
+
+    let mut bytes = vec![0u8;h.expected_bytes()];
+    if h.data_file.is_some() {
+        let resolved_paths = h.resolved_data_paths_in(filepath.parent().unwrap())?;
+        let bytes_per_file = h.elements_per_file(resolved_paths.len())? * h.element_size();
+        for (chunk,file) in bytes.chunks_exact_mut(bytes_per_file).zip(&resolved_paths) {
+            read_raw_async(file, 0, &h, chunk).await?;
+        }
+    }else {
+        read_raw_async(filepath, data_start, &h, &mut bytes).await?;
+    }
+    Ok((bytes,h))
+}
+
+/// reads the file until the blank line that ends the header and parses it, returning the header and
+/// the offset of the data that follows it
+async fn read_header_async(f:&mut File) -> Result<(NRRD, u64),NrrdError> {
+    let mut buf = vec![];
+    let mut chunk = vec![0u8;HEADER_CHUNK];
+    loop {
+        let n = f.read(&mut chunk).await?;
+        buf.extend_from_slice(&chunk[..n]);
+        let (_,blank) = io::read_until_blank(&mut Cursor::new(&buf))?;
+        if blank.is_some() || n == 0 {
+            let mut cursor = Cursor::new(&buf);
+            let h = read_header_from(&mut cursor)?;
+            return Ok((h, cursor.position()));
+        }
+    }
+}
+
+/// reads raw data starting at 'start' into 'bytes', after the lines and bytes skipped by the header
+async fn read_raw_async(path:&Path, start:u64, h:&NRRD, bytes:&mut [u8]) -> Result<(),NrrdError> {
+
+    let line_skip = h.line_skip.as_ref().map(|ls| ls.to_skip()).unwrap_or(0);
+    let byte_skip = h.byte_skip.as_ref().map(|bs| bs.to_skip()).unwrap_or(0);
+
+    let mut f = BufReader::new(File::open(path).await?);
+    f.seek(SeekFrom::Start(start)).await?;
+    let mut line = vec![];
+    for _ in 0..line_skip {
+        line.clear();
+        if f.read_until(b'\n', &mut line).await? == 0 {
+            break
+        }
+    }
+    tokio::io::copy(&mut (&mut f).take(byte_skip as u64), &mut tokio::io::sink()).await?;
+
+    let mut n = 0;
+    while n < bytes.len() {
+        let n_read = f.read(&mut bytes[n..]).await?;
+        if n_read == 0 {
+            break
+        }
+        n += n_read;
+    }
+    check_read_len(path, n, bytes.len())?;
+    Ok(())
+}
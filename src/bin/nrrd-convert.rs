@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use std::process::exit;
+use clap::Parser;
+use nrrd_rs::header_defs::Encoding;
+use nrrd_rs::{read_header, supports_writing};
+
+#[derive(Parser, Debug)]
+/// rewrites a NRRD as an attached .nrrd or a detached .nhdr + data file
+struct Args {
+    /// path to the .nrrd or .nhdr to convert
+    input:PathBuf,
+
+    /// output path. The extension is replaced with .nrrd or .nhdr to match the layout
+    output:PathBuf,
+
+    /// write a single .nrrd file
+    #[clap(long, conflicts_with = "detached")]
+    attached:bool,
+
+    /// write a .nhdr header with a separate data file
+    #[clap(long)]
+    detached:bool,
+
+    /// encoding of the written data.
+    /// Example: `-e raw`, `-e gz` or `-e bz2`. Default is the encoding of the input
+    #[clap(short,long)]
+    encoding:Option<String>,
+}
+
+fn main() {
+
+    let args = Args::parse();
+
+    let mut h = read_header(&args.input);
+    let src_attached = h.data_file.is_none();
+
+    // without a layout flag, the layout follows the output extension
+    let attached = if args.attached {
+        true
+    }else if args.detached {
+        false
+    }else {
+        args.output.extension().map(|e| e != "nhdr").unwrap_or(true)
+    };
+
+    let encoding = match &args.encoding {
+        Some(e) => format!("encoding: {e}").parse::<Encoding>().unwrap_or_else(|_|{
+            eprintln!("unknown encoding '{e}'");
+            exit(1);
+        }),
+        None => h.encoding,
+    };
+    if !supports_writing(encoding) {
+        eprintln!("encoding '{}' can't be written", encoding.name());
+        exit(1);
+    }
+
+    let output = args.output.with_extension(if attached {"nrrd"} else {"nhdr"});
+    if output == args.input && attached == src_attached && encoding == h.encoding {
+        println!("{} is already {} with encoding '{}'", args.input.display(), if attached {"attached"} else {"detached"}, encoding.name());
+        return
+    }
+
+    // the output data file is named after the output header
+    h.data_file = None;
+    let result = if attached {
+        h.attach(&args.input, &output, encoding)
+    }else {
+        h.detach(&args.input, &output, encoding)
+    };
+    if let Err(e) = result {
+        eprintln!("failed to convert {}: {e}", args.input.display());
+        exit(1);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use clap::Parser;
+use nrrd_rs::header_defs::{HeaderDef, NrrdVec};
+use nrrd_rs::read_header;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// path to a .nhdr or .nrrd file
+    header:PathBuf,
+    /// print the JSON on a single line
+    #[clap(short,long)]
+    compact:bool,
+}
+
+/// returns the value of a header field without its field name
+fn field_value<F:HeaderDef + Display>(field:&F) -> String {
+    let s = field.to_string();
+    s.strip_prefix(F::patterns()[0]).unwrap_or(&s).trim().to_string()
+}
+
+/// a JSON value built from header fields
+enum Json {
+    Null,
+    Number(f64),
+    Int(usize),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn str(s:impl Display) -> Json {
+        Json::Str(s.to_string())
+    }
+
+    /// NaN and infinite values have no JSON representation and are written as null
+    fn num(x:f64) -> Json {
+        if x.is_finite() {Json::Number(x)} else {Json::Null}
+    }
+
+    fn nums(x:&[f64]) -> Json {
+        Json::Array(x.iter().map(|&x| Json::num(x)).collect())
+    }
+
+    fn strs<S:Display>(x:&[S]) -> Json {
+        Json::Array(x.iter().map(Json::str).collect())
+    }
+
+    fn vec(v:&NrrdVec) -> Json {
+        Json::nums(v.values())
+    }
+
+    fn write(&self, out:&mut String, indent:Option<usize>) {
+        let newline = |out:&mut String, level:usize| {
+            if let Some(n) = indent {
+                out.push('\n');
+                out.push_str(&"  ".repeat(n + level));
+            }
+        };
+        let inner = indent.map(|n| n + 1);
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Number(x) => out.push_str(&x.to_string()),
+            Json::Int(x) => out.push_str(&x.to_string()),
+            Json::Str(s) => write_str(out, s),
+            Json::Array(items) => {
+                out.push('[');
+                // keep arrays of numbers on one line
+                let nested = items.iter().any(|i| matches!(i, Json::Array(_) | Json::Object(_)));
+                for (i,item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                        if !nested || indent.is_none() {out.push(' ')}
+                    }
+                    if nested {newline(out, 1)}
+                    item.write(out, if nested {inner} else {None});
+                }
+                if nested && !items.is_empty() {newline(out, 0)}
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (i,(key,value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                        if indent.is_none() {out.push(' ')}
+                    }
+                    newline(out, 1);
+                    write_str(out, key);
+                    out.push_str(": ");
+                    value.write(out, inner);
+                }
+                if !fields.is_empty() {newline(out, 0)}
+                out.push('}');
+            }
+        }
+    }
+}
+
+/// writes a quoted and escaped JSON string
+fn write_str(out:&mut String, s:&str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn main() {
+
+    let args = Args::parse();
+
+    let h = read_header(&args.header);
+
+    let mut fields = vec![
+        ("magic".to_string(), Json::str(h.magic)),
+        ("dimension".to_string(), Json::Int(h.dimension.dim())),
+        ("type".to_string(), Json::str(h.dtype.name())),
+        ("encoding".to_string(), Json::str(h.encoding.name())),
+        ("endian".to_string(), Json::str(field_value(&h.endian))),
+        ("sizes".to_string(), Json::Array(h.shape().iter().map(|&s| Json::Int(s)).collect())),
+    ];
+    let mut add = |key:&str, value:Json| fields.push((key.to_string(), value));
+
+    if let Some(bs) = &h.block_size {
+        add("block_size", Json::Int(bs.size()));
+    }
+    if let Some(c) = &h.content {
+        add("content", Json::str(c.text()));
+    }
+    if let Some(m) = &h.min {
+        add("min", Json::num(m.value()));
+    }
+    if let Some(m) = &h.max {
+        add("max", Json::num(m.value()));
+    }
+    if let Some(m) = &h.old_min {
+        add("old_min", Json::num(m.value()));
+    }
+    if let Some(m) = &h.old_max {
+        add("old_max", Json::num(m.value()));
+    }
+    if let Some(df) = &h.data_file {
+        add("data_files", Json::Array(df.paths().iter().map(|p| Json::str(p.display())).collect()));
+    }
+    if let Some(ls) = &h.line_skip {
+        add("line_skip", Json::Int(ls.to_skip()));
+    }
+    if let Some(bs) = &h.byte_skip {
+        if bs.read_tail() {
+            add("byte_skip", Json::Number(-1.));
+        }else {
+            add("byte_skip", Json::Int(bs.to_skip()));
+        }
+    }
+    if let Some(u) = &h.sample_units {
+        add("sample_units", Json::str(u.units()));
+    }
+
+    if let Some(s) = &h.spacings {
+        add("spacings", Json::nums(s.values()));
+    }
+    if let Some(t) = &h.thicknesses {
+        add("thicknesses", Json::nums(t.values()));
+    }
+    if let Some(m) = &h.axis_mins {
+        add("axis_mins", Json::nums(m.values()));
+    }
+    if let Some(m) = &h.axis_maxs {
+        add("axis_maxs", Json::nums(m.values()));
+    }
+    if let Some(c) = &h.centerings {
+        add("centerings", Json::strs(c.values()));
+    }
+    if let Some(l) = &h.labels {
+        add("labels", Json::strs(l.values()));
+    }
+    if let Some(u) = &h.units {
+        add("units", Json::strs(u.values()));
+    }
+    if let Some(k) = &h.kinds {
+        add("kinds", Json::strs(&k.kinds));
+    }
+
+    if let Some(s) = &h.space {
+        add("space", Json::str(field_value(s)));
+    }
+    if let Some(sd) = &h.space_dimension {
+        add("space_dimension", Json::Int(sd.dim()));
+    }
+    if let Some(u) = &h.space_units {
+        add("space_units", Json::strs(u.values()));
+    }
+    if let Some(o) = &h.space_origin {
+        add("space_origin", Json::nums(o.coords()));
+    }
+    if let Some(sd) = &h.space_directions {
+        let dirs = sd.vectors().iter().map(|v| v.as_ref().map(Json::vec).unwrap_or(Json::Null)).collect();
+        add("space_directions", Json::Array(dirs));
+    }
+    if let Some(mf) = &h.measurement_frame {
+        add("measurement_frame", Json::Array(mf.vectors().iter().map(Json::vec).collect()));
+    }
+
+    let mut keys:Vec<_> = h.key_vals.keys().collect();
+    keys.sort();
+    let key_vals = keys.into_iter().map(|k| (k.clone(), Json::str(&h.key_vals[k].val))).collect();
+    add("key_vals", Json::Object(key_vals));
+    add("comments", Json::strs(&h.comments));
+
+    let mut out = String::new();
+    Json::Object(fields).write(&mut out, if args.compact {None} else {Some(0)});
+    println!("{out}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_string(json:&Json) -> String {
+        let mut out = String::new();
+        json.write(&mut out, None);
+        out
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let mut out = String::new();
+        write_str(&mut out, "say \"hi\" C:\\dir\n\r\t\u{1}\u{1f} ok");
+        assert_eq!(out, r#""say \"hi\" C:\\dir\n\r\t\u0001\u001f ok""#);
+    }
+
+    #[test]
+    fn non_finite_numbers_are_null() {
+        let json = Json::nums(&[1.5, f64::NAN, f64::INFINITY, f64::NEG_INFINITY]);
+        assert_eq!(to_string(&json), "[1.5, null, null, null]");
+    }
+}
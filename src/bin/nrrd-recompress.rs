@@ -0,0 +1,240 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use bzip2::read::MultiBzDecoder;
+use clap::Parser;
+use flate2::read::MultiGzDecoder;
+use nrrd_rs::header_defs::{DataFile, Encoding, HeaderDef};
+use nrrd_rs::{io, read_payload_from_reader, supports_writing, NRRD};
+
+#[derive(Parser, Debug)]
+/// re-encodes the data of a NRRD, leaving the rest of the header exactly as it is
+struct Args {
+    /// path to the .nrrd or .nhdr to recompress
+    input:PathBuf,
+
+    /// encoding of the written data.
+    /// Example: `-e raw`, `-e gz` or `-e bz2`
+    #[clap(short,long)]
+    encoding:String,
+
+    /// path to write to. The input is rewritten in place if this isn't given
+    #[clap(short,long)]
+    output:Option<PathBuf>,
+}
+
+fn fail(msg:impl AsRef<str>) -> ! {
+    eprintln!("{}", msg.as_ref());
+    exit(1);
+}
+
+/// the header of a file as written: its lines with their line endings, and the blank line that
+/// ends it, if there is one
+struct RawHeader {
+    lines:Vec<u8>,
+    blank:Vec<u8>,
+}
+
+fn read_raw_header(path:&Path) -> Result<RawHeader,String> {
+    let mut f = File::open(path).map_err(|e| format!("unable to open {}: {e}", path.display()))?;
+    let (lines,blank_end) = io::read_until_blank(&mut f).map_err(|e| format!("failed to read header: {e}"))?;
+    let blank = match blank_end {
+        Some(end) => {
+            let bytes = fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+            bytes[lines.len()..end as usize].to_vec()
+        }
+        None => vec![],
+    };
+    Ok(RawHeader {lines, blank})
+}
+
+/// replaces the encoding line, and the data file line if 'data_file' is given, keeping every other
+/// byte of the header, line endings included
+fn rewrite_header(lines:&[u8], encoding:Encoding, data_file:Option<&DataFile>) -> Vec<u8> {
+    let mut out = vec![];
+    for line in lines.split_inclusive(|&b| b == b'\n') {
+        let ending:&[u8] = if line.ends_with(b"\r\n") {b"\r\n"} else if line.ends_with(b"\n") {b"\n"} else {b""};
+        let text = String::from_utf8_lossy(&line[..line.len() - ending.len()]);
+        let replacement = if Encoding::matches(&text) {
+            Some(encoding.to_string())
+        }else {
+            data_file.filter(|_| DataFile::matches(&text)).map(|df| df.to_string())
+        };
+        match replacement {
+            Some(new) => {
+                out.extend_from_slice(new.as_bytes());
+                out.extend_from_slice(ending);
+            }
+            None => out.extend_from_slice(line),
+        }
+    }
+    out
+}
+
+/// returns what the line and byte skips of the header pass over in the data stream: the skipped
+/// lines as they are in the file and the skipped bytes of the decoded data. They are written again
+/// in front of the new data so that the skip lines still hold
+fn skipped(h:&NRRD, data:impl Read, n_bytes:usize) -> Result<(Vec<u8>, Vec<u8>),String> {
+    let line_skip = h.line_skip.as_ref().map(|ls| ls.to_skip()).unwrap_or(0);
+    let (byte_skip,read_tail) = h.byte_skip.as_ref().map(|bs| (bs.to_skip(),bs.read_tail())).unwrap_or((0,false));
+
+    let mut r = BufReader::new(data);
+    let mut lines = vec![];
+    for _ in 0..line_skip {
+        r.read_until(b'\n', &mut lines).map_err(|e| e.to_string())?;
+    }
+    if byte_skip == 0 && !read_tail {
+        return Ok((lines, vec![]));
+    }
+
+    let mut decoded = vec![];
+    let read = match h.encoding {
+        Encoding::raw => r.read_to_end(&mut decoded),
+        Encoding::rawgz => MultiGzDecoder::new(r).read_to_end(&mut decoded),
+        Encoding::rawbz2 => MultiBzDecoder::new(r).read_to_end(&mut decoded),
+        Encoding::txt | Encoding::hex => return Err("skipped bytes of text data can't be carried over".to_string()),
+    };
+    read.map_err(|e| e.to_string())?;
+    let n_prefix = if read_tail {decoded.len().saturating_sub(n_bytes)} else {byte_skip.min(decoded.len())};
+    decoded.truncate(n_prefix);
+    Ok((lines, decoded))
+}
+
+fn recompress(args:&Args) -> Result<(),String> {
+
+    let encoding = format!("encoding: {}", args.encoding).parse::<Encoding>()
+        .map_err(|_| format!("unknown encoding '{}'", args.encoding))?;
+    if !supports_writing(encoding) {
+        return Err(format!("encoding '{}' can't be written", encoding.name()));
+    }
+
+    let header = read_raw_header(&args.input)?;
+    let mut f = File::open(&args.input).map_err(|e| e.to_string())?;
+    let (bytes,h) = read_payload_from_reader(&mut f, args.input.parent()).map_err(|e| e.to_string())?;
+
+    let output = args.output.clone().unwrap_or(args.input.clone());
+    let attached = match &h.data_file {
+        None => true,
+        Some(DataFile::SingleFile {..}) => false,
+        Some(_) => return Err("only attached and single-file detached data can be recompressed".to_string()),
+    };
+
+    // the new data file sits next to the header and is named after it
+    let data_path = output.with_extension(encoding.file_ext());
+    let data_name = Path::new(output.file_name().unwrap()).with_extension(encoding.file_ext());
+    let old_data_path = if attached {
+        args.input.clone()
+    }else {
+        h.resolved_data_paths(&args.input).map_err(|e| e.to_string())?[0].clone()
+    };
+
+    // the skipped region of the old data is read before anything is overwritten
+    let mut old_data = File::open(&old_data_path).map_err(|e| e.to_string())?;
+    if attached {
+        io::read_until_blank(&mut old_data).map_err(|e| e.to_string())?;
+    }
+    let (skipped_lines, skipped_bytes) = skipped(&h, old_data, bytes.len())?;
+    let payload = [skipped_bytes, bytes].concat();
+
+    let new_data_file = (!attached).then_some(DataFile::SingleFile {filename: data_name});
+    let new_header = rewrite_header(&header.lines, encoding, new_data_file.as_ref());
+
+    let written = if attached {
+        File::create(&output).and_then(|mut f|{
+            f.write_all(&new_header)?;
+            f.write_all(&header.blank)?;
+            f.write_all(&skipped_lines)?;
+            encoding.write_payload(&mut f, &payload);
+            Ok(())
+        })
+    }else {
+        File::create(&data_path).and_then(|mut f|{
+            f.write_all(&skipped_lines)?;
+            encoding.write_payload(&mut f, &payload);
+            let mut hf = File::create(&output)?;
+            hf.write_all(&new_header)?;
+            hf.write_all(&header.blank)
+        })
+    };
+    written.map_err(|e| format!("failed to write {}: {e}", output.display()))?;
+
+    // a detached data file that was recompressed in place is replaced by the new one
+    let same_file = |a:&Path, b:&Path| fs::canonicalize(a).ok() == fs::canonicalize(b).ok();
+    if args.output.is_none() && !attached && !same_file(&old_data_path, &data_path) {
+        fs::remove_file(&old_data_path).map_err(|e| format!("failed to remove {}: {e}", old_data_path.display()))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = recompress(&args) {
+        fail(e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nrrd_rs::read_nrrd_to;
+
+    fn args(input:&str, encoding:&str, output:Option<&str>) -> Args {
+        Args {input: input.into(), encoding: encoding.to_string(), output: output.map(PathBuf::from)}
+    }
+
+    /// returns the header lines that differ between the two files
+    fn changed_lines(a:&str, b:&str) -> Vec<(String, String)> {
+        let (a,b) = (read_raw_header(Path::new(a)).unwrap(), read_raw_header(Path::new(b)).unwrap());
+        let split = |x:&[u8]| x.split_inclusive(|&c| c == b'\n').map(|l| String::from_utf8_lossy(l).to_string()).collect::<Vec<_>>();
+        let (a,b) = (split(&a.lines), split(&b.lines));
+        assert_eq!(a.len(), b.len());
+        a.into_iter().zip(b).filter(|(x,y)| x != y).collect()
+    }
+
+    #[test]
+    fn only_the_encoding_line_changes() {
+        // CRLF line endings, a comment, key-values and skips must all survive
+        let data:Vec<u16> = (0..12).collect();
+        let header = "NRRD0005\r\n# keep me\r\ntype: uint16\r\ndimension: 2\r\nsizes: 3 4\r\nendian: little\r\n\
+            encoding: raw\r\nline skip: 1\r\nbyte skip: 4\r\nsite:=somewhere\r\n\r\n";
+        let mut file = header.as_bytes().to_vec();
+        file.extend_from_slice(b"skipped line\n\x01\x02\x03\x04");
+        file.extend_from_slice(bytemuck::cast_slice(&data));
+        fs::write("test_out_recompress_in.nrrd", &file).unwrap();
+
+        recompress(&args("test_out_recompress_in.nrrd", "gz", Some("test_out_recompress_out.nrrd"))).unwrap();
+        let changed = changed_lines("test_out_recompress_in.nrrd", "test_out_recompress_out.nrrd");
+        assert_eq!(changed, vec![("encoding: raw\r\n".to_string(), "encoding: gzip\r\n".to_string())]);
+        assert_eq!(read_raw_header(Path::new("test_out_recompress_out.nrrd")).unwrap().blank, b"\r\n");
+        assert_eq!(read_nrrd_to::<u16>("test_out_recompress_out.nrrd").0, data);
+
+        // and back again, in place
+        recompress(&args("test_out_recompress_out.nrrd", "raw", None)).unwrap();
+        assert_eq!(fs::read("test_out_recompress_out.nrrd").unwrap(), file);
+
+        fs::remove_file("test_out_recompress_in.nrrd").unwrap();
+        fs::remove_file("test_out_recompress_out.nrrd").unwrap();
+    }
+
+    #[test]
+    fn detached_data_file_line_follows_the_encoding() {
+        let data:Vec<f32> = (0..6).map(|x| x as f32).collect();
+        NRRD::new_from_dims::<f32>(&[2,3]).write("test_out_recompress_det", &data, false, Encoding::raw).unwrap();
+        recompress(&args("test_out_recompress_det.nhdr", "bz2", None)).unwrap();
+
+        assert!(!Path::new("test_out_recompress_det.raw").exists());
+        let (x,h) = read_nrrd_to::<f32>("test_out_recompress_det.nhdr");
+        assert_eq!(x, data);
+        assert_eq!(h.encoding, Encoding::rawbz2);
+        fs::remove_file("test_out_recompress_det.nhdr").unwrap();
+        fs::remove_file("test_out_recompress_det.raw.bz2").unwrap();
+    }
+
+    #[test]
+    fn bad_encoding_is_an_error() {
+        assert_eq!(recompress(&args("missing.nrrd", "zip", None)), Err("unknown encoding 'zip'".to_string()));
+        assert!(recompress(&args("missing.nrrd", "hex", None)).unwrap_err().contains("can't be written"));
+    }
+}
@@ -1,7 +1,8 @@
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::exit;
 use clap::Parser;
 use nrrd_rs::header_defs::DataFile;
 use nrrd_rs::NRRD;
@@ -13,50 +14,225 @@ struct Args {
     src_nhdr:PathBuf,
     /// new file name
     dst_nhdr:PathBuf,
+    /// print the planned renames without changing anything
+    #[clap(long)]
+    dry_run:bool,
+    /// overwrite an existing header or data file at the destination
+    #[clap(short,long)]
+    force:bool,
 }
 
-fn main() {
+/// returns the new data file entry and the new name of each data file, relative to the directory
+/// of the new header. Multi-file data is named after the new header with an index
+fn renamed_data_file(data_file:&DataFile, dst_hdr:&Path, ext:&str) -> (DataFile, Vec<PathBuf>) {
+    let stem = dst_hdr.file_stem().unwrap().to_str().unwrap();
+    match data_file {
+        DataFile::SingleFile {..} => {
+            let filename = PathBuf::from(format!("{stem}.{ext}"));
+            (DataFile::SingleFile {filename: filename.clone()}, vec![filename])
+        }
+        DataFile::List {file_paths, sub_dim} => {
+            let width = file_paths.len().saturating_sub(1).to_string().len();
+            let file_paths:Vec<_> = (0..file_paths.len())
+                .map(|i| PathBuf::from(format!("{stem}_{i:0width$}.{ext}")))
+                .collect();
+            (DataFile::List {file_paths: file_paths.clone(), sub_dim: *sub_dim}, file_paths)
+        }
+        DataFile::FileFormat {min, max, step, sub_dim, ..} => {
+            let width = min.unsigned_abs().max(max.unsigned_abs()).to_string().len();
+            let df = DataFile::FileFormat {
+                fmt_string: format!("{stem}_%0{width}d.{ext}"),
+                min: *min,
+                max: *max,
+                step: *step,
+                sub_dim: *sub_dim,
+            };
+            let paths = df.paths();
+            (df, paths)
+        }
+    }
+}
 
-    let args = Args::parse();
+/// moves each file to its destination. If any move fails, the files already moved are put back
+fn move_all(moves:&[(PathBuf, PathBuf)]) -> std::io::Result<()> {
+    for (i,(src,dst)) in moves.iter().enumerate() {
+        if let Err(e) = fs::rename(src, dst) {
+            undo_moves(&moves[..i]);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// moves files back to where they came from, in reverse order
+fn undo_moves(moves:&[(PathBuf, PathBuf)]) {
+    for (src,dst) in moves.iter().rev() {
+        if let Err(e) = fs::rename(dst, src) {
+            eprintln!("failed to move {} back to {}: {e}", dst.display(), src.display());
+        }
+    }
+}
+
+/// renames the header and its data files as described by 'args'
+fn rename(args:&Args) -> Result<(),String> {
 
     let src_hdr = args.src_nhdr.with_extension("nhdr");
     let dst_hdr = args.dst_nhdr.with_extension("nhdr");
 
-    let mut f = match File::open(&src_hdr) {
-        Ok(f) => f,
-        Err(e) => panic!("unable to open nhdr: {} with error {e}", src_hdr.display()),
-    };
+    let mut f = File::open(&src_hdr)
+        .map_err(|e| format!("unable to open nhdr: {} with error {e}", src_hdr.display()))?;
 
-    let (header_bytes,_offset) = io::read_until_blank(&mut f).expect("failed to read header");
-    let header_str = String::from_utf8(header_bytes).expect("failed to convert bytes to string");
+    let (header_bytes,_offset) = io::read_until_blank(&mut f).map_err(|e| format!("failed to read header: {e}"))?;
+    let header_str = String::from_utf8(header_bytes).map_err(|_| "failed to convert bytes to string".to_string())?;
     let mut header_lines = header_str.lines().collect::<Vec<&str>>();
     let mut nrrd = NRRD::from_lines_full(&mut header_lines);
 
     let encoding = nrrd.encoding.to_owned();
-    let dst_data_file = dst_hdr.with_extension(encoding.file_ext());
 
-    match nrrd.data_file.as_mut() {
-        Some(data_file) => {
-            if let DataFile::SingleFile{filename} = data_file {
-                *filename = PathBuf::from(dst_data_file.file_name().unwrap());
-            }else {
-                panic!("only single-file detached nhdrs are supported.")
-            }
+    // make sure all data files exist before anything is moved
+    let src_files = nrrd.resolved_data_paths(&src_hdr).map_err(|e| e.to_string())?;
+
+    let (data_file, new_names) = match nrrd.data_file.as_ref() {
+        Some(data_file) => renamed_data_file(data_file, &dst_hdr, encoding.file_ext()),
+        None => return Err("data file field not found!".to_string()),
+    };
+    if new_names.len() != src_files.len() {
+        return Err(format!("expected {} data files but found {}", new_names.len(), src_files.len()));
+    }
+
+    let dst_dir = dst_hdr.parent().unwrap();
+    let moves:Vec<_> = src_files.into_iter()
+        .zip(new_names.iter().map(|name| dst_dir.join(name)))
+        .filter(|(src,dst)| src != dst)
+        .collect();
+
+    // renaming one file onto another of the set would lose data partway through
+    if moves.iter().any(|(_,dst)| moves.iter().any(|(src,_)| src == dst)) {
+        return Err("new data file names overlap the existing ones. Rename to a different name first".to_string());
+    }
+    if !args.force {
+        if let Some((_,dst)) = moves.iter().find(|(_,dst)| dst.exists()) {
+            return Err(format!("destination data file already exists: {}. Use --force to overwrite it", dst.display()));
+        }
+        if src_hdr != dst_hdr && dst_hdr.exists() {
+            return Err(format!("destination header already exists: {}. Use --force to overwrite it", dst_hdr.display()));
         }
-        None => panic!("data file field not found!")
     }
 
-    // make sure data file exists
-    let src_data_file = src_hdr.with_extension(encoding.file_ext());
-    if !src_data_file.exists() {
-        panic!("detached data file doesn't exist: {}",src_data_file.display());
+    if args.dry_run {
+        for (src,dst) in &moves {
+            println!("move {} -> {}", src.display(), dst.display());
+        }
+        println!("write {}", dst_hdr.display());
+        if src_hdr != dst_hdr {
+            println!("remove {}", src_hdr.display());
+        }
+        return Ok(())
     }
 
-    fs::rename(&src_data_file, dst_hdr.with_extension(encoding.file_ext()))
-        .expect("failed to rename detached data file");
+    move_all(&moves).map_err(|e| format!("failed to rename detached data files: {e}"))?;
+    nrrd.data_file = Some(data_file);
 
-    let mut f = File::create(dst_hdr).expect("failed to create new header file");
-    f.write_all(nrrd.to_string().as_bytes()).expect("failed to write to header file");
-    fs::remove_file(src_hdr).expect("failed to remove old header file");
+    // put the data files back if the new header can't be written
+    let written = File::create(&dst_hdr).and_then(|mut f| f.write_all(nrrd.to_string().as_bytes()));
+    if let Err(e) = written {
+        undo_moves(&moves);
+        return Err(format!("failed to write header file {}: {e}", dst_hdr.display()));
+    }
+    if src_hdr != dst_hdr {
+        fs::remove_file(src_hdr).map_err(|e| format!("failed to remove old header file: {e}"))?;
+    }
+    Ok(())
+}
+
+fn fail(msg:impl AsRef<str>) -> ! {
+    eprintln!("{}", msg.as_ref());
+    exit(1);
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = rename(&args) {
+        fail(e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nrrd_rs::header_defs::Encoding;
+    use nrrd_rs::{read_nrrd_to, write_nrrd_list};
+
+    fn args(src:&str, dst:&str) -> Args {
+        Args {src_nhdr: src.into(), dst_nhdr: dst.into(), dry_run: false, force: false}
+    }
+
+    #[test]
+    fn rename_list() {
+        let data:Vec<u16> = (0..12).collect();
+        let files:Vec<PathBuf> = ["a","b","c"].iter().map(|x| PathBuf::from(format!("test_out_rename_list_{x}.raw"))).collect();
+        write_nrrd_list("test_out_rename_list.nhdr", &NRRD::new_from_dims::<u16>(&[4,3]), &data, &files, Encoding::raw).unwrap();
+
+        rename(&args("test_out_rename_list.nhdr", "test_out_renamed_list.nhdr")).unwrap();
+        assert!(!Path::new("test_out_rename_list.nhdr").exists());
+        assert!(files.iter().all(|f| !f.exists()));
 
-}
\ No newline at end of file
+        let (x,h) = read_nrrd_to::<u16>("test_out_renamed_list.nhdr");
+        assert_eq!(x, data);
+        let expected:Vec<PathBuf> = (0..3).map(|i| PathBuf::from(format!("test_out_renamed_list_{i}.raw"))).collect();
+        assert_eq!(h.data_file, Some(DataFile::List {file_paths: expected.clone(), sub_dim: None}));
+
+        fs::remove_file("test_out_renamed_list.nhdr").unwrap();
+        expected.iter().for_each(|f| fs::remove_file(f).unwrap());
+    }
+
+    #[test]
+    fn dry_run_and_force() {
+        let data:Vec<f32> = (0..6).map(|x| x as f32).collect();
+        NRRD::new_from_dims::<f32>(&[2,3]).write("test_out_rename_dry", &data, false, Encoding::raw).unwrap();
+        let header = fs::read("test_out_rename_dry.nhdr").unwrap();
+
+        // a dry run leaves everything where it is
+        let dry = Args {dry_run: true, ..args("test_out_rename_dry.nhdr", "test_out_rename_dry_dst.nhdr")};
+        rename(&dry).unwrap();
+        assert_eq!(fs::read("test_out_rename_dry.nhdr").unwrap(), header);
+        assert!(Path::new("test_out_rename_dry.raw").exists());
+        assert!(!Path::new("test_out_rename_dry_dst.nhdr").exists());
+        assert!(!Path::new("test_out_rename_dry_dst.raw").exists());
+
+        // an existing destination is refused without --force and nothing is moved
+        fs::write("test_out_rename_dry_dst.nhdr", b"in the way").unwrap();
+        let err = rename(&args("test_out_rename_dry.nhdr", "test_out_rename_dry_dst.nhdr")).unwrap_err();
+        assert!(err.contains("--force"));
+        assert_eq!(fs::read("test_out_rename_dry.nhdr").unwrap(), header);
+        assert!(Path::new("test_out_rename_dry.raw").exists());
+        assert_eq!(fs::read("test_out_rename_dry_dst.nhdr").unwrap(), b"in the way");
+
+        let forced = Args {force: true, ..args("test_out_rename_dry.nhdr", "test_out_rename_dry_dst.nhdr")};
+        rename(&forced).unwrap();
+        assert!(!Path::new("test_out_rename_dry.nhdr").exists());
+        assert_eq!(read_nrrd_to::<f32>("test_out_rename_dry_dst.nhdr").0, data);
+
+        fs::remove_file("test_out_rename_dry_dst.nhdr").unwrap();
+        fs::remove_file("test_out_rename_dry_dst.raw").unwrap();
+    }
+
+    #[test]
+    fn failed_move_puts_files_back() {
+        let names = ["test_out_undo_0.raw","test_out_undo_1.raw","test_out_undo_2.raw"];
+        names.iter().for_each(|f| fs::write(f, f.as_bytes()).unwrap());
+
+        // the last destination is in a directory that doesn't exist
+        let moves:Vec<(PathBuf, PathBuf)> = vec![
+            (names[0].into(), "test_out_undo_moved_0.raw".into()),
+            (names[1].into(), "test_out_undo_moved_1.raw".into()),
+            (names[2].into(), "test_out_undo_missing_dir/moved_2.raw".into()),
+        ];
+        assert!(move_all(&moves).is_err());
+        for (src,dst) in &moves {
+            assert_eq!(fs::read(src).unwrap(), src.to_str().unwrap().as_bytes());
+            assert!(!dst.exists());
+        }
+        names.iter().for_each(|f| fs::remove_file(f).unwrap());
+    }
+}
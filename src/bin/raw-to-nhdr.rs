@@ -1,4 +1,3 @@
-use std::error::Error;
 use std::fmt::Display;
 use std::fs::File;
 use std::io::Write;
@@ -6,8 +5,8 @@ use std::path::PathBuf;
 use std::process::exit;
 use std::str::FromStr;
 use clap::Parser;
-use nrrd_rs::NRRD;
-use nrrd_rs::header_defs::{DType, DataFile, Encoding, Endian, Kind, Kinds, SpaceDimension, SpaceDirections, SpaceOrigin, SpaceUnits};
+use nrrd_rs::{NrrdBuilder, NRRD};
+use nrrd_rs::header_defs::{DType, DataFile, Endian, Kind, SpaceDirections, SpaceUnits};
 
 // #[derive(Parser, Debug)]
 // struct Args {
@@ -141,9 +140,14 @@ fn build_nrrd(args: &BuildArgs) -> Result<NRRD,String> {
         dims.insert(0,2);
     }
 
-    let mut nrrd = NRRD::new_from_type_dims(dtype,&dims);
+    // number of spatial axes
+    let n_space = if args.complex {
+        dims.len() - 1
+    }else {
+        dims.len()
+    };
 
-    nrrd.endian = args.endianness.as_ref().map(|e|{
+    let endian = args.endianness.as_ref().map(|e|{
         match e.to_lowercase().as_str() {
             "big" => Endian::Big,
             "little" => Endian::Little,
@@ -169,22 +173,9 @@ fn build_nrrd(args: &BuildArgs) -> Result<NRRD,String> {
         return Err("a single file or sprintf file format must be specified".to_string());
     };
 
-    nrrd.data_file = Some(data_file);
-
-    let kinds = if args.complex {
-        let mut k = vec![Kind::complex];
-        k.extend_from_slice(&vec![Kind::domain;dims.len() - 1]);
-        Kinds::from_vec(k)
-    }else {
-        Kinds::from_vec(vec![Kind::domain;dims.len()])
-    };
-
-    nrrd.kinds = Some(kinds);
-
+    let mut kinds = vec![Kind::domain;n_space];
     if args.complex {
-        nrrd.space_dimension = Some(SpaceDimension::new(dims.len() - 1));
-    }else {
-        nrrd.space_dimension = Some(SpaceDimension::new(dims.len()));
+        kinds.insert(0,Kind::complex);
     }
 
     // handle space directions for complex data. If vox spacing is not given, default to 1mm
@@ -196,17 +187,18 @@ fn build_nrrd(args: &BuildArgs) -> Result<NRRD,String> {
         let vox_size = parse_list_input::<f64>(vox_size_str, '[', ']')?;
         sd.extend_from_spacing(&vox_size);
     }else {
-        sd.extend_from_spacing(&vec![1.; dims.len()]);
-    }
-    nrrd.space_directions = Some(sd);
-
-    if args.complex {
-        nrrd.space_units = Some(SpaceUnits::new_mm(dims.len() - 1));
-    }else {
-        nrrd.space_units = Some(SpaceUnits::new_mm(dims.len()));
+        sd.extend_from_spacing(&vec![1.; n_space]);
     }
 
-    Ok(nrrd)
+    NrrdBuilder::new(dtype,&dims)
+        .endian(endian)
+        .data_file(data_file)
+        .kinds(&kinds)
+        .space_dimension(n_space)
+        .space_directions(sd)
+        .space_units(SpaceUnits::new_mm(n_space))
+        .build()
+        .map_err(|e| e.to_string())
 
 }
 
@@ -217,7 +209,7 @@ fn main() {
 
     match build_nrrd(&args) {
         Ok(nrrd) => {
-            let mut f = File::create(&args.output_nhdr.with_extension("nhdr")).unwrap_or_else(|e|{
+            let mut f = File::create(args.output_nhdr.with_extension("nhdr")).unwrap_or_else(|e|{
                 panic!("failed to create file {} with error: {}",&args.output_nhdr.display(),e)
             });
             f.write_all(nrrd.to_string().as_bytes()).unwrap_or_else(|e|{
@@ -0,0 +1,111 @@
+use crate::header_defs::{Centering, Comment, DType, DataFile, Encoding, Endian, Kind, Kinds, Labels, Space, SpaceDimension, SpaceDirections, SpaceOrigin, SpaceUnits, Spacings};
+use crate::{NrrdError, NRRD};
+
+/// chainable construction of a NRRD header
+#[derive(Debug,Clone)]
+pub struct NrrdBuilder {
+    header: NRRD,
+}
+
+impl NrrdBuilder {
+
+    pub fn new(dtype:DType, dims:&[usize]) -> NrrdBuilder {
+        NrrdBuilder {
+            header: NRRD::new_from_type_dims(dtype, dims),
+        }
+    }
+
+    pub fn dtype(mut self, dtype:DType) -> Self {
+        self.header.dtype = dtype;
+        self
+    }
+
+    pub fn encoding(mut self, encoding:Encoding) -> Self {
+        self.header.encoding = encoding;
+        self
+    }
+
+    pub fn endian(mut self, endian:Endian) -> Self {
+        self.header.endian = endian;
+        self
+    }
+
+    pub fn data_file(mut self, data_file:DataFile) -> Self {
+        self.header.data_file = Some(data_file);
+        self
+    }
+
+    pub fn spacings(mut self, spacings:&[f64]) -> Self {
+        self.header.spacings = Some(Spacings::new(spacings));
+        self
+    }
+
+    /// applies the same centering to all axes
+    pub fn centering(mut self, centering:Centering) -> Self {
+        self.header.set_uniform_centering(centering);
+        self
+    }
+
+    pub fn kinds(mut self, kinds:&[Kind]) -> Self {
+        self.header.kinds = Some(Kinds::from_vec(kinds.to_vec()));
+        self
+    }
+
+    pub fn labels(mut self, labels:&[&str]) -> Self {
+        self.header.labels = Some(Labels::from_list(labels));
+        self
+    }
+
+    pub fn space(mut self, space:Space) -> Self {
+        self.header.space = Some(space);
+        self
+    }
+
+    pub fn space_dimension(mut self, dim:usize) -> Self {
+        self.header.space_dimension = Some(SpaceDimension::new(dim));
+        self
+    }
+
+    pub fn space_units(mut self, space_units:SpaceUnits) -> Self {
+        self.header.space_units = Some(space_units);
+        self
+    }
+
+    pub fn space_origin(mut self, origin:&[f64]) -> Self {
+        self.header.space_origin = Some(SpaceOrigin::new(origin));
+        self
+    }
+
+    pub fn space_directions(mut self, space_directions:SpaceDirections) -> Self {
+        self.header.space_directions = Some(space_directions);
+        self
+    }
+
+    /// sets axis-aligned space directions from the voxel spacing of each axis. If neither the space
+    /// nor the space dimension has been set, the space dimension is set to the number of axes
+    pub fn space_directions_from_spacing(mut self, spacing:&[f64]) -> Self {
+        self.header.space_directions = Some(SpaceDirections::from_spacing(spacing));
+        if self.header.space.is_none() && self.header.space_dimension.is_none() {
+            self.header.space_dimension = Some(SpaceDimension::new(spacing.len()));
+        }
+        self
+    }
+
+    /// adds a comment. Comments are written in the order they were added
+    pub fn comment(mut self, comment:&str) -> Self {
+        self.header.comments.push(Comment {val: comment.to_string()}.to_string());
+        self
+    }
+
+    /// sets a key-value pair. Invalid comments and key-values are reported by 'build'
+    pub fn key_value(mut self, key:&str, value:&str) -> Self {
+        self.header.set_kv(key, value);
+        self
+    }
+
+    /// returns the header after checking that all fields are consistent
+    pub fn build(self) -> Result<NRRD,NrrdError> {
+        self.header.validate()?;
+        Ok(self.header)
+    }
+}
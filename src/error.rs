@@ -0,0 +1,166 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+use crate::header_defs::{DType, Encoding};
+
+#[derive(Debug)]
+pub enum NrrdError {
+    /// underlying read/write failure
+    Io(io::Error),
+    /// a per-axis or per-space-axis field has the wrong number of entries
+    FieldLength {
+        field: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// the number of data elements doesn't match the header
+    DataLength {
+        expected: usize,
+        found: usize,
+    },
+    /// data can't be split evenly across the data files
+    UnevenFileSplit {
+        n_files: usize,
+        n_elements: usize,
+    },
+    /// the data file sub-dim doesn't agree with the number of data files
+    SubDimMismatch {
+        sub_dim: usize,
+        expected_files: usize,
+        n_files: usize,
+    },
+    /// a required header field is missing
+    MissingField(&'static str),
+    /// the header ended mid-line at the end of the file and is incomplete
+    TruncatedHeader {
+        partial_line: String,
+    },
+    /// header lines that are not a recognized field, key-value pair or comment
+    UnrecognizedLines(Vec<String>),
+    /// fields that appear more than once in the header
+    DuplicateFields(Vec<&'static str>),
+    /// keys that appear in more than one key-value pair
+    DuplicateKeys(Vec<String>),
+    /// data files whose contents don't match the declared encoding, with the detected encoding
+    EncodingMismatch {
+        declared: Encoding,
+        files: Vec<(PathBuf, Encoding)>,
+    },
+    /// a block doesn't have exactly 'block size' bytes
+    BlockLength {
+        index: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// the data type doesn't match the header
+    TypeMismatch {
+        expected: DType,
+        found: DType,
+    },
+    /// a field value is not valid
+    InvalidValue {
+        field: &'static str,
+        value: String,
+    },
+    /// values can't be converted between the units
+    IncompatibleUnits {
+        from: String,
+        to: String,
+    },
+    /// a value can't be represented in the requested data type
+    OutOfRange {
+        index: usize,
+        from: DType,
+        to: DType,
+    },
+    /// the data type is not part of the NRRD spec and writing it wasn't explicitly allowed
+    NonStandardType(DType),
+    /// the encoding can't be read or written by this build
+    UnsupportedEncoding(Encoding),
+    /// the data file layout doesn't support the operation
+    UnsupportedDataFile(String),
+    /// detached data files that don't exist
+    MissingDataFiles(Vec<PathBuf>),
+    /// the volumes don't sample the same grid
+    GridMismatch,
+    /// the data or header can't be represented in the requested form
+    Unsupported(String),
+    /// the operation was cancelled before it finished
+    Cancelled,
+    /// the header has no axis of kind 'complex' with size 2
+    MissingComplexAxis,
+}
+
+impl Display for NrrdError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NrrdError::Io(e) => write!(f, "io error: {e}"),
+            NrrdError::FieldLength { field, expected, found } => {
+                write!(f, "field '{field}' has {found} entries but {expected} were expected")
+            }
+            NrrdError::DataLength { expected, found } => {
+                write!(f, "expected {expected} data elements but found {found}")
+            }
+            NrrdError::UnevenFileSplit { n_files, n_elements } => {
+                write!(f, "number of files ({n_files}) doesn't divide the number of elements evenly ({n_elements})")
+            }
+            NrrdError::SubDimMismatch { sub_dim, expected_files, n_files } => {
+                write!(f, "data file sub-dim {sub_dim} implies {expected_files} files but {n_files} were given")
+            }
+            NrrdError::MissingField(field) => write!(f, "required field '{field}' is missing"),
+            NrrdError::TruncatedHeader { partial_line } => {
+                write!(f, "header appears truncated: file ends mid-line at '{partial_line}'")
+            }
+            NrrdError::UnrecognizedLines(lines) => {
+                write!(f, "unrecognized header lines: {}", lines.join(", "))
+            }
+            NrrdError::DuplicateFields(fields) => write!(f, "duplicate header fields: {}", fields.join(", ")),
+            NrrdError::DuplicateKeys(keys) => write!(f, "duplicate key-value keys: {}", keys.join(", ")),
+            NrrdError::EncodingMismatch { declared, files } => {
+                let files = files.iter()
+                    .map(|(p,e)| format!("{} ({})", p.display(), e.name()))
+                    .collect::<Vec<_>>();
+                write!(f, "data files do not match declared encoding '{}': {}", declared.name(), files.join(", "))
+            }
+            NrrdError::BlockLength { index, expected, found } => {
+                write!(f, "block {index} has {found} bytes but the block size is {expected}")
+            }
+            NrrdError::TypeMismatch { expected, found } => {
+                write!(f, "data type '{}' doesn't match header type '{}'", found.name(), expected.name())
+            }
+            NrrdError::InvalidValue { field, value } => write!(f, "invalid value '{value}' for field '{field}'"),
+            NrrdError::IncompatibleUnits { from, to } => write!(f, "cannot convert from '{from}' to '{to}'"),
+            NrrdError::OutOfRange { index, from, to } => {
+                write!(f, "value {index} of type '{}' doesn't fit in type '{}'", from.name(), to.name())
+            }
+            NrrdError::NonStandardType(dtype) => {
+                write!(f, "data type '{}' is not part of the NRRD spec and must be explicitly allowed", dtype.name())
+            }
+            NrrdError::UnsupportedEncoding(encoding) => write!(f, "encoding '{}' is not supported", encoding.name()),
+            NrrdError::UnsupportedDataFile(msg) => write!(f, "unsupported data file: {msg}"),
+            NrrdError::MissingDataFiles(files) => {
+                let files = files.iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+                write!(f, "data files do not exist: {}", files.join(", "))
+            }
+            NrrdError::GridMismatch => write!(f, "volumes are not on the same grid"),
+            NrrdError::Unsupported(msg) => write!(f, "unsupported: {msg}"),
+            NrrdError::Cancelled => write!(f, "operation was cancelled"),
+            NrrdError::MissingComplexAxis => write!(f, "no axis of kind 'complex' with size 2 was found"),
+        }
+    }
+}
+
+impl std::error::Error for NrrdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NrrdError::Io(e) => Some(e),
+            _=> None,
+        }
+    }
+}
+
+impl From<io::Error> for NrrdError {
+    fn from(e: io::Error) -> Self {
+        NrrdError::Io(e)
+    }
+}
@@ -0,0 +1,403 @@
+use crate::error::NrrdError;
+use crate::header_defs::{Centering, Kind, NrrdVec, Space, SpaceOrigin};
+use crate::NRRD;
+
+/// returns the signs that take each world axis of an anatomical space to LPS (x left, y posterior, z
+/// superior). Returns None for spaces that aren't tied to patient anatomy
+pub(crate) fn lps_signs(space:Space) -> Option<[f64;3]> {
+    match space {
+        Space::LPS | Space::LPST => Some([1.,1.,1.]),
+        Space::RAS | Space::RAST => Some([-1.,-1.,1.]),
+        Space::LAS | Space::LAST => Some([1.,-1.,1.]),
+        _=> None,
+    }
+}
+
+/// DICOM-style geometry of a 3D volume, in LPS patient coordinates
+#[derive(Debug,Clone,PartialEq)]
+pub struct DicomGeometry {
+    /// direction of the first (fastest) axis, along a row (first half of ImageOrientationPatient)
+    pub row_cosines: [f64;3],
+    /// direction of the second axis, down a column (second half of ImageOrientationPatient)
+    pub column_cosines: [f64;3],
+    /// cross product of the row and column cosines
+    pub slice_normal: [f64;3],
+    /// spacing between rows and between columns, in that order (PixelSpacing)
+    pub pixel_spacing: [f64;2],
+    /// distance between slices along the third axis
+    pub slice_spacing: f64,
+    /// thickness of the third axis, if given in the header
+    pub slice_thickness: Option<f64>,
+    /// position of the first voxel (ImagePositionPatient)
+    pub image_position: [f64;3],
+}
+
+/// the cheapest way to bring a volume onto a target grid
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum ResamplingPlan {
+    /// the grids already match
+    None,
+    /// the grids share the same voxels in a different axis order or direction, so permuting and
+    /// flipping is enough. Axis 'i' of the result is axis 'order[i]' of the source, and the result
+    /// axes listed in 'flip' are reversed after permuting
+    ReorientOnly {
+        order: Vec<usize>,
+        flip: Vec<usize>,
+    },
+    /// the voxels don't line up and values must be interpolated
+    InterpolationRequired,
+}
+
+impl NRRD {
+
+    /// returns the axes that have a space direction vector
+    pub fn spatial_axes(&self) -> Vec<usize> {
+        self.space_directions.as_ref().map(|sd|{
+            sd.vectors().iter().enumerate().filter(|(_,v)| v.is_some()).map(|(i,_)| i).collect()
+        }).unwrap_or_default()
+    }
+
+    /// returns the sample spacing of every axis. With space directions, the spacing of an axis is
+    /// the length of its direction vector, and NaN for axes with a 'none' direction, even if
+    /// 'spacings' gives them a value. Without space directions, 'spacings' is returned as-is (NaN
+    /// for axes it doesn't space). Returns None if neither field is set
+    pub fn voxel_spacing(&self) -> Option<Vec<f64>> {
+        if let Some(sd) = &self.space_directions {
+            let spacing = sd.vectors().iter().map(|v|{
+                v.as_ref().map(|v| v.values().iter().map(|x| x * x).sum::<f64>().sqrt()).unwrap_or(f64::NAN)
+            }).collect();
+            return Some(spacing);
+        }
+        self.spacings.as_ref().map(|s| s.values().to_vec())
+    }
+
+    /// returns the space direction vector of an axis, or None if it is non-spatial
+    pub(crate) fn space_direction(&self, axis:usize) -> Option<&[f64]> {
+        self.space_directions.as_ref()?.vector(axis)
+    }
+
+    /// returns the 4x4 matrix mapping (i,j,k,1) voxel indices of the spatial axes to physical
+    /// coordinates in the header's space. Columns 0-2 are the space directions of the spatial axes
+    /// in axis order (non-spatial axes are skipped) and column 3 is the space origin. Returns None
+    /// if space directions or origin are missing, or if the space is not 3-dimensional
+    pub fn affine(&self) -> Option<[[f64;4];4]> {
+
+        let origin = self.space_origin.as_ref()?.coords();
+        let directions:Vec<&[f64]> = self.space_directions.as_ref()?
+            .vectors().iter().flatten().map(|v| v.values()).collect();
+
+        if origin.len() != 3 || directions.len() != 3 || directions.iter().any(|d| d.len() != 3) {
+            return None;
+        }
+
+        let mut affine = [[0.;4];4];
+        for row in 0..3 {
+            for (col,dir) in directions.iter().enumerate() {
+                affine[row][col] = dir[row];
+            }
+            affine[row][3] = origin[row];
+        }
+        affine[3][3] = 1.;
+        Some(affine)
+    }
+
+    /// returns the position of the first sample along the axis in steps, measured from the axis min.
+    /// The axis min of a cell-centered axis is the outer edge of the first cell, so its first sample
+    /// sits half a step in. Other axes have their first sample on the axis min. The space origin is
+    /// always the first sample itself, whatever the centering
+    pub(crate) fn sample_offset(&self, axis:usize) -> f64 {
+        match self.centerings.as_ref().and_then(|c| c.values().get(axis).copied()) {
+            Some(Centering::Cell) => 0.5,
+            _=> 0.,
+        }
+    }
+
+    /// returns the world position of the sample at 'index' (one entry per axis). With a space origin
+    /// and directions, sample i is at 'origin + i * direction' for any centering, since the NRRD
+    /// format defines the origin as the center of the first sample. Non-spatial axes don't move the
+    /// position. Without them, the position has one coordinate per axis with a spacing, at
+    /// 'axis min + i * spacing', where cell-centered axes are shifted by half a spacing from the
+    /// edge of the first cell to its center. Returns None if the index doesn't match the dimension
+    /// or the header has neither an origin and directions nor spacings and axis mins
+    pub fn sample_world_coord(&self, index:&[usize]) -> Option<Vec<f64>> {
+
+        if index.len() != self.dimension.dim() {
+            return None;
+        }
+
+        if let (Some(origin), Some(space_directions)) = (&self.space_origin, &self.space_directions) {
+            let mut coord = origin.coords().to_vec();
+            for (axis,&i) in index.iter().enumerate() {
+                let Some(dir) = space_directions.vector(axis) else {
+                    continue
+                };
+                coord.iter_mut().zip(dir).for_each(|(c,d)| *c += i as f64 * d);
+            }
+            return Some(coord);
+        }
+
+        let spacings = self.spacings.as_ref()?.values();
+        let mins = self.axis_mins.as_ref()?.values();
+        let coord = index.iter().enumerate()
+            .filter(|(axis,_)| !spacings[*axis].is_nan())
+            .map(|(axis,&i)| mins[axis] + (i as f64 + self.sample_offset(axis)) * spacings[axis])
+            .collect();
+        Some(coord)
+    }
+
+    /// returns the DICOM-style geometry of a volume with 3 spatial axes in an anatomical space
+    /// (RAS, LAS or LPS, with or without time). Directions and the origin are converted to LPS, the
+    /// DICOM convention, by negating x and y for RAS and y for LAS. The first spatial axis runs along
+    /// rows and the second down columns. Returns None without space, directions or an origin
+    pub fn dicom_orientation(&self) -> Option<DicomGeometry> {
+
+        let signs = lps_signs(self.space?)?;
+        let axes = self.spatial_axes();
+        let origin = self.space_origin.as_ref()?.coords();
+        if axes.len() != 3 || origin.len() < 3 {
+            return None;
+        }
+
+        let lps = |v:&[f64]| -> Option<[f64;3]> {
+            (v.len() >= 3).then(|| [v[0] * signs[0], v[1] * signs[1], v[2] * signs[2]])
+        };
+        let norm = |v:&[f64;3]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        let unit = |v:&[f64;3]| {
+            let n = norm(v);
+            [v[0] / n, v[1] / n, v[2] / n]
+        };
+
+        let dirs = [
+            lps(self.space_direction(axes[0])?)?,
+            lps(self.space_direction(axes[1])?)?,
+            lps(self.space_direction(axes[2])?)?,
+        ];
+        let row = unit(&dirs[0]);
+        let col = unit(&dirs[1]);
+        let normal = [
+            row[1] * col[2] - row[2] * col[1],
+            row[2] * col[0] - row[0] * col[2],
+            row[0] * col[1] - row[1] * col[0],
+        ];
+
+        Some(DicomGeometry {
+            row_cosines: row,
+            column_cosines: col,
+            slice_normal: normal,
+            pixel_spacing: [norm(&dirs[1]), norm(&dirs[0])],
+            slice_spacing: norm(&dirs[2]),
+            slice_thickness: self.thicknesses.as_ref().and_then(|t| t.get(axes[2])),
+            image_position: lps(origin)?,
+        })
+    }
+
+    /// returns true if any field placing the samples in physical space is set: space, space
+    /// dimension, space units, space origin, space directions, measurement frame or spacings
+    pub fn has_geometry(&self) -> bool {
+        self.space.is_some()
+            || self.space_dimension.is_some()
+            || self.space_units.is_some()
+            || self.space_origin.is_some()
+            || self.space_directions.is_some()
+            || self.measurement_frame.is_some()
+            || self.spacings.is_some()
+    }
+
+    /// removes all fields that place the samples in physical space (see 'has_geometry'), leaving
+    /// only the shape of the data. Axes of kind 'space' become 'domain', since 'space' implies a
+    /// world space that no longer exists
+    pub fn strip_geometry(&mut self) {
+        self.space = None;
+        self.space_dimension = None;
+        self.space_units = None;
+        self.space_origin = None;
+        self.space_directions = None;
+        self.measurement_frame = None;
+        self.spacings = None;
+        if let Some(kinds) = self.kinds.as_mut() {
+            kinds.kinds.iter_mut().filter(|k| **k == Kind::space).for_each(|k| *k = Kind::domain);
+        }
+    }
+
+    /// re-expresses the geometry in another anatomical space (RAS, LAS or LPS, with or without
+    /// time) by negating the world components that point the other way in the space directions,
+    /// space origin and measurement frame. The voxel data and axis order are unchanged. Fails if
+    /// the space is unset, or if either space isn't anatomical or they differ in whether they
+    /// include time
+    pub fn convert_space(&mut self, target:Space) -> Result<(),NrrdError> {
+
+        let space = self.space.ok_or(NrrdError::MissingField("space"))?;
+        let unsupported = || NrrdError::Unsupported(format!("can't convert from {space} to {target}"));
+        let (Some(from), Some(to)) = (lps_signs(space), lps_signs(target)) else {
+            return Err(unsupported());
+        };
+        if space.dim() != target.dim() {
+            return Err(unsupported());
+        }
+
+        // a component flips when the two spaces point its world axis in opposite directions. Adding
+        // 0 keeps zeros from being written as -0
+        let flip = |v:&mut [f64]| v.iter_mut().zip(from.iter().zip(&to)).for_each(|(x,(a,b))| *x = *x * a * b + 0.);
+
+        if let Some(sd) = self.space_directions.as_mut() {
+            sd.directions.iter_mut().flatten().for_each(|v| flip(v.values_mut()));
+        }
+        if let Some(origin) = self.space_origin.as_mut() {
+            let mut coords = origin.coords().to_vec();
+            flip(&mut coords);
+            *origin = SpaceOrigin::new(&coords);
+        }
+        if let Some(mf) = self.measurement_frame.as_mut() {
+            mf.vectors_mut().iter_mut().for_each(|v:&mut NrrdVec| flip(v.values_mut()));
+        }
+
+        self.space = Some(target);
+        Ok(())
+    }
+
+    /// some writers omit the 'none' entries of non-spatial axes from 'space directions', giving
+    /// fewer directions than axes. The missing 'none' entries are inserted so there is one entry
+    /// per axis. If the kinds mark exactly as many spatial axes as there are directions, the
+    /// directions are assigned to those axes, otherwise they are assigned to the last axes
+    pub(crate) fn reconcile_space_directions(&mut self) {
+
+        let dim = self.dimension.dim();
+        let Some(space_directions) = self.space_directions.as_mut() else {
+            return
+        };
+        let n = space_directions.len();
+        if n >= dim || space_directions.vectors().iter().any(|v| v.is_none()) {
+            return
+        }
+
+        let spatial:Vec<bool> = match &self.kinds {
+            Some(kinds) if kinds.len() == dim && kinds.kinds.iter().filter(|k| k.is_spatial()).count() == n => {
+                kinds.kinds.iter().map(|k| k.is_spatial()).collect()
+            }
+            _=> (0..dim).map(|i| i >= dim - n).collect(),
+        };
+
+        let mut vectors = std::mem::take(&mut space_directions.directions).into_iter();
+        space_directions.directions = spatial.into_iter()
+            .map(|is_spatial| if is_spatial {vectors.next().flatten()} else {None})
+            .collect();
+    }
+
+    /// returns the physical coordinate of each sample along an axis. When the axis has a space
+    /// direction, sample i is at 'origin + i * direction' and the coordinate reported is the world
+    /// component the direction points along most strongly (the axis's own coordinate). Without
+    /// space directions, 'axis min + i * spacing' is used, with an axis min of 0 if it isn't given,
+    /// plus half a spacing for cell-centered axes, whose axis min is the edge of the first cell.
+    /// Returns None if the axis is out of range, is non-spatial, or has no known spacing
+    pub fn axis_coordinates(&self, axis:usize) -> Option<Vec<f64>> {
+
+        let n = *self.shape().get(axis)?;
+
+        if let Some(space_directions) = &self.space_directions {
+            let dir = space_directions.directions.get(axis)?.as_ref()?.values();
+            let (component,step) = dir.iter().cloned().enumerate()
+                .max_by(|(_,a),(_,b)| a.abs().total_cmp(&b.abs()))?;
+            let origin = self.space_origin.as_ref()
+                .and_then(|o| o.coords().get(component).cloned())
+                .unwrap_or(0.);
+            return Some((0..n).map(|i| origin + i as f64 * step).collect());
+        }
+
+        let spacing = *self.spacings.as_ref()?.values().get(axis)?;
+        if spacing.is_nan() {
+            return None;
+        }
+        let min = self.axis_mins.as_ref()
+            .and_then(|m| m.values().get(axis).cloned())
+            .filter(|m| !m.is_nan())
+            .unwrap_or(0.);
+        let offset = self.sample_offset(axis);
+        Some((0..n).map(|i| min + (i as f64 + offset) * spacing).collect())
+    }
+
+    /// returns true if both headers sample the same points in space, in the same axis order and
+    /// direction, within a tolerance of 'tol'
+    pub fn same_grid(&self, other:&NRRD, tol:f64) -> bool {
+        self.resampling_needed(other, tol) == ResamplingPlan::None
+    }
+
+    /// returns the step between samples of an axis: its space direction vector, or its spacing as a
+    /// single component when the header has no space directions. Returns None for axes with neither
+    fn sample_step(&self, axis:usize) -> Option<Vec<f64>> {
+        if self.space_directions.is_some() {
+            return self.space_direction(axis).map(|d| d.to_vec());
+        }
+        let spacing = *self.spacings.as_ref()?.values().get(axis)?;
+        (!spacing.is_nan()).then_some(vec![spacing])
+    }
+
+    /// compares this grid to a target grid to decide how data would need to be resampled to match
+    /// it. The space directions of the axes (or their spacings, without space directions) are
+    /// matched up with a tolerance of 'tol', finding axes that are permuted or reversed. The first
+    /// samples are only compared when both headers have a space origin. Grids in different spaces
+    /// always need interpolation
+    pub fn resampling_needed(&self, target:&NRRD, tol:f64) -> ResamplingPlan {
+
+        let close = |a:&[f64], b:&[f64]| a.len() == b.len() && a.iter().zip(b).all(|(x,y)| (x - y).abs() <= tol);
+
+        let n = self.shape().len();
+        if n != target.shape().len() || self.space != target.space {
+            return ResamplingPlan::InterpolationRequired;
+        }
+
+        let steps:Vec<_> = (0..n).map(|axis| self.sample_step(axis)).collect();
+
+        let mut order = Vec::with_capacity(n);
+        let mut flip = vec![];
+        let mut used = vec![false;n];
+
+        for axis in 0..n {
+            let size = target.shape()[axis];
+            let found = match target.sample_step(axis) {
+                // axes without a step must stay in place
+                None => (steps[axis].is_none() && self.shape()[axis] == size).then_some((axis, false)),
+                Some(target_step) => (0..n)
+                    .filter(|&i| !used[i] && self.shape()[i] == size)
+                    .find_map(|i| {
+                        let step = steps[i].as_ref()?;
+                        let reversed:Vec<f64> = step.iter().map(|x| -x).collect();
+                        if close(step, &target_step) {
+                            Some((i, false))
+                        }else {
+                            close(&reversed, &target_step).then_some((i, true))
+                        }
+                    }),
+            };
+            let Some((src_axis, flipped)) = found else {
+                return ResamplingPlan::InterpolationRequired;
+            };
+            used[src_axis] = true;
+            order.push(src_axis);
+            if flipped {
+                flip.push(axis);
+            }
+        }
+
+        if let (Some(origin), Some(target_origin)) = (&self.space_origin, &target.space_origin) {
+            // world position of the source sample that lands on the first target sample, which is
+            // the target origin
+            let mut corner = origin.coords().to_vec();
+            for &axis in &flip {
+                let src_axis = order[axis];
+                if let Some(dir) = self.space_direction(src_axis) {
+                    let steps = (self.shape()[src_axis] - 1) as f64;
+                    corner.iter_mut().zip(dir).for_each(|(c,d)| *c += steps * d);
+                }
+            }
+            if !close(&corner, target_origin.coords()) {
+                return ResamplingPlan::InterpolationRequired;
+            }
+        }
+
+        if flip.is_empty() && order.iter().enumerate().all(|(i,&src)| i == src) {
+            ResamplingPlan::None
+        }else {
+            ResamplingPlan::ReorientOnly {order, flip}
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use bytemuck::{Pod, Zeroable};
+use num_traits::{FromPrimitive, ToPrimitive};
+use crate::header_defs::{DType, NRRDType};
+
+/// an IEEE 754 half-precision float stored as its raw bits. Values are converted through f32
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+#[repr(transparent)]
+pub struct Half(u16);
+
+// SAFETY: Half is a transparent wrapper around u16, so every bit pattern is valid and it has no padding
+unsafe impl Zeroable for Half {}
+unsafe impl Pod for Half {}
+
+impl Half {
+    pub fn from_bits(bits:u16) -> Half {
+        Half(bits)
+    }
+
+    pub fn to_bits(self) -> u16 {
+        self.0
+    }
+
+    /// converts from f32, rounding to the nearest representable value (ties to even). Values too
+    /// large for half precision become infinity
+    pub fn from_f32(x:f32) -> Half {
+        let bits = x.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exp = ((bits >> 23) & 0xff) as i32;
+        let man = bits & 0x7f_ffff;
+
+        // infinity and nan, keeping nans quiet
+        if exp == 0xff {
+            let nan = if man != 0 {0x200 | (man >> 13) as u16} else {0};
+            return Half(sign | 0x7c00 | nan);
+        }
+
+        let e = exp - 127 + 15;
+        if e >= 0x1f {
+            return Half(sign | 0x7c00);
+        }
+
+        if e <= 0 {
+            // subnormal or zero. Too small values round to zero
+            if e < -10 {
+                return Half(sign);
+            }
+            let man = man | 0x80_0000;
+            let shift = (14 - e) as u32;
+            let half = man >> shift;
+            let rem = man & ((1 << shift) - 1);
+            let halfway = 1 << (shift - 1);
+            let round_up = rem > halfway || (rem == halfway && half & 1 == 1);
+            return Half(sign | (half + round_up as u32) as u16);
+        }
+
+        // a carry out of the mantissa correctly bumps the exponent, up to infinity
+        let mut out = ((e as u32) << 10) | (man >> 13);
+        let rem = man & 0x1fff;
+        if rem > 0x1000 || (rem == 0x1000 && out & 1 == 1) {
+            out += 1;
+        }
+        Half(sign | out as u16)
+    }
+
+    /// converts to f32. This is exact
+    pub fn to_f32(self) -> f32 {
+        let h = self.0;
+        let sign = ((h & 0x8000) as u32) << 16;
+        let exp = ((h >> 10) & 0x1f) as u32;
+        let man = (h & 0x3ff) as u32;
+        match exp {
+            0 => {
+                let v = man as f32 * 2f32.powi(-24);
+                if sign != 0 {-v} else {v}
+            }
+            0x1f => f32::from_bits(sign | 0x7f80_0000 | (man << 13)),
+            _=> f32::from_bits(sign | ((exp + 112) << 23) | (man << 13)),
+        }
+    }
+}
+
+impl NRRDType for Half {
+    fn dtype() -> DType {
+        DType::f16
+    }
+}
+
+impl ToPrimitive for Half {
+    fn to_i64(&self) -> Option<i64> {
+        Half::to_f32(*self).to_i64()
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        Half::to_f32(*self).to_u64()
+    }
+
+    fn to_f32(&self) -> Option<f32> {
+        Some(Half::to_f32(*self))
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        Some(Half::to_f32(*self) as f64)
+    }
+}
+
+impl FromPrimitive for Half {
+    fn from_i64(n:i64) -> Option<Half> {
+        Some(Half::from_f32(n as f32))
+    }
+
+    fn from_u64(n:u64) -> Option<Half> {
+        Some(Half::from_f32(n as f32))
+    }
+
+    fn from_f32(n:f32) -> Option<Half> {
+        Some(Half::from_f32(n))
+    }
+
+    fn from_f64(n:f64) -> Option<Half> {
+        Some(Half::from_f32(n as f32))
+    }
+}
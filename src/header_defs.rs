@@ -5,6 +5,7 @@ use std::str::FromStr;
 use bytemuck::Pod;
 use regex::{Regex, RegexSet};
 use sprintf::sprintf;
+use crate::error::NrrdError;
 use crate::io;
 
 /// Header Definition
@@ -12,7 +13,7 @@ pub trait HeaderDef {
     fn patterns<'a>() -> &'a[&'a str];
     fn matches(s:&str) -> bool {
         // add an anchor to the front of each pattern to avoid duplicate matching
-        let pats = Self::patterns().iter().map(|p| format!(r"^{p}"));
+        let pats = Self::patterns().iter().map(|p| format!(r"^{}", field_regex(p, true)));
         RegexSet::new(pats).unwrap()
             .is_match(s)
     }
@@ -20,8 +21,15 @@ pub trait HeaderDef {
     /// return the byte index in 's' of the first character after the pattern match
     fn idx(s:&str) -> Option<usize> {
         for pat in Self::patterns() {
-            if let Some(idx) = s.find(pat) {
-                return Some(idx + pat.len());
+            if !pat.ends_with(": ") {
+                if let Some(idx) = s.find(pat) {
+                    return Some(idx + pat.len());
+                }
+                continue;
+            }
+            let re = Regex::new(&format!(r"^{}", field_regex(pat, false))).unwrap();
+            if let Some(m) = re.find(s) {
+                return Some(m.end());
             }
         }
         None
@@ -29,11 +37,125 @@ pub trait HeaderDef {
 
 }
 
+/// per-axis fields hold one entry for each axis of the array, in axis order
+pub trait PerAxis {
+    type Entry: Clone;
+    fn entries_mut(&mut self) -> &mut Vec<Self::Entry>;
+
+    /// removes the entry of an axis, shifting the following axes down
+    fn remove_axis(&mut self, axis:usize) {
+        self.entries_mut().remove(axis);
+    }
+
+    /// adds an entry for a new last axis
+    fn push_axis(&mut self, entry:Self::Entry) {
+        self.entries_mut().push(entry);
+    }
+
+    /// reorders the entries so that new axis i holds the entry of old axis 'order[i]'
+    fn permute_axes(&mut self, order:&[usize]) {
+        let entries = self.entries_mut();
+        let old = entries.clone();
+        *entries = order.iter().map(|&axis| old[axis].clone()).collect();
+    }
+}
+
+/// formats a value in its shortest form that parses back to the same value. NaN is written as
+/// 'nan', as teem does, rather than Rust's 'NaN'
+fn format_f64(x:f64) -> String {
+    if x.is_nan() {
+        "nan".to_string()
+    }else {
+        x.to_string()
+    }
+}
+
+/// compares floating point field values, treating NaN (such as the spacing of a non-spatial axis)
+/// as equal to NaN
+/// serializes a float with NaN as null, since JSON has no NaN, and reads null back as NaN
+#[cfg(feature = "serde")]
+mod nan_as_null {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S:Serializer>(x:&f64, s:S) -> Result<S::Ok, S::Error> {
+        if x.is_nan() {s.serialize_none()} else {s.serialize_some(x)}
+    }
+
+    pub fn deserialize<'de, D:Deserializer<'de>>(d:D) -> Result<f64, D::Error> {
+        Ok(Option::<f64>::deserialize(d)?.unwrap_or(f64::NAN))
+    }
+}
+
+/// serializes floats as a plain array with NaNs as nulls, as in 'nan_as_null'
+#[cfg(feature = "serde")]
+mod nans_as_nulls {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S:Serializer>(x:&[f64], s:S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(x.iter().map(|x| (!x.is_nan()).then_some(*x)))
+    }
+
+    pub fn deserialize<'de, D:Deserializer<'de>>(d:D) -> Result<Vec<f64>, D::Error> {
+        Ok(Vec::<Option<f64>>::deserialize(d)?.into_iter().map(|x| x.unwrap_or(f64::NAN)).collect())
+    }
+}
+
+fn same_f64(a:f64, b:f64) -> bool {
+    a == b || a.is_nan() && b.is_nan()
+}
+
+fn same_f64s(a:&[f64], b:&[f64]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(&a,&b)| same_f64(a, b))
+}
+
+/// builds a regex for a field pattern. Field patterns of the form "<field>: " are relaxed to allow
+/// zero or more spaces/tabs after the colon. When 'exclude_key_value' is set, "<field>:=" is not
+/// matched so that key-value pairs are not mistaken for fields
+fn field_regex(pattern:&str, exclude_key_value:bool) -> String {
+    match pattern.strip_suffix(": ") {
+        Some(field) if exclude_key_value => format!(r"{}:([ \t]*$|[ \t]*[^=\s])", regex::escape(field)),
+        Some(field) => format!(r"{}:[ \t]*", regex::escape(field)),
+        None => regex::escape(pattern),
+    }
+}
+
+/// parses a list of double-quoted strings, such as labels or units. Strings may be empty and may
+/// contain quotes or backslashes escaped with a backslash
+fn parse_quoted(s:&str) -> Vec<String> {
+    let mut strings = vec![];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '"' {
+            continue;
+        }
+        let mut string = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => string.extend(chars.next()),
+                c => string.push(c),
+            }
+        }
+        strings.push(string);
+    }
+    strings
+}
+
+/// writes strings as a space-separated list of double-quoted strings, escaping quotes and
+/// backslashes
+fn format_quoted(strings:&[String]) -> String {
+    strings.iter()
+        .map(|x| format!("\"{}\"", x.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 /******************************
  ********** MAGIC ************
  ****************************/
 
-#[derive(Debug,Clone,Copy)]
+#[derive(Debug,PartialEq,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Magic {
     pub version: u8,
 }
@@ -44,6 +166,11 @@ impl Default for Magic {
     }
 }
 
+impl Magic {
+    /// the newest format version this crate reads and writes
+    pub const MAX_VERSION: u8 = 5;
+}
+
 impl HeaderDef for Magic {
     fn patterns<'a>() -> &'a [&'a str] {
         &["NRRD"]
@@ -53,23 +180,32 @@ impl HeaderDef for Magic {
 impl FromStr for Magic {
     type Err = ();
 
+    /// parses 'NRRDxxxx' where xxxx is the zero-padded version, from 1 up to 'MAX_VERSION'
     fn from_str(s: &str) -> Result<Self,()> {
-        let idx = Magic::idx(s).unwrap();
-        let version = s[idx..].trim().parse::<u8>().unwrap();
+        let idx = Magic::idx(s).ok_or(())?;
+        let digits = s[idx..].trim();
+        if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        let version = digits.parse::<u8>().map_err(|_| ())?;
+        if version == 0 || version > Magic::MAX_VERSION {
+            return Err(());
+        }
         Ok(Magic{version})
     }
 }
 
 impl Display for Magic {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", format_args!("NRRD000{}", self.version))
+        write!(f, "{}{:04}", Self::patterns()[0], self.version)
     }
 }
 
 /******************************
  ********** Comment *********
  ****************************/
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Comment {
     pub val: String,
 }
@@ -105,7 +241,8 @@ impl Display for Comment {
  ********** KEY-VALUE *********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Value {
     pub val: String,
 }
@@ -117,6 +254,10 @@ impl HeaderDef for Value {
 }
 
 impl Value {
+    pub fn new(val:&str) -> Value {
+        Value {val: val.to_string()}
+    }
+
     /// returns true if the header line matches the key-value pattern
     pub fn matches_key_value(s:&str) -> bool {
         let pats = Self::patterns();
@@ -133,16 +274,38 @@ impl Value {
 
 impl FromStr for Value {
     type Err = ();
+    /// the value starts after ':=' with one optional space. '\n', '\t' and '\\' escapes are decoded
     fn from_str(s: &str) -> Result<Self,()> {
         let idx = Value::idx(s).unwrap();
-        let val = s[idx..].to_string();
+        let raw = &s[idx..];
+        let raw = raw.strip_prefix(' ').unwrap_or(raw);
+        let mut val = String::with_capacity(raw.len());
+        let mut chars = raw.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                val.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => val.push('\n'),
+                Some('t') => val.push('\t'),
+                Some('\\') => val.push('\\'),
+                Some(other) => {
+                    val.push('\\');
+                    val.push(other);
+                }
+                None => val.push('\\'),
+            }
+        }
         Ok(Value{val})
     }
 }
 
 impl Display for Value {
+    /// backslashes, newlines and tabs are escaped so the value stays on one header line
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", Self::patterns()[0], self.val)
+        let escaped = self.val.replace('\\', "\\\\").replace('\n', "\\n").replace('\t', "\\t");
+        write!(f, "{}{}", Self::patterns()[0], escaped)
     }
 }
 
@@ -153,21 +316,45 @@ impl Display for Value {
 
 #[derive(Debug,Clone,Copy,Eq,PartialEq)]
 #[allow(non_camel_case_types)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Space {
+    #[cfg_attr(feature = "serde", serde(rename = "right-anterior-superior"))]
     RAS,
+    #[cfg_attr(feature = "serde", serde(rename = "left-anterior-superior"))]
     LAS,
+    #[cfg_attr(feature = "serde", serde(rename = "left-posterior-superior"))]
     LPS,
+    #[cfg_attr(feature = "serde", serde(rename = "right-anterior-superior-time"))]
     RAST,
+    #[cfg_attr(feature = "serde", serde(rename = "left-anterior-superior-time"))]
     LAST,
+    #[cfg_attr(feature = "serde", serde(rename = "left-posterior-superior-time"))]
     LPST,
+    #[cfg_attr(feature = "serde", serde(rename = "scanner-xyz"))]
     scanner_xyz,
+    #[cfg_attr(feature = "serde", serde(rename = "scanner-xyz-time"))]
     scanner_xyz_time,
+    #[cfg_attr(feature = "serde", serde(rename = "3D-right-handed"))]
     _3D_right_handed,
+    #[cfg_attr(feature = "serde", serde(rename = "3D-left-handed"))]
     _3D_left_handed,
+    #[cfg_attr(feature = "serde", serde(rename = "3D-right-handed-time"))]
     _3D_right_handed_time,
+    #[cfg_attr(feature = "serde", serde(rename = "3D-left-handed-time"))]
     _3D_left_handed_time,
 }
 
+impl Space {
+    /// returns the number of space axes implied by the space
+    pub fn dim(&self) -> usize {
+        use Space::*;
+        match self {
+            RAS | LAS | LPS | scanner_xyz | _3D_right_handed | _3D_left_handed => 3,
+            RAST | LAST | LPST | scanner_xyz_time | _3D_right_handed_time | _3D_left_handed_time => 4,
+        }
+    }
+}
+
 impl HeaderDef for Space {
     fn patterns<'a>() -> &'a [&'a str] {
         &["space: "]
@@ -222,7 +409,8 @@ impl Display for Space {
  ***** SPACE DIMENSION ********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct SpaceDimension {
     dim:usize
 }
@@ -231,11 +419,15 @@ impl SpaceDimension {
     pub fn new(dim:usize) -> SpaceDimension {
         SpaceDimension{dim}
     }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
 }
 
 impl HeaderDef for SpaceDimension {
     fn patterns<'a>() -> &'a [&'a str] {
-        &["space dimension: "]
+        &["space dimension: ","spacedimension: "]
     }
 }
 
@@ -259,7 +451,8 @@ impl Display for SpaceDimension {
  ******** SPACE UNITS ********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct SpaceUnits {
     units: Vec<String>
 }
@@ -278,11 +471,23 @@ impl SpaceUnits {
             units: units.iter().map(|s| s.to_string()).collect()
         }
     }
+
+    pub fn values(&self) -> &[String] {
+        &self.units
+    }
+
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
 }
 
 impl HeaderDef for SpaceUnits {
     fn patterns<'a>() -> &'a [&'a str] {
-        &["space units: "]
+        &["space units: ","spaceunits: "]
     }
 }
 
@@ -291,20 +496,13 @@ impl FromStr for SpaceUnits {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let idx = SpaceUnits::idx(s).unwrap();
         let s = s[idx..].trim();
-        let re = Regex::new(r#""([^"]+)""#).unwrap();
-        let units = re.find_iter(s)
-            .map(|m| m.as_str()[1..m.as_str().len() - 1].to_string()) // Strip quotes
-            .collect();
-        Ok(SpaceUnits{units})
+        Ok(SpaceUnits{units: parse_quoted(s)})
     }
 }
 
 impl Display for SpaceUnits {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f,"{}{}",
-               Self::patterns()[0],
-               self.units.iter().map(|x|format!("\"{x}\"")).collect::<Vec<_>>().join(" ")
-        )
+        write!(f,"{}{}", Self::patterns()[0], format_quoted(&self.units))
     }
 }
 
@@ -313,16 +511,40 @@ impl Display for SpaceUnits {
  ****************************/
 
 #[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct NrrdVec {
+    #[cfg_attr(feature = "serde", serde(with = "nans_as_nulls"))]
     v: Vec<f64>
 }
 
+impl PartialEq for NrrdVec {
+    fn eq(&self, other:&Self) -> bool {
+        same_f64s(&self.v, &other.v)
+    }
+}
+
 impl NrrdVec {
     pub fn new(v:&[f64]) -> NrrdVec {
         NrrdVec {
             v: v.to_vec()
         }
     }
+
+    pub fn values(&self) -> &[f64] {
+        &self.v
+    }
+
+    pub fn values_mut(&mut self) -> &mut [f64] {
+        &mut self.v
+    }
+
+    pub fn len(&self) -> usize {
+        self.v.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v.is_empty()
+    }
 }
 
 impl FromStr for NrrdVec {
@@ -358,8 +580,9 @@ impl FromStr for NrrdVec {
 }
 
 impl Display for NrrdVec {
+    /// components are written in their shortest form that parses back to the same value
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let s:Vec<_> = self.v.iter().map(|x| format!("{x:.17}")).collect();
+        let s:Vec<_> = self.v.iter().map(|x| format_f64(*x)).collect();
         write!(f,"({})",s.join(","))
     }
 }
@@ -368,7 +591,8 @@ impl Display for NrrdVec {
  ********* SPACE ORIGIN *******
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct SpaceOrigin {
     origin: NrrdVec,
 }
@@ -377,11 +601,24 @@ impl SpaceOrigin {
     pub fn new(origin:&[f64]) -> SpaceOrigin {
         SpaceOrigin{origin:NrrdVec::new(origin)}
     }
+
+    /// returns the coordinates of the origin
+    pub fn coords(&self) -> &[f64] {
+        self.origin.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.origin.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.origin.is_empty()
+    }
 }
 
 impl HeaderDef for SpaceOrigin {
     fn patterns<'a>() -> &'a [&'a str] {
-        &["space origin: "]
+        &["space origin: ","spaceorigin: "]
     }
 }
 
@@ -404,7 +641,8 @@ impl Display for SpaceOrigin {
  ****** SPACE DIRECTIONS ******
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct SpaceDirections {
     pub directions:Vec<Option<NrrdVec>>,
 }
@@ -436,10 +674,25 @@ impl SpaceDirections {
         SpaceDirections {directions:Vec::new()}
     }
 
+    /// returns the direction vector of each axis, None for non-spatial axes
+    pub fn vectors(&self) -> &[Option<NrrdVec>] {
+        &self.directions
+    }
+
+    /// returns the components of the direction vector for an axis. Returns None if the axis is
+    /// out of range or non-spatial
+    pub fn vector(&self, axis:usize) -> Option<&[f64]> {
+        self.directions.get(axis)?.as_ref().map(|v| v.values())
+    }
+
     pub fn len(&self) -> usize {
         self.directions.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.directions.is_empty()
+    }
+
     /// returns the vector magnitude for each space direction
     pub fn norms(&self) -> Vec<f64> {
         self.directions.iter().filter_map(|x|x.as_ref()).map(|v|{
@@ -448,9 +701,22 @@ impl SpaceDirections {
     }
 }
 
+impl Default for SpaceDirections {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PerAxis for SpaceDirections {
+    type Entry = Option<NrrdVec>;
+    fn entries_mut(&mut self) -> &mut Vec<Option<NrrdVec>> {
+        &mut self.directions
+    }
+}
+
 impl HeaderDef for SpaceDirections {
     fn patterns<'a>() -> &'a [&'a str] {
-        &["space directions: "]
+        &["space directions: ","spacedirections: "]
     }
 }
 
@@ -483,13 +749,25 @@ impl Display for SpaceDirections {
  **** MEASUREMENT FRAME ******
  ****************************/
 
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct MeasurementFrame {
     frame_vecs:Vec<NrrdVec>,
 }
 
+impl MeasurementFrame {
+    pub fn vectors(&self) -> &[NrrdVec] {
+        &self.frame_vecs
+    }
+
+    pub fn vectors_mut(&mut self) -> &mut [NrrdVec] {
+        &mut self.frame_vecs
+    }
+}
+
 impl HeaderDef for MeasurementFrame {
     fn patterns<'a>() -> &'a [&'a str] {
-        &["measurement frame: "]
+        &["measurement frame: ","measurementframe: "]
     }
 }
 
@@ -519,7 +797,8 @@ impl Display for MeasurementFrame {
  ******** DIMENSION ***********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Dimension {
     dim:usize,
 }
@@ -528,6 +807,10 @@ impl Dimension {
     pub fn new(dim:usize) -> Self {
         Dimension{dim}
     }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
 }
 
 impl HeaderDef for Dimension {
@@ -558,6 +841,7 @@ impl Display for Dimension {
 
 #[derive(Debug,Clone,Copy,PartialEq,Eq)]
 #[allow(non_camel_case_types)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DType {
     int8,
     uint8,
@@ -567,8 +851,14 @@ pub enum DType {
     uint32,
     int64,
     uint64,
+    #[cfg_attr(feature = "serde", serde(rename = "float"))]
     f32,
+    #[cfg_attr(feature = "serde", serde(rename = "double"))]
     f64,
+    /// half-precision float. This is not part of the NRRD spec
+    #[cfg(feature = "f16")]
+    #[cfg_attr(feature = "serde", serde(rename = "float16"))]
+    f16,
     block,
 }
 
@@ -655,12 +945,62 @@ impl DType {
             DType::uint64 => size_of::<u64>(),
             DType::f32 => size_of::<f32>(),
             DType::f64 => size_of::<f64>(),
+            #[cfg(feature = "f16")]
+            DType::f16 => 2,
             DType::block => 1, // placeholder for blocksize
         }
     }
 
+    /// returns the name of the type as written in the header
+    pub fn name(&self) -> &'static str {
+        match self {
+            DType::int8 => "int8",
+            DType::uint8 => "uint8",
+            DType::int16 => "int16",
+            DType::uint16 => "uint16",
+            DType::int32 => "int32",
+            DType::uint32 => "uint32",
+            DType::int64 => "int64",
+            DType::uint64 => "uint64",
+            DType::f32 => "float",
+            DType::f64 => "double",
+            #[cfg(feature = "f16")]
+            DType::f16 => "float16",
+            DType::block => "block",
+        }
+    }
+
+    /// returns false for data types that are not part of the NRRD spec, which other readers may
+    /// not accept
+    pub fn is_standard(&self) -> bool {
+        #[cfg(feature = "f16")]
+        if *self == DType::f16 {
+            return false;
+        }
+        true
+    }
+
+    /// returns true for floating point types
+    pub fn is_float(&self) -> bool {
+        #[cfg(feature = "f16")]
+        if *self == DType::f16 {
+            return true;
+        }
+        matches!(self, DType::f32 | DType::f64)
+    }
+
+    /// returns true for opaque 'block' data, whose element size is set by the 'block size' field
+    pub fn is_block(&self) -> bool {
+        matches!(self, DType::block)
+    }
+
     pub fn new(dtype:&str) -> Self {
-        match dtype.trim() {
+        DType::from_name(dtype).unwrap_or_else(|| panic!("unknown data type {dtype}"))
+    }
+
+    /// returns the data type for any of the type names allowed by the spec
+    pub fn from_name(dtype:&str) -> Option<Self> {
+        let t = match dtype.trim() {
             "signed char" | "int8" | "int8_t" => DType::int8,
             "uchar" | "unsigned char" | "uint8" | "uint8_t" => DType::uint8,
             "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => DType::int16,
@@ -671,9 +1011,12 @@ impl DType {
             "ulonglong" | "unsigned long long" | "unsigned long long int" | "uint64" | "uint64_t" => DType::uint64,
             "float" => DType::f32,
             "double" => DType::f64,
+            #[cfg(feature = "f16")]
+            "half" | "float16" => DType::f16,
             "block" => DType::block,
-            _=> panic!("unknown data type {dtype}")
-        }
+            _=> return None,
+        };
+        Some(t)
     }
 }
 
@@ -681,7 +1024,6 @@ impl FromStr for DType {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let idx = DType::idx(s).unwrap();
-        use DType::*;
         let t = Self::new(s[idx..].trim());
         // let t = match s[idx..].trim() {
         //     "signed char" | "int8" | "int8_t" => int8,
@@ -703,20 +1045,7 @@ impl FromStr for DType {
 
 impl Display for DType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let s = match self {
-            DType::int8 => "int8",
-            DType::uint8 => "uint8",
-            DType::int16 => "int16",
-            DType::uint16 => "uint16",
-            DType::int32 => "int32",
-            DType::uint32 => "uint32",
-            DType::int64 => "int64",
-            DType::uint64 => "uint64",
-            DType::f32 => "float",
-            DType::f64 => "double",
-            DType::block => "block",
-        };
-        write!(f, "{}{s}",Self::patterns()[0])
+        write!(f, "{}{}",Self::patterns()[0],self.name())
     }
 }
 
@@ -724,12 +1053,17 @@ impl Display for DType {
  ******* BLOCKSIZE ***********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct BlockSize {
     bs: usize,
 }
 
 impl BlockSize {
+    pub fn new(bs:usize) -> BlockSize {
+        BlockSize {bs}
+    }
+
     pub fn size(&self) -> usize {
         self.bs
     }
@@ -762,15 +1096,47 @@ impl Display for BlockSize {
 
 #[derive(Debug,PartialEq,Eq,Clone,Copy)]
 #[allow(non_camel_case_types)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Encoding {
     raw,
+    #[cfg_attr(feature = "serde", serde(rename = "text"))]
     txt,
     hex,
+    #[cfg_attr(feature = "serde", serde(rename = "gzip"))]
     rawgz,
+    #[cfg_attr(feature = "serde", serde(rename = "bzip2"))]
     rawbz2,
 }
 
 impl Encoding {
+    /// returns the name of the encoding as written in the header
+    pub fn name(&self) -> &str {
+        match self {
+            Encoding::raw => "raw",
+            Encoding::txt => "txt",
+            Encoding::hex => "hex",
+            Encoding::rawgz => "gzip",
+            Encoding::rawbz2 => "bzip2",
+        }
+    }
+
+    /// guesses the encoding of a data stream from its leading bytes. Anything that doesn't start
+    /// with gzip or bzip2 magic bytes is assumed to be raw
+    pub fn sniff(leading_bytes:&[u8]) -> Encoding {
+        if leading_bytes.starts_with(&io::GZIP_MAGIC) {
+            Encoding::rawgz
+        }else if leading_bytes.starts_with(&io::BZIP2_MAGIC) {
+            Encoding::rawbz2
+        }else {
+            Encoding::raw
+        }
+    }
+
+    /// returns true for encodings that are compressed
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, Encoding::rawgz | Encoding::rawbz2)
+    }
+
     pub fn file_ext(&self) -> &str {
         match self {
             Encoding::raw => "raw",
@@ -810,7 +1176,7 @@ impl FromStr for Encoding {
             "gz" | "gzip" => rawgz,
             "bz2" | "bzip2" =>  rawbz2,
             "hex" => hex,
-            _=> panic!("unknown encoding {s}")
+            _=> return Err(())
         };
         Ok(e)
     }
@@ -818,13 +1184,7 @@ impl FromStr for Encoding {
 
 impl Display for Encoding {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Encoding::raw => write!(f,"{}raw",Self::patterns()[0]),
-            Encoding::txt => write!(f,"{}txt",Self::patterns()[0]),
-            Encoding::hex => write!(f,"{}hex",Self::patterns()[0]),
-            Encoding::rawgz => write!(f,"{}gzip",Self::patterns()[0]),
-            Encoding::rawbz2 => write!(f,"{}bzip2",Self::patterns()[0]),
-        }
+        write!(f,"{}{}",Self::patterns()[0],self.name())
     }
 }
 
@@ -847,6 +1207,8 @@ fn native_endian() -> Endian {
 }
 
 #[derive(Debug,PartialEq,Eq,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Endian {
     Big,
     Little,
@@ -898,10 +1260,22 @@ impl Display for Endian {
  ****************************/
 
 #[derive(Debug,PartialEq,Eq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Content {
     content: String,
 }
 
+impl Content {
+    pub fn new(content:&str) -> Content {
+        Content {content: content.to_string()}
+    }
+
+    /// returns the content description
+    pub fn text(&self) -> &str {
+        &self.content
+    }
+}
+
 impl HeaderDef for Content {
     fn patterns<'a>() -> &'a [&'a str] {
         &["content: "]
@@ -927,11 +1301,29 @@ impl Display for Content {
  ********** MIN/MAX **********
  ****************************/
 
-#[derive(Debug,PartialEq,Clone,Copy)]
+#[derive(Debug,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Min {
+    #[cfg_attr(feature = "serde", serde(with = "nan_as_null"))]
     min: f64,
 }
 
+impl PartialEq for Min {
+    fn eq(&self, other:&Self) -> bool {
+        same_f64(self.min, other.min)
+    }
+}
+
+impl Min {
+    pub fn new(value:f64) -> Min {
+        Min{min: value}
+    }
+
+    pub fn value(&self) -> f64 {
+        self.min
+    }
+}
+
 impl HeaderDef for Min {
     fn patterns<'a>() -> &'a [&'a str] {
         &["min: "]
@@ -949,20 +1341,38 @@ impl FromStr for Min {
 
 impl Display for Min {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}",Self::patterns()[0], self.min)
+        write!(f, "{}{}",Self::patterns()[0], format_f64(self.min))
     }
 }
 
 
 
-#[derive(Debug,PartialEq,Clone,Copy)]
+#[derive(Debug,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct OldMin {
+    #[cfg_attr(feature = "serde", serde(with = "nan_as_null"))]
     min: f64,
 }
 
+impl PartialEq for OldMin {
+    fn eq(&self, other:&Self) -> bool {
+        same_f64(self.min, other.min)
+    }
+}
+
+impl OldMin {
+    pub fn new(value:f64) -> OldMin {
+        OldMin{min: value}
+    }
+
+    pub fn value(&self) -> f64 {
+        self.min
+    }
+}
+
 impl HeaderDef for OldMin {
     fn patterns<'a>() -> &'a [&'a str] {
-        &["min: "]
+        &["old min: ","oldmin: "]
     }
 }
 
@@ -977,15 +1387,33 @@ impl FromStr for OldMin {
 
 impl Display for OldMin {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}",Self::patterns()[0], self.min)
+        write!(f, "{}{}",Self::patterns()[0], format_f64(self.min))
     }
 }
 
-#[derive(Debug,PartialEq,Clone,Copy)]
+#[derive(Debug,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Max {
+    #[cfg_attr(feature = "serde", serde(with = "nan_as_null"))]
     max: f64,
 }
 
+impl PartialEq for Max {
+    fn eq(&self, other:&Self) -> bool {
+        same_f64(self.max, other.max)
+    }
+}
+
+impl Max {
+    pub fn new(value:f64) -> Max {
+        Max{max: value}
+    }
+
+    pub fn value(&self) -> f64 {
+        self.max
+    }
+}
+
 impl HeaderDef for Max {
     fn patterns<'a>() -> &'a [&'a str] {
         &["max: "]
@@ -1003,15 +1431,33 @@ impl FromStr for Max {
 
 impl Display for Max {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", Self::patterns()[0], self.max)
+        write!(f, "{}{}", Self::patterns()[0], format_f64(self.max))
     }
 }
 
-#[derive(Debug,PartialEq,Clone,Copy)]
+#[derive(Debug,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct OldMax {
+    #[cfg_attr(feature = "serde", serde(with = "nan_as_null"))]
     max: f64,
 }
 
+impl PartialEq for OldMax {
+    fn eq(&self, other:&Self) -> bool {
+        same_f64(self.max, other.max)
+    }
+}
+
+impl OldMax {
+    pub fn new(value:f64) -> OldMax {
+        OldMax{max: value}
+    }
+
+    pub fn value(&self) -> f64 {
+        self.max
+    }
+}
+
 impl HeaderDef for OldMax {
     fn patterns<'a>() -> &'a [&'a str] {
         &["old max: ","oldmax: "]
@@ -1029,15 +1475,35 @@ impl FromStr for OldMax {
 
 impl Display for OldMax {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", Self::patterns()[0],self.max)
+        write!(f, "{}{}", Self::patterns()[0], format_f64(self.max))
     }
 }
 
+/// returns true if the format string has exactly one integer conversion (such as %d, %i or %03d)
+/// for the file index
+fn is_index_format(fmt:&str) -> bool {
+    use sprintf::parser::{parse_format_string, FormatElement};
+    use sprintf::{ConversionType, NumericParam};
+    let Ok(elements) = parse_format_string(fmt) else {
+        return false
+    };
+    let conversions = elements.iter().filter_map(|e| match e {
+        FormatElement::Format(spec) if spec.conversion_type != ConversionType::PercentSign => Some(spec),
+        _=> None,
+    }).collect::<Vec<_>>();
+    matches!(conversions.as_slice(), [spec] if
+        matches!(spec.conversion_type, ConversionType::DecInt | ConversionType::OctInt | ConversionType::HexIntLower | ConversionType::HexIntUpper)
+        && !matches!(spec.width, NumericParam::FromArgument)
+    )
+}
+
 /******************************
  ********** DATAFILE *********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum DataFile {
     SingleFile{filename: PathBuf},
     FileFormat{fmt_string: String, min:i32, max:i32, step:i32, sub_dim: Option<usize>},
@@ -1046,25 +1512,23 @@ pub enum DataFile {
 
 impl DataFile {
 
+    /// returns the data file paths. For a format, the paths are generated for each index from min
+    /// to max (inclusive) in steps of 'step', which may be negative. Panics if the format string
+    /// isn't valid, see 'check'
     pub fn paths(&self) -> Vec<PathBuf> {
 
         match &self {
             DataFile::SingleFile { filename } => vec![filename.clone()],
             DataFile::FileFormat { fmt_string, min, max, step, .. } => {
-                let mut paths = vec![];
-                if *step > 0 {
-                    for i in (min.abs()..=max.abs()).step_by(*step as usize) {
-                        paths.push(
-                            PathBuf::from(sprintf!(fmt_string, i).unwrap())
-                        )
-                    }
+                if !is_index_format(fmt_string) {
+                    panic!("data file format '{fmt_string}' must have exactly one integer conversion");
                 }
-                if *step < 0 {
-                    for i in (max.abs()..=min.abs()).rev().step_by(step.unsigned_abs() as usize) {
-                        paths.push(
-                            PathBuf::from(sprintf!(fmt_string, i).unwrap())
-                        )
-                    }
+                let (min, max, step) = (*min as i64, *max as i64, *step as i64);
+                let mut paths = vec![];
+                let mut i = min;
+                while step != 0 && (step > 0 && i <= max || step < 0 && i >= max) {
+                    paths.push(PathBuf::from(sprintf!(fmt_string, i).unwrap()));
+                    i += step;
                 }
                 paths
             }
@@ -1073,6 +1537,29 @@ impl DataFile {
 
     }
 
+    /// checks that a format has a valid format string and a step that reaches max from min
+    pub fn check(&self) -> Result<(),NrrdError> {
+        if let DataFile::FileFormat {fmt_string, min, max, step, ..} = self {
+            if !is_index_format(fmt_string) {
+                return Err(NrrdError::InvalidValue {field: "data file", value: fmt_string.clone()});
+            }
+            if *step == 0 || (max - min).signum() * step.signum() < 0 {
+                return Err(NrrdError::InvalidValue {field: "data file", value: format!("{min} {max} {step}")});
+            }
+        }
+        Ok(())
+    }
+
+    /// returns the number of axes held by each data file, if given. The files then hold the first
+    /// 'sub_dim' axes and are stacked in order over the remaining ones
+    pub fn sub_dim(&self) -> Option<usize> {
+        match self {
+            DataFile::SingleFile {..} => None,
+            DataFile::FileFormat {sub_dim, ..} => *sub_dim,
+            DataFile::List {sub_dim, ..} => *sub_dim,
+        }
+    }
+
 }
 
 impl HeaderDef for DataFile {
@@ -1088,7 +1575,9 @@ impl FromStr for DataFile {
         let idx = DataFile::idx(s).unwrap();
         let s = s[idx..].trim();
 
-        let re = Regex::new(r"(?:(\S+))\s+(-?\d+)\s+(-?\d+)\s+(-?\d+)(?:\s+(-?\d+))?")
+        // the format string must contain a conversion so that single filenames with spaces and
+        // numbers are not mistaken for a format
+        let re = Regex::new(r"^(\S*%\S*)\s+(-?\d+)\s+(-?\d+)\s+(-?\d+)(?:\s+(\d+))?$")
             .expect("invalid regex");
 
         if let Some(capture) = re.captures(s) {
@@ -1145,7 +1634,10 @@ impl Display for DataFile {
  ********** LINE SKIP ********
  ****************************/
 
-#[derive(Debug,Clone)]
+/// number of lines to skip at the start of each data file. Lines are skipped in the file as stored,
+/// before any decompression, so a compressed stream may follow uncompressed text lines
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct LineSkip {
     skip: usize,
 }
@@ -1181,8 +1673,12 @@ impl Display for LineSkip {
  ********** BYTE SKIP ********
  ****************************/
 
+/// number of bytes to skip before the data, applied after line skip and after decompression. 'rev'
+/// (byte skip -1) means the data is the last bytes of the file, or of the decompressed stream
 #[derive(Debug,PartialEq,Eq,Clone,Copy)]
 #[allow(non_camel_case_types)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "i64", try_from = "i64"))]
 pub enum ByteSkip {
     skip(usize),
     rev,
@@ -1203,6 +1699,29 @@ impl ByteSkip {
     }
 }
 
+/// byte skip as written in a header, with -1 for 'rev'
+#[cfg(feature = "serde")]
+impl From<ByteSkip> for i64 {
+    fn from(bs:ByteSkip) -> i64 {
+        match bs {
+            ByteSkip::skip(skip) => skip as i64,
+            ByteSkip::rev => -1,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<i64> for ByteSkip {
+    type Error = String;
+    fn try_from(skip:i64) -> Result<Self, Self::Error> {
+        match skip {
+            -1 => Ok(ByteSkip::rev),
+            skip if skip >= 0 => Ok(ByteSkip::skip(skip as usize)),
+            _=> Err(format!("byte skip must be -1 or at least 0, found {skip}")),
+        }
+    }
+}
+
 impl HeaderDef for ByteSkip {
     fn patterns<'a>() -> &'a [&'a str] {
         &["byte skip: ", "byteskip: "]
@@ -1235,11 +1754,56 @@ impl Display for ByteSkip {
  ******** SAMPLE UNITS ********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct SampleUnits {
     units: String,
 }
 
+/// SI prefixes recognized when converting sample units, with their scale factors
+const SI_PREFIXES:[(&str, f64);10] = [
+    ("G", 1e9), ("M", 1e6), ("k", 1e3), ("", 1.), ("c", 1e-2),
+    ("m", 1e-3), ("u", 1e-6), ("µ", 1e-6), ("n", 1e-9), ("p", 1e-12),
+];
+
+/// base units that may carry an SI prefix when converting sample units
+const SI_BASE_UNITS:[&str;8] = ["m", "s", "g", "Hz", "T", "V", "A", "mol"];
+
+impl SampleUnits {
+    /// sample units must be a single token, such as 'HU' or 'mm'
+    pub fn new(units:&str) -> Result<SampleUnits, NrrdError> {
+        let units = units.trim();
+        if units.is_empty() || units.split_whitespace().count() != 1 {
+            return Err(NrrdError::InvalidValue {field: "sample units", value: units.to_string()});
+        }
+        Ok(SampleUnits {units: units.to_string()})
+    }
+
+    pub fn units(&self) -> &str {
+        &self.units
+    }
+
+    /// splits the units into an SI prefix scale and a base unit from the known unit table
+    fn si_scale(&self) -> Option<(f64, &'static str)> {
+        SI_BASE_UNITS.iter().find_map(|base|{
+            let prefix = self.units.strip_suffix(base)?;
+            SI_PREFIXES.iter().find(|(p,_)| *p == prefix).map(|(_,scale)| (*scale, *base))
+        })
+    }
+
+    /// returns the factor that values in these units are multiplied by to express them in 'target'
+    /// units. Identical units always convert with a factor of 1. Otherwise, both units must be
+    /// the same SI base unit ('m', 's', 'g', 'Hz', 'T', 'V', 'A' or 'mol') with an SI prefix
+    pub fn scale_to(&self, target:&SampleUnits) -> Option<f64> {
+        if self.units == target.units {
+            return Some(1.);
+        }
+        let (from, from_base) = self.si_scale()?;
+        let (to, to_base) = target.si_scale()?;
+        (from_base == to_base).then_some(from / to)
+    }
+}
+
 impl HeaderDef for SampleUnits {
     fn patterns<'a>() -> &'a [&'a str] {
         &["sample units: ", "sampleunits: "]
@@ -1265,6 +1829,7 @@ impl Display for SampleUnits {
  ****************************/
 
 #[derive(Debug,PartialEq,Eq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Sizes {
     sizes: Vec<usize>
 }
@@ -1276,6 +1841,7 @@ impl Sizes {
         }
     }
 
+    /// returns the size of each axis, fastest first
     pub fn shape(&self) -> &[usize] {
         &self.sizes
     }
@@ -1283,11 +1849,20 @@ impl Sizes {
 }
 
 impl Sizes {
+    /// returns the number of elements in the array. This is 1 for empty sizes (the empty product),
+    /// although a valid header always has at least one axis
     pub fn n_elements(&self) -> usize {
         self.sizes.iter().product()
     }
 }
 
+impl PerAxis for Sizes {
+    type Entry = usize;
+    fn entries_mut(&mut self) -> &mut Vec<usize> {
+        &mut self.sizes
+    }
+}
+
 impl HeaderDef for Sizes {
     fn patterns<'a>() -> &'a [&'a str] {
         &["sizes: "]
@@ -1322,20 +1897,55 @@ impl Display for Sizes {
  *********** SPACINGS ********
  ****************************/
 
-#[derive(Debug,PartialEq,Clone)]
+#[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Spacings {
+    #[cfg_attr(feature = "serde", serde(with = "nans_as_nulls"))]
     spacings: Vec<f64>
 }
 
+impl PartialEq for Spacings {
+    fn eq(&self, other:&Self) -> bool {
+        same_f64s(&self.spacings, &other.spacings)
+    }
+}
+
 impl Spacings {
     pub fn new(spacings: &[f64]) -> Spacings {
         Spacings {
             spacings: spacings.to_vec()
         }
     }
+    /// builds spacings where None marks an axis without a spacing (written as 'nan')
+    pub fn from_options(spacings:&[Option<f64>]) -> Spacings {
+        Spacings {
+            spacings: spacings.iter().map(|s| s.unwrap_or(f64::NAN)).collect()
+        }
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.spacings
+    }
+
+    /// returns the spacing of an axis, or None if it is out of range or 'nan'
+    pub fn get(&self, axis:usize) -> Option<f64> {
+        self.spacings.get(axis).cloned().filter(|s| !s.is_nan())
+    }
+
     pub fn len(&self) -> usize {
         self.spacings.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.spacings.is_empty()
+    }
+}
+
+impl PerAxis for Spacings {
+    type Entry = f64;
+    fn entries_mut(&mut self) -> &mut Vec<f64> {
+        &mut self.spacings
+    }
 }
 
 impl HeaderDef for Spacings {
@@ -1364,7 +1974,7 @@ impl Display for Spacings {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f,"{}{}",
                Self::patterns()[0],
-               self.spacings.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")
+               self.spacings.iter().map(|x| format_f64(*x)).collect::<Vec<_>>().join(" ")
         )
     }
 }
@@ -1373,11 +1983,58 @@ impl Display for Spacings {
  *********** THICKNESS ********
  ****************************/
 
-#[derive(Debug,PartialEq,Clone)]
+#[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Thicknesses {
+    #[cfg_attr(feature = "serde", serde(with = "nans_as_nulls"))]
     thicknesses: Vec<f64>
 }
 
+impl PartialEq for Thicknesses {
+    fn eq(&self, other:&Self) -> bool {
+        same_f64s(&self.thicknesses, &other.thicknesses)
+    }
+}
+
+impl Thicknesses {
+    pub fn new(values:&[f64]) -> Thicknesses {
+        Thicknesses {
+            thicknesses: values.to_vec()
+        }
+    }
+
+    /// builds per-axis values where None marks an axis without a thickness (written as 'nan')
+    pub fn from_options(values:&[Option<f64>]) -> Thicknesses {
+        Thicknesses {
+            thicknesses: values.iter().map(|v| v.unwrap_or(f64::NAN)).collect()
+        }
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.thicknesses
+    }
+
+    /// returns the thickness of an axis, or None if it is out of range or 'nan'
+    pub fn get(&self, axis:usize) -> Option<f64> {
+        self.thicknesses.get(axis).cloned().filter(|v| !v.is_nan())
+    }
+
+    pub fn len(&self) -> usize {
+        self.thicknesses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.thicknesses.is_empty()
+    }
+}
+
+impl PerAxis for Thicknesses {
+    type Entry = f64;
+    fn entries_mut(&mut self) -> &mut Vec<f64> {
+        &mut self.thicknesses
+    }
+}
+
 impl HeaderDef for Thicknesses {
     fn patterns<'a>() -> &'a [&'a str] {
         &["thicknesses: "]
@@ -1402,7 +2059,7 @@ impl Display for Thicknesses {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f,"{}{}",
                Self::patterns()[0],
-               self.thicknesses.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")
+               self.thicknesses.iter().map(|x| format_f64(*x)).collect::<Vec<_>>().join(" ")
         )
     }
 }
@@ -1411,11 +2068,58 @@ impl Display for Thicknesses {
  *********** AXIS MINS ********
  ****************************/
 
-#[derive(Debug,PartialEq,Clone)]
+#[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct AxisMins {
+    #[cfg_attr(feature = "serde", serde(with = "nans_as_nulls"))]
     mins: Vec<f64>
 }
 
+impl PartialEq for AxisMins {
+    fn eq(&self, other:&Self) -> bool {
+        same_f64s(&self.mins, &other.mins)
+    }
+}
+
+impl AxisMins {
+    pub fn new(values:&[f64]) -> AxisMins {
+        AxisMins {
+            mins: values.to_vec()
+        }
+    }
+
+    /// builds per-axis values where None marks an axis without a min (written as 'nan')
+    pub fn from_options(values:&[Option<f64>]) -> AxisMins {
+        AxisMins {
+            mins: values.iter().map(|v| v.unwrap_or(f64::NAN)).collect()
+        }
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.mins
+    }
+
+    /// returns the min of an axis, or None if it is out of range or 'nan'
+    pub fn get(&self, axis:usize) -> Option<f64> {
+        self.mins.get(axis).cloned().filter(|v| !v.is_nan())
+    }
+
+    pub fn len(&self) -> usize {
+        self.mins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mins.is_empty()
+    }
+}
+
+impl PerAxis for AxisMins {
+    type Entry = f64;
+    fn entries_mut(&mut self) -> &mut Vec<f64> {
+        &mut self.mins
+    }
+}
+
 impl HeaderDef for AxisMins {
     fn patterns<'a>() -> &'a [&'a str] {
         &["axis mins: ","axismins: "]
@@ -1429,9 +2133,8 @@ impl FromStr for AxisMins {
         let s = s[idx..].trim();
         let mut mins = vec![];
         for mins_str in s.split_ascii_whitespace() {
-            let min = mins_str.parse::<f64>().unwrap();
-            // nans are allowed, but no Inf or -Inf
-            assert!(!min.is_infinite(),"infinite min values are not valid");
+            // nan marks an unknown min and inf/-inf an open-ended axis. Both are written back as-is
+            let min = mins_str.parse::<f64>().map_err(|_|())?;
             mins.push(min);
         }
         Ok(AxisMins{mins})
@@ -1442,7 +2145,7 @@ impl Display for AxisMins {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f,"{}{}",
                Self::patterns()[0],
-               self.mins.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")
+               self.mins.iter().map(|x| format_f64(*x)).collect::<Vec<_>>().join(" ")
         )
     }
 }
@@ -1451,11 +2154,58 @@ impl Display for AxisMins {
  *********** AXIS MAX ********
  ****************************/
 
-#[derive(Debug,PartialEq,Clone)]
+#[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct AxisMaxs {
+    #[cfg_attr(feature = "serde", serde(with = "nans_as_nulls"))]
     maxs: Vec<f64>
 }
 
+impl PartialEq for AxisMaxs {
+    fn eq(&self, other:&Self) -> bool {
+        same_f64s(&self.maxs, &other.maxs)
+    }
+}
+
+impl AxisMaxs {
+    pub fn new(values:&[f64]) -> AxisMaxs {
+        AxisMaxs {
+            maxs: values.to_vec()
+        }
+    }
+
+    /// builds per-axis values where None marks an axis without a max (written as 'nan')
+    pub fn from_options(values:&[Option<f64>]) -> AxisMaxs {
+        AxisMaxs {
+            maxs: values.iter().map(|v| v.unwrap_or(f64::NAN)).collect()
+        }
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.maxs
+    }
+
+    /// returns the max of an axis, or None if it is out of range or 'nan'
+    pub fn get(&self, axis:usize) -> Option<f64> {
+        self.maxs.get(axis).cloned().filter(|v| !v.is_nan())
+    }
+
+    pub fn len(&self) -> usize {
+        self.maxs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.maxs.is_empty()
+    }
+}
+
+impl PerAxis for AxisMaxs {
+    type Entry = f64;
+    fn entries_mut(&mut self) -> &mut Vec<f64> {
+        &mut self.maxs
+    }
+}
+
 impl HeaderDef for AxisMaxs {
     fn patterns<'a>() -> &'a [&'a str] {
         &["axis maxs: ","axismaxs: "]
@@ -1469,9 +2219,8 @@ impl FromStr for AxisMaxs {
         let s = s[idx..].trim();
         let mut maxs = vec![];
         for maxs_str in s.split_ascii_whitespace() {
-            let max = maxs_str.parse::<f64>().unwrap();
-            // nans are allowed, but no Inf, -Inf
-            assert!(!max.is_infinite(),"infinite max values are not valid");
+            // nan marks an unknown max and inf/-inf an open-ended axis. Both are written back as-is
+            let max = maxs_str.parse::<f64>().map_err(|_|())?;
             maxs.push(max);
         }
         Ok(AxisMaxs{maxs})
@@ -1482,7 +2231,7 @@ impl Display for AxisMaxs {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f,"{}{}",
                Self::patterns()[0],
-               self.maxs.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")
+               self.maxs.iter().map(|x| format_f64(*x)).collect::<Vec<_>>().join(" ")
         )
     }
 }
@@ -1492,18 +2241,48 @@ impl Display for AxisMaxs {
  *********** CENTERING *******
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Centering {
     Cell,
     Node,
     None,
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Centerings {
     centerings: Vec<Centering>
 }
 
+impl Centerings {
+    pub fn new(centering:Centering,n:usize) -> Centerings {
+        Centerings {
+            centerings: vec![centering;n],
+        }
+    }
+
+    pub fn values(&self) -> &[Centering] {
+        &self.centerings
+    }
+
+    pub fn len(&self) -> usize {
+        self.centerings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.centerings.is_empty()
+    }
+}
+
+impl PerAxis for Centerings {
+    type Entry = Centering;
+    fn entries_mut(&mut self) -> &mut Vec<Centering> {
+        &mut self.centerings
+    }
+}
+
 impl HeaderDef for Centerings {
     fn patterns<'a>() -> &'a [&'a str] {
         &["centerings: ","centers: "]
@@ -1528,7 +2307,7 @@ impl FromStr for Centerings {
         let mut centerings = vec![];
 
         for center in s.split_ascii_whitespace() {
-            match center {
+            match center.to_ascii_lowercase().as_str() {
                 "cell" => centerings.push(Centering::Cell),
                 "node" => centerings.push(Centering::Node),
                 _=> centerings.push(Centering::None),
@@ -1551,11 +2330,41 @@ impl Display for Centerings {
  ********** LABELS ***********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Labels {
     labels: Vec<String>
 }
 
+impl Labels {
+    /// construct labels from a list of strings
+    pub fn from_list(labels:&[&str]) -> Labels {
+        Labels {
+            labels: labels.iter().map(|s| s.to_string()).collect()
+        }
+    }
+
+    /// returns the label of each axis
+    pub fn values(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+impl PerAxis for Labels {
+    type Entry = String;
+    fn entries_mut(&mut self) -> &mut Vec<String> {
+        &mut self.labels
+    }
+}
+
 impl HeaderDef for Labels {
     fn patterns<'a>() -> &'a [&'a str] {
         &["labels: "]
@@ -1567,20 +2376,13 @@ impl FromStr for Labels {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let idx = Labels::idx(s).unwrap();
         let s = s[idx..].trim();
-        let re = Regex::new(r#""([^"]+)""#).unwrap();
-        let labels = re.find_iter(s)
-            .map(|m| m.as_str()[1..m.as_str().len() - 1].to_string()) // Strip quotes
-            .collect();
-        Ok(Labels{labels})
+        Ok(Labels{labels: parse_quoted(s)})
     }
 }
 
 impl Display for Labels {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f,"{}{}",
-            Self::patterns()[0],
-            self.labels.iter().map(|x|format!("\"{x}\"")).collect::<Vec<_>>().join(" ")
-        )
+        write!(f,"{}{}", Self::patterns()[0], format_quoted(&self.labels))
     }
 }
 
@@ -1588,11 +2390,34 @@ impl Display for Labels {
  *********** UNITS ***********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Units {
     units: Vec<String>
 }
 
+impl Units {
+    /// returns the unit of each axis
+    pub fn values(&self) -> &[String] {
+        &self.units
+    }
+
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+}
+
+impl PerAxis for Units {
+    type Entry = String;
+    fn entries_mut(&mut self) -> &mut Vec<String> {
+        &mut self.units
+    }
+}
+
 impl HeaderDef for Units {
     fn patterns<'a>() -> &'a [&'a str] {
         &["units: "]
@@ -1604,20 +2429,13 @@ impl FromStr for Units {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let idx = Units::idx(s).unwrap();
         let s = s[idx..].trim();
-        let re = Regex::new(r#""([^"]+)""#).unwrap();
-        let units = re.find_iter(s)
-            .map(|m| m.as_str()[1..m.as_str().len() - 1].to_string()) // Strip quotes
-            .collect();
-        Ok(Units{units})
+        Ok(Units{units: parse_quoted(s)})
     }
 }
 
 impl Display for Units {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f,"{}{}",
-               Self::patterns()[0],
-               self.units.iter().map(|x|format!("\"{x}\"")).collect::<Vec<_>>().join(" ")
-        )
+        write!(f,"{}{}", Self::patterns()[0], format_quoted(&self.units))
     }
 }
 
@@ -1625,7 +2443,8 @@ impl Display for Units {
  *********** KINDS ***********
  ****************************/
 
-#[derive(Debug,Clone)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Kinds {
     pub kinds: Vec<Kind>
 }
@@ -1643,6 +2462,21 @@ impl Kinds {
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.kinds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+}
+
+impl PerAxis for Kinds {
+    type Entry = Kind;
+    fn entries_mut(&mut self) -> &mut Vec<Kind> {
+        &mut self.kinds
+    }
 }
 
 impl HeaderDef for Kinds {
@@ -1674,8 +2508,16 @@ impl Display for Kinds {
     }
 }
 
+impl Kind {
+    /// returns true for kinds that describe a spatial (domain) axis
+    pub fn is_spatial(&self) -> bool {
+        matches!(self, Kind::domain | Kind::space)
+    }
+}
+
 #[derive(Debug,PartialEq,Clone,Copy,Eq)]
 #[allow(non_camel_case_types)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Kind {
     domain,
     space,
@@ -1683,30 +2525,50 @@ pub enum Kind {
     list,
     point,
     vector,
+    #[cfg_attr(feature = "serde", serde(rename = "covariant-vector"))]
     covariant_vector,
     normal,
     stub,
     scalar,
     complex,
+    #[cfg_attr(feature = "serde", serde(rename = "2-vector"))]
     _2_vector,
+    #[cfg_attr(feature = "serde", serde(rename = "3-color"))]
     _3_color,
+    #[cfg_attr(feature = "serde", serde(rename = "RGB-color"))]
     RGB_color,
+    #[cfg_attr(feature = "serde", serde(rename = "HSV-color"))]
     HSV_color,
+    #[cfg_attr(feature = "serde", serde(rename = "XYZ-color"))]
     XYZ_color,
+    #[cfg_attr(feature = "serde", serde(rename = "4-color"))]
     _4_color,
+    #[cfg_attr(feature = "serde", serde(rename = "RGBA-color"))]
     RGBA_color,
+    #[cfg_attr(feature = "serde", serde(rename = "3-vector"))]
     _3_vector,
+    #[cfg_attr(feature = "serde", serde(rename = "3-gradient"))]
     _3_gradient,
+    #[cfg_attr(feature = "serde", serde(rename = "3-normal"))]
     _3_normal,
+    #[cfg_attr(feature = "serde", serde(rename = "4-vector"))]
     _4_vector,
     quaternion,
+    #[cfg_attr(feature = "serde", serde(rename = "2D-symmetric-matrix"))]
     _2D_symmetric_matrix,
+    #[cfg_attr(feature = "serde", serde(rename = "2D-masked-symmetric-matrix"))]
     _2D_masked_symmetric_matrix,
+    #[cfg_attr(feature = "serde", serde(rename = "2D-matrix"))]
     _2D_matrix,
+    #[cfg_attr(feature = "serde", serde(rename = "2D-masked-matrix"))]
     _2D_masked_matrix,
+    #[cfg_attr(feature = "serde", serde(rename = "3D-symmetric-matrix"))]
     _3D_symmetric_matrix,
+    #[cfg_attr(feature = "serde", serde(rename = "3D-masked-symmetric-matrix"))]
     _3D_masked_symmetric_matrix,
+    #[cfg_attr(feature = "serde", serde(rename = "3D-matrix"))]
     _3D_matrix,
+    #[cfg_attr(feature = "serde", serde(rename = "3D-masked-matrix"))]
     _3D_masked_matrix,
     none,
 }
@@ -1758,7 +2620,8 @@ impl FromStr for Kind {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use Kind::*;
-        match s.trim() {
+        // kind names are case-insensitive, as in teem
+        match s.trim().to_ascii_lowercase().as_str() {
             "domain" => Ok(domain),
             "space" => Ok(space),
             "time" => Ok(time),
@@ -1772,24 +2635,24 @@ impl FromStr for Kind {
             "complex" => Ok(complex),
             "2-vector" => Ok(_2_vector),
             "3-color" => Ok(_3_color),
-            "RGB-color" => Ok(RGB_color),
-            "HSV-color" => Ok(HSV_color),
-            "XYZ-color" => Ok(XYZ_color),
+            "rgb-color" => Ok(RGB_color),
+            "hsv-color" => Ok(HSV_color),
+            "xyz-color" => Ok(XYZ_color),
             "4-color" => Ok(_4_color),
-            "RGBA-color" => Ok(RGBA_color),
+            "rgba-color" => Ok(RGBA_color),
             "3-vector" => Ok(_3_vector),
             "3-gradient" => Ok(_3_gradient),
             "3-normal" => Ok(_3_normal),
             "4-vector" => Ok(_4_vector),
             "quaternion" => Ok(quaternion),
-            "2D-symmetric-matrix" => Ok(_2D_symmetric_matrix),
-            "2D-masked-symmetric-matrix" => Ok(_2D_masked_symmetric_matrix),
-            "2D-matrix" => Ok(_2D_matrix),
-            "2D-masked-matrix" => Ok(_2D_masked_matrix),
-            "3D-symmetric-matrix" => Ok(_3D_symmetric_matrix),
-            "3D-masked-symmetric-matrix" => Ok(_3D_masked_symmetric_matrix),
-            "3D-matrix" => Ok(_3D_matrix),
-            "3D-masked-matrix" => Ok(_3D_masked_matrix),
+            "2d-symmetric-matrix" => Ok(_2D_symmetric_matrix),
+            "2d-masked-symmetric-matrix" => Ok(_2D_masked_symmetric_matrix),
+            "2d-matrix" => Ok(_2D_matrix),
+            "2d-masked-matrix" => Ok(_2D_masked_matrix),
+            "3d-symmetric-matrix" => Ok(_3D_symmetric_matrix),
+            "3d-masked-symmetric-matrix" => Ok(_3D_masked_symmetric_matrix),
+            "3d-matrix" => Ok(_3D_matrix),
+            "3d-masked-matrix" => Ok(_3D_masked_matrix),
             "none" => Ok(none),
             _ => panic!("invalid kind type {s}"),
         }
@@ -2,12 +2,27 @@ use std::cmp::min;
 use std::fs::File;
 use std::io;
 use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
-use bzip2::read::BzDecoder;
+use bzip2::read::MultiBzDecoder;
 use bzip2::write::BzEncoder;
-use flate2::read::GzDecoder;
+use flate2::read::MultiGzDecoder;
 use flate2::write::GzEncoder;
 
-pub fn read_until_blank(file: &mut File) -> io::Result<(Vec<u8>, Option<u64>)> {
+/// leading bytes of a gzip stream
+pub const GZIP_MAGIC: [u8;2] = [0x1f, 0x8b];
+
+/// leading bytes of a bzip2 stream
+pub const BZIP2_MAGIC: [u8;3] = *b"BZh";
+
+/// reads up to n bytes from the cursor position without advancing the cursor
+pub fn peek(f: &mut File, n: usize) -> io::Result<Vec<u8>> {
+    let pos = f.stream_position()?;
+    let mut buf = Vec::with_capacity(n);
+    (&mut *f).take(n as u64).read_to_end(&mut buf)?;
+    f.seek(SeekFrom::Start(pos))?;
+    Ok(buf)
+}
+
+pub fn read_until_blank<R:Read + Seek>(file: &mut R) -> io::Result<(Vec<u8>, Option<u64>)> {
     let start_pos = file.stream_position()?;          // where we began
     let mut rdr  = BufReader::new(file);
     let mut line = Vec::new();
@@ -30,7 +45,7 @@ pub fn read_until_blank(file: &mut File) -> io::Result<(Vec<u8>, Option<u64>)> {
 
     // Put the underlying File cursor exactly where we want it
     let unread = rdr.buffer().len();
-    let file = rdr.into_inner();                      // back to &mut File
+    let file = rdr.into_inner();                      // back to the underlying reader
 
     // First, undo the unread buffered bytes (BufReader over-read)
     if unread > 0 {
@@ -48,7 +63,7 @@ pub fn read_until_blank(file: &mut File) -> io::Result<(Vec<u8>, Option<u64>)> {
 }
 
 /// advances the file cursor to the byte just after the nth line
-pub fn skip_lines(f: &mut File, n_lines: usize) -> usize {
+pub fn skip_lines<R:Read + Seek>(f: &mut R, n_lines: usize) -> usize {
     let mut rdr = BufReader::new(f);
     let mut buf = Vec::new();
     let mut bytes = 0usize;
@@ -62,17 +77,17 @@ pub fn skip_lines(f: &mut File, n_lines: usize) -> usize {
 
     // Rewind by what BufReader buffered but we didn't consume
     let unread = rdr.buffer().len();
-    let inner = rdr.into_inner(); // gives us back &mut File
+    let inner = rdr.into_inner(); // gives us back the underlying reader
     if unread > 0 {
         inner.seek(SeekFrom::Current(-(unread as i64))).expect("failed to seek");
     }
     bytes
 }
 
-pub fn read_tail(f:&mut File, bytes: &mut [u8]) -> usize {
+pub fn read_tail<R:Read + Seek>(f:&mut R, bytes: &mut [u8]) -> usize {
 
     // 1. how many bytes do we *need* and how many are *there*?
-    let file_len = f.metadata().expect("failed to get file metadata").len();
+    let file_len = f.seek(SeekFrom::End(0)).expect("failed to seek to EOF");
     let want = bytes.len() as u64;
     if want == 0 || file_len == 0 {
         return 0
@@ -98,8 +113,8 @@ pub fn write_raw(
     f.write_all(payload).expect("failed to write raw");
 }
 
-pub fn read_raw(
-    f: &mut File,
+pub fn read_raw<R:Read + Seek>(
+    f: &mut R,
     seek_to_raw: Option<u64>,
     bytes: &mut [u8],
     bytes_to_skip: usize,
@@ -121,8 +136,9 @@ pub fn write_gzip(
 }
 
 
-pub fn read_gzip(
-    f: &mut File,
+/// decompresses a gzip stream, including streams made of several concatenated members
+pub fn read_gzip<R:Read + Seek>(
+    f: &mut R,
     seek_to_raw_compressed: Option<u64>,
     decompressed: &mut [u8],
     bytes_to_skip: usize,
@@ -130,12 +146,13 @@ pub fn read_gzip(
     if let Some(seek_to) = seek_to_raw_compressed {
         f.seek(SeekFrom::Start(seek_to)).expect("seek to raw compressed data failed");
     }
-    let mut dec = GzDecoder::new(&mut *f);
+    let mut dec = MultiGzDecoder::new(&mut *f);
     read_with_skip(&mut dec, decompressed, bytes_to_skip)
 }
 
-pub fn read_bzip2(
-    f: &mut File,
+/// decompresses a bzip2 stream, including streams made of several concatenated members
+pub fn read_bzip2<R:Read + Seek>(
+    f: &mut R,
     seek_to_raw_compressed: Option<u64>,
     decompressed: &mut [u8],
     bytes_to_skip: usize,
@@ -143,10 +160,64 @@ pub fn read_bzip2(
     if let Some(seek_to) = seek_to_raw_compressed {
         f.seek(SeekFrom::Start(seek_to)).expect("seek to raw compressed data failed");
     }
-    let mut dec = BzDecoder::new(&mut *f);
+    let mut dec = MultiBzDecoder::new(&mut *f);
     read_with_skip(&mut dec, decompressed, bytes_to_skip)
 }
 
+/// decodes hex data, two digits per byte with any whitespace between them, after skipping
+/// 'bytes_to_skip' bytes of the file. Returns the number of bytes decoded, which is less than the
+/// buffer length if the data ends early
+pub fn read_hex<R:Read>(f: &mut R, decoded: &mut [u8], bytes_to_skip: usize) -> io::Result<usize> {
+    let mut text = vec![];
+    f.read_to_end(&mut text)?;
+    let digits = text.iter().skip(bytes_to_skip).filter(|c| !c.is_ascii_whitespace());
+    let mut n = 0;
+    let mut high = None;
+    for &c in digits {
+        if n == decoded.len() {
+            break;
+        }
+        let digit = (c as char).to_digit(16).ok_or_else(||{
+            io::Error::new(io::ErrorKind::InvalidData, format!("'{}' is not a hex digit", c as char))
+        })? as u8;
+        match high.take() {
+            None => high = Some(digit),
+            Some(h) => {
+                decoded[n] = h << 4 | digit;
+                n += 1;
+            }
+        }
+    }
+    Ok(n)
+}
+
+/// decompresses a whole gzip stream and keeps its last 'decompressed.len()' bytes, as byte skip -1
+/// requires for compressed data
+pub fn read_gzip_tail<R:Read>(f: &mut R, decompressed: &mut [u8]) -> usize {
+    read_stream_tail(&mut MultiGzDecoder::new(f), decompressed)
+}
+
+/// decompresses a whole bzip2 stream and keeps its last 'decompressed.len()' bytes, as byte skip -1
+/// requires for compressed data
+pub fn read_bzip2_tail<R:Read>(f: &mut R, decompressed: &mut [u8]) -> usize {
+    read_stream_tail(&mut MultiBzDecoder::new(f), decompressed)
+}
+
+/// reads a stream to its end and copies its last bytes to the buffer. Returns the number of bytes
+/// copied, which is less than the buffer length if the stream is shorter
+pub fn read_stream_tail<R:Read>(reader: &mut R, tail: &mut [u8]) -> usize {
+    let mut all = vec![];
+    if let Err(e) = reader.read_to_end(&mut all) {
+        // a cut-off stream keeps the bytes decoded before the cut
+        if e.kind() != io::ErrorKind::UnexpectedEof {
+            panic!("failed to read from reader: {e}");
+        }
+    }
+    let n = tail.len().min(all.len());
+    tail[..n].copy_from_slice(&all[all.len() - n..]);
+    n
+}
+
 pub fn write_bzip2(
     f: &mut File,
     payload: &[u8],
@@ -156,6 +227,8 @@ pub fn write_bzip2(
     enc.try_finish().unwrap();
 }
 
+/// skips 'bytes_to_skip' bytes and then reads until the buffer is full or the stream ends. Returns
+/// the number of bytes read, which is less than the buffer length if the stream ended early
 pub fn read_with_skip<R:Read>(reader:&mut R, decompressed: &mut [u8], bytes_to_skip: usize) -> usize {
     // Discard the first `bytes_to_skip` bytes of the stream
     if bytes_to_skip > 0 {
@@ -163,9 +236,10 @@ pub fn read_with_skip<R:Read>(reader:&mut R, decompressed: &mut [u8], bytes_to_s
         let mut tmp = [0u8; 8 * 1024];
         while skipped < bytes_to_skip {
             let need = min(tmp.len(), bytes_to_skip - skipped);
-            let n = reader.read(&mut tmp[..need]).expect("failed to read from reader");
+            let n = read_until_eof(reader, &mut tmp[..need]);
             if n == 0 {
-                panic!("reached EOF while skipping")
+                // EOF while skipping, so no data could be read
+                return 0;
             }
             skipped += n;
         }
@@ -174,7 +248,7 @@ pub fn read_with_skip<R:Read>(reader:&mut R, decompressed: &mut [u8], bytes_to_s
     // Now read into the provided buffer.
     let mut written = 0usize;
     while written < decompressed.len() {
-        let n = reader.read(&mut decompressed[written..]).expect("failed to read from reader");
+        let n = read_until_eof(reader, &mut decompressed[written..]);
         if n == 0 {
             break; // EOF of decompressed stream
         }
@@ -183,4 +257,18 @@ pub fn read_with_skip<R:Read>(reader:&mut R, decompressed: &mut [u8], bytes_to_s
 
     written
 
-}
\ No newline at end of file
+}
+
+/// reads once from the reader, retrying if interrupted. A compressed stream that was cut off
+/// reports an unexpected end of file, which is treated as the end of the data (0 bytes read) so that
+/// the caller can report how much of the data is missing
+fn read_until_eof<R:Read>(reader:&mut R, buf:&mut [u8]) -> usize {
+    loop {
+        match reader.read(buf) {
+            Ok(n) => return n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return 0,
+            Err(e) => panic!("failed to read from reader: {e}"),
+        }
+    }
+}
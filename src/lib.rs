@@ -1,51 +1,2419 @@
 use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Seek, Write};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
-use num_traits::{Euclid, FromPrimitive};
+use num_traits::FromPrimitive;
 
+pub mod builder;
+pub mod error;
+pub mod geometry;
 pub mod header_defs;
 pub mod io;
+pub mod ops;
+pub mod stream;
+pub mod volume;
+#[cfg(feature = "npy")]
+pub mod npy;
+#[cfg(feature = "f16")]
+pub mod half;
+#[cfg(feature = "nifti")]
+pub mod nifti;
+#[cfg(feature = "ndarray")]
+pub mod array;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+
+#[cfg(feature = "npy")]
+pub use npy::{read_npy, write_npy};
+#[cfg(feature = "ndarray")]
+pub use array::read_nrrd_to_array;
+#[cfg(feature = "tokio")]
+pub use async_io::{read_nrrd_to_async, read_payload_async};
+pub use volume::Volume;
+pub use error::NrrdError;
+pub use builder::NrrdBuilder;
+pub use geometry::{DicomGeometry, ResamplingPlan};
+pub use ops::{concat, volume_diff, DiffStats, DownsampleMode};
+pub use stream::{NrrdReader, NrrdWriter};
+
+use header_defs::{AxisMaxs, AxisMins, BlockSize, ByteSkip, Centering, Centerings, Comment, Content, DType, DataFile, Dimension, Encoding, Endian, HeaderDef, Kinds, PerAxis, Labels, LineSkip, Magic, Max, MeasurementFrame, Min, NRRDType, OldMax, OldMin, SampleUnits, Sizes, Space, SpaceDimension, SpaceDirections, SpaceOrigin, SpaceUnits, Spacings, Thicknesses, Units, Value};
 
-use header_defs::{AxisMaxs, AxisMins, BlockSize, ByteSkip, Centerings, Comment, Content, DType, DataFile, Dimension, Encoding, Endian, HeaderDef, Kinds, Labels, LineSkip, Magic, Max, Min, NRRDType, OldMax, OldMin, SampleUnits, Sizes, Space, SpaceDimension, SpaceDirections, SpaceOrigin, SpaceUnits, Spacings, Thicknesses, Units, Value};
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::File;
+    use crate::header_defs::Encoding;
+    use super::*;
+
+    #[test]
+    pub fn read_header() {
+        let test_header = "test_nrrds/detached_list.nhdr";
+        let mut f = File::open(test_header).unwrap();
+        let (header_bytes,..) = io::read_until_blank(&mut f).expect("failed to read header");
+        let header_str = String::from_utf8(header_bytes).expect("failed to convert bytes to string");
+        let mut header_lines = header_str.lines().collect::<Vec<&str>>();
+        let _ = NRRD::from_lines_full(&mut header_lines);
+        // this means we accounted for every line in the string
+        assert!(header_lines.is_empty());
+    }
+
+    #[test]
+    pub fn resolve_detached() {
+
+        let test_header = "test_nrrds/detached_multi.nhdr";
+        let mut f = File::open(test_header).unwrap();
+        let (header_bytes,..) = io::read_until_blank(&mut f).expect("failed to read header");
+        let header_str = String::from_utf8(header_bytes).expect("failed to convert bytes to string");
+        let mut header_lines = header_str.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut header_lines);
+
+        assert!(header_lines.is_empty());
+
+        println!("{h}");
+        let paths = h.data_file.as_ref().unwrap().paths();
+        println!("{paths:?}");
+    }
+
+    #[test]
+    fn endian_whitespace_tolerant() {
+        let e:Endian = read_header_def(&mut vec!["endian:big"]).unwrap();
+        assert_eq!(e, Endian::Big);
+        let e:Endian = read_header_def(&mut vec!["endian:  BIG"]).unwrap();
+        assert_eq!(e, Endian::Big);
+        // key-values that share a field name must not be parsed as the field
+        let mut lines = vec!["endian:=little"];
+        assert!(read_header_def::<Endian>(&mut lines).is_none());
+        assert_eq!(lines.len(),1);
+    }
+
+    #[test]
+    fn measurement_frame_round_trip() {
+        let h = crate::read_header("test_nrrds/dti.nhdr");
+        let mf = h.measurement_frame.as_ref().expect("measurement frame not parsed").to_string();
+        assert_eq!(mf,"measurement frame: (1,0,0) (0,-1,0) (0,0,1)");
+        let s = h.to_string();
+        let mut lines = s.lines().collect::<Vec<&str>>();
+        let h_ = NRRD::from_lines_full(&mut lines);
+        assert!(lines.is_empty());
+        assert_eq!(h_.measurement_frame.unwrap().to_string(),mf);
+    }
+
+    #[cfg(feature = "nifti")]
+    #[test]
+    fn nifti_export() {
+        let data:Vec<f32> = (0..120).map(|x| x as f32).collect();
+        let mut h = NRRD::new_from_dims::<f32>(&[4,5,6]);
+        h.space = Some(Space::LPS);
+        h.space_directions = Some("space directions: (0.5,0,0) (0,0.5,0) (0,0,2)".parse().unwrap());
+        h.space_origin = Some(SpaceOrigin::new(&[10.,20.,30.]));
+        h.space_units = Some(SpaceUnits::new_mm(3));
+
+        // LPS is flipped to RAS in x and y, which is a 180 degree rotation about z
+        let nii = h.to_nifti(&data).unwrap();
+        assert_eq!(nii.srow, Some([[-0.5,0.,0.,-10.],[0.,-0.5,0.,-20.],[0.,0.,2.,30.]]));
+        assert_eq!(nii.quatern(), Some(([0.,0.,1.], 1.)));
+        assert_eq!(nii.pixdim, vec![0.5,0.5,2.]);
+
+        nii.write("test_out_nifti.nii").unwrap();
+        let bytes = fs::read("test_out_nifti.nii").unwrap();
+        fs::remove_file("test_out_nifti.nii").unwrap();
+        let i16_at = |o:usize| i16::from_ne_bytes([bytes[o],bytes[o + 1]]);
+        let f32_at = |o:usize| f32::from_ne_bytes(bytes[o..o + 4].try_into().unwrap());
+        assert_eq!(i32::from_ne_bytes(bytes[0..4].try_into().unwrap()), 348);
+        assert_eq!((0..4).map(|i| i16_at(40 + 2 * i)).collect::<Vec<_>>(), vec![3,4,5,6]);
+        assert_eq!(i16_at(70), 16);
+        assert_eq!(f32_at(108), 352.);
+        assert_eq!(bytes[123], 2);
+        assert_eq!(f32_at(280), -0.5);
+        assert_eq!(f32_at(292), -10.);
+        assert_eq!(&bytes[344..348], b"n+1\0");
+        assert_eq!(bytemuck::cast_slice::<u8,f32>(&bytes[352..]), data.as_slice());
+
+        // spatial axes have to come first
+        let mut h = NRRD::new_from_dims::<f32>(&[2,3,4,5]);
+        h.space = Some(Space::RAS);
+        h.space_directions = Some("space directions: none (1,0,0) (0,1,0) (0,0,1)".parse().unwrap());
+        assert!(matches!(h.to_nifti(&vec![0f32;120]), Err(NrrdError::Unsupported(_))));
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn ndarray_indexing() {
+        use ndarray::{Ix2, Ix3, IxDyn};
+
+        // a 2x3x4 volume as written by 'unu save -e text', where the sample at (x,y,z) is 100z + 10y + x
+        let values:Vec<String> = (0..4).flat_map(|z| (0..3).flat_map(move |y| (0..2).map(move |x| (100*z + 10*y + x).to_string()))).collect();
+        let text = format!("NRRD0004\ntype: short\ndimension: 3\nsizes: 2 3 4\nencoding: ascii\n\n{}\n", values.join(" "));
+        fs::write("test_out_ndarray.nrrd", text).unwrap();
+
+        let (a,_) = read_nrrd_to_array::<i16, IxDyn>("test_out_ndarray.nrrd");
+        assert_eq!(a.shape(), &[2,3,4]);
+        assert_eq!(a[[0,0,0].as_slice()], 0);
+        assert_eq!(a[[1,0,0].as_slice()], 1);
+        assert_eq!(a[[0,2,0].as_slice()], 20);
+        assert_eq!(a[[1,2,3].as_slice()], 321);
+
+        let (a,_) = read_nrrd_to_array::<f32, Ix3>("test_out_ndarray.nrrd");
+        assert_eq!(a[[1,1,2]], 211.);
+        assert_eq!(a[[0,2,3]], 320.);
+        fs::remove_file("test_out_ndarray.nrrd").unwrap();
+
+        // 2D: x along the first size, y along the second
+        let data:Vec<u8> = (0..6).collect();
+        write_nrrd("test_out_ndarray_2d", &NRRD::new_from_dims::<u8>(&[3,2]), &data, true, Encoding::raw);
+        let (a,_) = read_nrrd_to_array::<u8, Ix2>("test_out_ndarray_2d.nrrd");
+        assert_eq!(a.dim(), (3,2));
+        assert_eq!(a[[2,0]], 2);
+        assert_eq!(a[[0,1]], 3);
+        assert_eq!(a[[2,1]], 5);
+        fs::remove_file("test_out_ndarray_2d.nrrd").unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_read_matches_sync() {
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let data:Vec<u16> = (0..60).collect();
+        let h = NRRD::new_from_dims::<u16>(&[3,4,5]);
+
+        // attached and detached raw data are read with tokio, gzip on the blocking pool
+        write_nrrd("test_out_async_attached", &h, &data, true, Encoding::raw);
+        write_nrrd("test_out_async_detached", &h, &data, false, Encoding::raw);
+        write_nrrd("test_out_async_gz", &h, &data, true, Encoding::rawgz);
+        let files:Vec<PathBuf> = (0..5).map(|i| PathBuf::from(format!("test_out_async_list_{i}.raw"))).collect();
+        write_nrrd_list("test_out_async_list.nhdr", &h, &data, &files, Encoding::raw).unwrap();
+
+        for path in ["test_out_async_attached.nrrd","test_out_async_detached.nhdr","test_out_async_gz.nrrd","test_out_async_list.nhdr"] {
+            let (bytes,h_) = rt.block_on(read_payload_async(path));
+            assert_eq!((bytes,h_), read_payload(path), "{path}");
+            let (x,_) = rt.block_on(read_nrrd_to_async::<f64>(path));
+            assert_eq!(x, read_nrrd_to::<f64>(path).0, "{path}");
+        }
+
+        // skipped lines and bytes in front of attached raw data
+        let mut file = b"NRRD0004\ntype: uint16\ndimension: 1\nsizes: 3\nendian: little\nencoding: raw\nline skip: 1\nbyte skip: 2\n\nskip\n..".to_vec();
+        file.extend_from_slice(bytemuck::cast_slice(&[7u16,8,9]));
+        fs::write("test_out_async_skip.nrrd", file).unwrap();
+        assert_eq!(rt.block_on(read_nrrd_to_async::<u16>("test_out_async_skip.nrrd")).0, vec![7,8,9]);
+
+        for f in ["test_out_async_attached.nrrd","test_out_async_detached.nhdr","test_out_async_detached.raw","test_out_async_gz.nrrd","test_out_async_list.nhdr","test_out_async_skip.nrrd"] {
+            fs::remove_file(f).unwrap();
+        }
+        files.iter().for_each(|f| fs::remove_file(f).unwrap());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_json_round_trip() {
+        let header = "NRRD0005\n# a comment\ntype: short\ndimension: 4\nspace: left-posterior-superior\nsizes: 7 4 5 6\n\
+            space directions: none (1.5,0,0) (0,1.5,0) (0,0,2)\nkinds: list domain domain RGB-color\ncenters: ??? cell cell node\n\
+            spacings: nan 1.5 1.5 2\nendian: little\nencoding: gzip\nbyte skip: -1\nspace origin: (-1,-2,-3)\nold min: -5\n\
+            measurement frame: (1,0,0) (0,-1,0) (0,0,1)\nmodality:=DWMRI\ndata file: LIST 3\na.raw.gz\nb.raw.gz\nc.raw.gz\nd.raw.gz\ne.raw.gz\nf.raw.gz";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let mut h = NRRD::from_lines_full(&mut lines);
+        h.source_lines.clear();
+
+        let json = serde_json::to_value(&h).unwrap();
+        assert_eq!(json["type"], "int16");
+        assert_eq!(json["encoding"], "gzip");
+        assert_eq!(json["space"], "left-posterior-superior");
+        assert_eq!(json["sizes"], serde_json::json!([7,4,5,6]));
+        assert_eq!(json["spacings"], serde_json::json!([null,1.5,1.5,2.0]));
+        assert_eq!(json["space_origin"], serde_json::json!([-1.0,-2.0,-3.0]));
+        assert_eq!(json["space_directions"][0], serde_json::Value::Null);
+        assert_eq!(json["space_directions"][3], serde_json::json!([0.0,0.0,2.0]));
+        assert_eq!(json["kinds"], serde_json::json!(["list","domain","domain","RGB-color"]));
+        assert_eq!(json["centerings"], serde_json::json!(["none","cell","cell","node"]));
+        assert_eq!(json["byte_skip"], -1);
+        assert_eq!(json["old_min"], -5.0);
+        assert_eq!(json["data_file"]["list"]["sub_dim"], 3);
+        assert_eq!(json["key_vals"]["modality"], "DWMRI");
+
+        let h_:NRRD = serde_json::from_str(&serde_json::to_string(&h).unwrap()).unwrap();
+        assert_eq!(h_, h);
+        assert_eq!(h_.to_string(), h.to_string());
+    }
+
+    #[cfg(feature = "npy")]
+    #[test]
+    fn npy_export() {
+        let dims = [2,3,4];
+        let n = dims.iter().product::<usize>();
+        let data:Vec<_> = (0..n).map(|x| x as f32).collect();
+        let nrrd = NRRD::new_from_dims::<f32>(&dims);
+        write_npy("test_out_npy.npy", &data, &nrrd).unwrap();
+
+        let bytes = fs::read("test_out_npy.npy").unwrap();
+        fs::remove_file("test_out_npy.npy").unwrap();
+        assert_eq!(&bytes[0..6], b"\x93NUMPY");
+        let header_len = u16::from_le_bytes([bytes[8],bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.contains("'descr': '<f4'") || header.contains("'descr': '>f4'"));
+        assert!(header.contains("'shape': (4, 3, 2)"));
+        let data_:&[f32] = bytemuck::cast_slice(&bytes[10 + header_len..]);
+        assert_eq!(data_, data.as_slice());
+    }
+
+    #[cfg(feature = "npy")]
+    #[test]
+    fn npy_round_trip() {
+        let dims = [2,3,4];
+        let n = dims.iter().product::<usize>();
+        let data:Vec<_> = (0..n).map(|x| x as f32 * 0.5).collect();
+        let nrrd = NRRD::new_from_dims::<f32>(&dims);
+        write_npy("test_out_npy_rt.npy", &data, &nrrd).unwrap();
+
+        // npy -> nrrd
+        let (bytes,h) = read_npy("test_out_npy_rt.npy").unwrap();
+        assert_eq!(h.shape(), &dims);
+        assert_eq!(h.dtype, DType::f32);
+        let typed:Vec<f32> = bytemuck::pod_collect_to_vec(&bytes);
+        write_nrrd("test_out_npy_rt", &h, &typed, true, Encoding::raw);
+
+        // nrrd -> npy
+        let (data_,h_) = read_nrrd_to::<f32>("test_out_npy_rt.nrrd");
+        write_npy("test_out_npy_rt2.npy", &data_, &h_).unwrap();
+
+        assert_eq!(fs::read("test_out_npy_rt.npy").unwrap(), fs::read("test_out_npy_rt2.npy").unwrap());
+        fs::remove_file("test_out_npy_rt.npy").unwrap();
+        fs::remove_file("test_out_npy_rt2.npy").unwrap();
+        fs::remove_file("test_out_npy_rt.nrrd").unwrap();
+    }
+
+    #[test]
+    fn indexed_volume() {
+        let dims = [5,6,7];
+        let n = dims.iter().product::<usize>();
+        let data:Vec<_> = (0..n).map(|x| x as u32).collect();
+        let mut vol = Volume::new(NRRD::new_from_dims::<u32>(&dims), data.clone()).unwrap();
+        assert_eq!(vol.strides(), &[1,5,30]);
+        for k in 0..dims[2] {
+            for j in 0..dims[1] {
+                for i in 0..dims[0] {
+                    assert_eq!(vol.get(&[i,j,k]), Some(&data[i + j*dims[0] + k*dims[0]*dims[1]]));
+                }
+            }
+        }
+        vol.set(&[4,5,6], 0);
+        assert_eq!(vol.as_slice()[n - 1], 0);
+    }
+
+    #[test]
+    fn validate_axis_lengths() {
+        crate::read_header_validated("test_nrrds/dti.nhdr").expect("dti header should be valid");
+
+        let header = "NRRD0004\ntype: float\ndimension: 3\nsizes: 2 3 4\nspacings: 1 1\nendian: little\nencoding: raw";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+        match h.validate() {
+            Err(NrrdError::FieldLength {field,expected,found}) => {
+                assert_eq!((field,expected,found),("spacings",3,2));
+            }
+            r => panic!("expected a field length error, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn uniform_centering() {
+        let h = NrrdBuilder::new(DType::f32, &[2,3,4])
+            .centering(Centering::Cell)
+            .build()
+            .unwrap();
+        assert!(h.to_string().lines().any(|l| l == "centerings: cell cell cell"));
+
+        let mut h = NRRD::new_from_dims::<f32>(&[2,3]);
+        h.set_uniform_centering(Centering::Node);
+        assert_eq!(h.centerings.unwrap().to_string(), "centerings: node node");
+    }
+
+    #[test]
+    fn strict_parsing() {
+        let header = "NRRD0004\ntype: float\ndimension: 3\nsizes: 2 3 4\nspacing: 1 1 1\nendian: little\nencoding: raw\nkey:=value";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        match NRRD::from_lines_strict(&mut lines) {
+            Err(NrrdError::UnrecognizedLines(l)) => assert_eq!(l, vec!["spacing: 1 1 1".to_string()]),
+            r => panic!("expected unrecognized lines error, got {r:?}"),
+        }
+
+        // lenient parsing ignores the typo
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+        assert!(h.spacings.is_none());
+
+        let fixed = header.replace("spacing:","spacings:");
+        let mut lines = fixed.lines().collect::<Vec<&str>>();
+        assert!(NRRD::from_lines_strict(&mut lines).is_ok());
+    }
+
+    #[test]
+    fn verify_encodings() {
+        let dir = Path::new("test_out_verify_enc");
+        fs::create_dir_all(dir).unwrap();
+
+        let files = ["slice_0.raw","slice_1.raw","slice_2.raw"];
+        let mut h = NRRD::new_from_dims::<u8>(&[4,4,3]);
+        h.data_file = Some(DataFile::List {
+            file_paths: files.iter().map(PathBuf::from).collect(),
+            sub_dim: None,
+        });
+        fs::write(dir.join("stack.nhdr"), h.to_string()).unwrap();
+        for (i,file) in files.iter().enumerate() {
+            let mut f = File::create(dir.join(file)).unwrap();
+            if i == 1 {
+                io::write_gzip(&mut f, &[0u8;16]);
+            }else {
+                io::write_raw(&mut f, &[0u8;16]);
+            }
+        }
+
+        let h = crate::read_header(dir.join("stack.nhdr"));
+        let result = h.verify_data_file_encodings(dir.join("stack.nhdr"));
+        fs::remove_dir_all(dir).unwrap();
+
+        match result {
+            Err(NrrdError::EncodingMismatch {declared,files}) => {
+                assert_eq!(declared, Encoding::raw);
+                assert_eq!(files, vec![(dir.join("slice_1.raw"), Encoding::rawgz)]);
+            }
+            r => panic!("expected encoding mismatch, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn write_list() {
+        let dir = Path::new("test_out_write_list");
+        fs::create_dir_all(dir).unwrap();
+
+        let dims = [2,2,4];
+        let n = dims.iter().product::<usize>();
+        let data:Vec<_> = (0..n).map(|x| x as u16).collect();
+        let h = NRRD::new_from_dims::<u16>(&dims);
+        let files:Vec<PathBuf> = (0..4).map(|i| PathBuf::from(format!("slice_{i}.raw.gz"))).collect();
+
+        write_nrrd_list(dir.join("stack.nhdr"), &h, &data, &files, Encoding::rawgz).unwrap();
+        let (data_,h_) = read_nrrd_to::<u16>(dir.join("stack.nhdr"));
+        let uneven = write_nrrd_list(dir.join("uneven.nhdr"), &h, &data, &files[0..3], Encoding::raw);
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(data_,data);
+        assert_eq!(h_.data_file.unwrap().paths(),files);
+        assert!(matches!(uneven, Err(NrrdError::UnevenFileSplit {n_files: 3, n_elements: 16})));
+    }
+
+    #[test]
+    fn axis_coordinates() {
+        let mut h = NRRD::new_from_dims::<f32>(&[3,2,4]);
+        h.space_directions = Some(SpaceDirections::from_spacing(&[0.5,2.,-1.]));
+        h.space_origin = Some(SpaceOrigin::new(&[10.,-4.,1.]));
+        assert_eq!(h.axis_coordinates(0).unwrap(), vec![10.,10.5,11.]);
+        assert_eq!(h.axis_coordinates(1).unwrap(), vec![-4.,-2.]);
+        assert_eq!(h.axis_coordinates(2).unwrap(), vec![1.,0.,-1.,-2.]);
+        assert!(h.axis_coordinates(3).is_none());
+
+        let mut h = NRRD::new_from_dims::<f32>(&[3,2]);
+        h.spacings = Some(Spacings::new(&[0.25,f64::NAN]));
+        assert_eq!(h.axis_coordinates(0).unwrap(), vec![0.,0.25,0.5]);
+        assert!(h.axis_coordinates(1).is_none());
+    }
+
+    #[test]
+    fn write_fileformat() {
+        let dir = Path::new("test_out_write_fmt");
+        fs::create_dir_all(dir).unwrap();
+
+        let dims = [3,2,4];
+        let n = dims.iter().product::<usize>();
+        let data:Vec<_> = (0..n).map(|x| x as f32).collect();
+        let h = NRRD::new_from_dims::<f32>(&dims);
+
+        write_nrrd_fileformat(dir.join("stack.nhdr"), &h, &data, "slice_%03d.raw", 1, 4, 1, Encoding::raw).unwrap();
+        let exists = (1..=4).all(|i| dir.join(format!("slice_{i:03}.raw")).exists());
+        let (data_,..) = read_nrrd_to::<f32>(dir.join("stack.nhdr"));
+        fs::remove_dir_all(dir).unwrap();
+
+        assert!(exists);
+        assert_eq!(data_,data);
+    }
+
+    #[test]
+    fn write_method() {
+        let dims = [4,3,2];
+        let n = dims.iter().product::<usize>();
+        let data:Vec<_> = (0..n).map(|x| x as i32 - 10).collect();
+        let h = NRRD::new_from_dims::<u8>(&dims);
+
+        h.write("test_out_method", &data, true, Encoding::rawgz).unwrap();
+        let (data_,h_) = read_nrrd_to::<i32>("test_out_method.nrrd");
+        fs::remove_file("test_out_method.nrrd").unwrap();
+        assert_eq!(data_,data);
+        assert_eq!(h_.dtype, DType::int32);
+
+        let short = h.write("test_out_method", &data[1..], true, Encoding::raw);
+        assert!(matches!(short, Err(NrrdError::DataLength {expected: 24, found: 23})));
+    }
+
+    #[test]
+    fn write_non_native_endian() {
+        let dims = [3,2];
+        let data:Vec<u16> = vec![1,2,3,0x0102,0xfff0,0];
+        let h = NRRD::new_from_dims::<u16>(&dims);
+        let endian = match Endian::native() {
+            Endian::Big => Endian::Little,
+            Endian::Little => Endian::Big,
+        };
+        let options = WriteOptions {endian, ..WriteOptions::new(false, Encoding::raw)};
+        h.write_with_options("test_out_endian", &data, &options).unwrap();
+
+        let raw = fs::read("test_out_endian.raw").unwrap();
+        let (data_,h_) = read_nrrd_to::<u16>("test_out_endian.nhdr");
+        fs::remove_file("test_out_endian.raw").unwrap();
+        fs::remove_file("test_out_endian.nhdr").unwrap();
+
+        let swapped:Vec<u8> = data.iter().flat_map(|x| x.swap_bytes().to_ne_bytes()).collect();
+        assert_eq!(raw,swapped);
+        assert_eq!(h_.endian,endian);
+        assert_eq!(data_,data);
+    }
+
+    #[test]
+    fn builder() {
+        let h = NrrdBuilder::new(DType::uint8, &[2,3,4])
+            .dtype(DType::f32)
+            .encoding(Encoding::rawgz)
+            .space(Space::RAS)
+            .space_directions_from_spacing(&[0.5,0.5,1.])
+            .space_origin(&[0.,0.,0.])
+            .kinds(&[header_defs::Kind::domain;3])
+            .labels(&["x","y","z"])
+            .build()
+            .unwrap();
+        assert_eq!(h.dtype, DType::f32);
+        assert_eq!(h.encoding, Encoding::rawgz);
+        assert!(h.space_dimension.is_none());
+        assert_eq!(h.labels.unwrap().to_string(), r#"labels: "x" "y" "z""#);
+
+        let bad = NrrdBuilder::new(DType::f32, &[2,3,4])
+            .space_directions_from_spacing(&[0.5,0.5])
+            .build();
+        assert!(matches!(bad, Err(NrrdError::FieldLength {field: "space directions", ..})));
+    }
+
+    #[test]
+    fn truncated_header() {
+        let header = "NRRD0004\ntype: float\ndimension: 3\nsizes: 700 3";
+        fs::write("test_out_truncated.nhdr", header).unwrap();
+        let truncated = read_header_checked("test_out_truncated.nhdr");
+        fs::write("test_out_truncated.nhdr", format!("{header}60 1\nendian: little\nencoding: raw\ndata file: x.raw")).unwrap();
+        let complete = read_header_checked("test_out_truncated.nhdr");
+        fs::remove_file("test_out_truncated.nhdr").unwrap();
+
+        match truncated {
+            Err(NrrdError::TruncatedHeader {partial_line}) => assert_eq!(partial_line, "sizes: 700 3"),
+            r => panic!("expected truncated header error, got {r:?}"),
+        }
+        // no trailing newline, but nothing is missing
+        assert_eq!(complete.unwrap().shape(), &[700,360,1]);
+    }
+
+    #[test]
+    fn support_matrix() {
+        for encoding in [Encoding::raw, Encoding::rawgz, Encoding::rawbz2] {
+            assert!(supports_reading(encoding) && supports_writing(encoding));
+        }
+        for encoding in [Encoding::txt, Encoding::hex] {
+            assert!(supports_reading(encoding) && !supports_writing(encoding));
+        }
+        assert_eq!(supported_features().contains(&"npy"), cfg!(feature = "npy"));
+    }
+
+    #[test]
+    fn geometry_accessors() {
+        let h = crate::read_header("test_nrrds/dti.nhdr");
+        let sd = h.space_directions.as_ref().unwrap();
+        assert!(sd.vectors()[0].is_none());
+        assert_eq!(sd.vectors()[1].as_ref().unwrap().values(), &[1.875,0.,0.]);
+        assert_eq!(sd.vector(3).unwrap(), &[0.,0.,2.]);
+        assert!(sd.vector(0).is_none());
+        assert_eq!(h.space_origin.as_ref().unwrap().coords(), &[-119.0625,-119.0625,-59.]);
+        assert_eq!(Spacings::new(&[1.,2.]).values(), &[1.,2.]);
+    }
+
+    #[test]
+    fn space_directions_without_none() {
+        let header = "NRRD0004\ntype: short\ndimension: 4\nspace dimension: 3\nsizes: 7 128 128 60\nspace directions: (1.875,0,0) (0,1.875,0) (0,0,2)\nendian: little\nencoding: raw";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+        assert_eq!(h.spatial_axes(), vec![1,2,3]);
+        assert!(h.validate().is_ok());
+        assert!(h.space_directions.as_ref().unwrap().to_string().starts_with("space directions: none (1.875"));
+
+        // kinds decide which axis is non-spatial
+        let header = header.replace("dimension: 4\n","dimension: 4\nkinds: domain domain domain list\n");
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+        assert_eq!(h.spatial_axes(), vec![0,1,2]);
+    }
+
+    #[test]
+    fn affine() {
+        let header = "NRRD0004\ntype: float\ndimension: 3\nspace: left-posterior-superior\nsizes: 10 20 30\nspace directions: (0,0.5,0) (-0.25,0,0) (0,0,2)\nspace origin: (10,-20,5)\nendian: little\nencoding: raw";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+        let expected = [
+            [0.,-0.25,0.,10.],
+            [0.5,0.,0.,-20.],
+            [0.,0.,2.,5.],
+            [0.,0.,0.,1.],
+        ];
+        assert_eq!(h.affine().unwrap(), expected);
+
+        // leading non-spatial axis is skipped
+        let h = crate::read_header("test_nrrds/dti.nhdr");
+        let a = h.affine().unwrap();
+        assert_eq!(a[0], [1.875,0.,0.,-119.0625]);
+        assert_eq!(a[2], [0.,0.,2.,-59.]);
+
+        let h = NRRD::new_from_dims::<f32>(&[2,2,2]);
+        assert!(h.affine().is_none());
+    }
+
+    #[test]
+    fn complex_to_mag_phase() {
+        use crate::header_defs::{Kind, SpaceDirections};
+        let sd = {
+            let mut sd = SpaceDirections::new();
+            sd.extend_none();
+            sd.extend_from_spacing(&[0.5,0.5]);
+            sd
+        };
+        let h = NrrdBuilder::new(DType::int16, &[2,2,3])
+            .kinds(&[Kind::complex,Kind::domain,Kind::domain])
+            .space_directions(sd)
+            .build().unwrap();
+        // re/im pairs: (3,4) (0,1) (-1,0) (0,-2) (1,1) (-5,0)
+        let data:Vec<i16> = vec![3,4, 0,1, -1,0, 0,-2, 1,1, -5,0];
+        let ((mag,hm),(phase,hp)) = h.complex_to_mag_phase(&data).unwrap();
+        assert_eq!(mag, vec![5.,1.,1.,2.,2f32.sqrt(),5.]);
+        let expected_phase = [4f32.atan2(3.),std::f32::consts::FRAC_PI_2,std::f32::consts::PI,-std::f32::consts::FRAC_PI_2,std::f32::consts::FRAC_PI_4,std::f32::consts::PI];
+        phase.iter().zip(expected_phase).for_each(|(p,e)| assert!((p - e).abs() < 1e-6));
+        for h in [&hm,&hp] {
+            assert_eq!(h.shape(), &[2,3]);
+            assert_eq!(h.dtype, DType::f32);
+            assert_eq!(h.kinds.as_ref().unwrap().len(), 2);
+            assert_eq!(h.spatial_axes(), vec![0,1]);
+            assert!(h.validate().is_ok());
+        }
+
+        let h = NRRD::new_from_dims::<i16>(&[2,3]);
+        assert!(h.complex_to_mag_phase(&[0i16;6]).is_err());
+    }
+
+    #[test]
+    fn block_round_trip() {
+        use crate::header_defs::BlockSize;
+        fs::create_dir_all("test_out_blocks").unwrap();
+        let mut h = NRRD::new_from_type_dims(DType::block, &[2,2]);
+        h.block_size = Some(BlockSize::new(3));
+        let blocks:Vec<Vec<u8>> = (0..4u8).map(|i| vec![i,i+10,i+20]).collect();
+
+        write_blocks("test_out_blocks/attached", &h, &blocks, true, Encoding::raw).unwrap();
+        let (b,hb) = read_blocks("test_out_blocks/attached.nrrd");
+        assert_eq!(b, blocks);
+        assert_eq!(hb.element_size(), 3);
+
+        write_blocks("test_out_blocks/detached", &h, &blocks, false, Encoding::rawgz).unwrap();
+        let (b,_) = read_blocks("test_out_blocks/detached.nhdr");
+        assert_eq!(b, blocks);
+
+        let mut bad = blocks.clone();
+        bad[2].push(0);
+        assert!(matches!(
+            write_blocks("test_out_blocks/bad", &h, &bad, true, Encoding::raw),
+            Err(NrrdError::BlockLength {index: 2, expected: 3, found: 4})
+        ));
+        fs::remove_dir_all("test_out_blocks").unwrap();
+    }
+
+    #[test]
+    fn append_slices() {
+        let dir = Path::new("test_out_append");
+        fs::create_dir_all(dir).unwrap();
+
+        let h = NRRD::new_from_dims::<i16>(&[4,2,3]);
+        let data:Vec<i16> = (0..24).collect();
+        write_nrrd_fileformat(dir.join("stack.nhdr"), &h, &data, "slice_%02d.raw", 0, 2, 1, Encoding::raw).unwrap();
+        let first_slice = fs::read(dir.join("slice_00.raw")).unwrap();
+
+        let s3:Vec<i16> = (24..32).collect();
+        let s4:Vec<i16> = (32..40).collect();
+        crate::append_slices(dir.join("stack.nhdr"), &[&s3, &s4]).unwrap();
+
+        let (data_,h_) = read_nrrd_to::<i16>(dir.join("stack.nhdr"));
+        assert_eq!(h_.shape(), &[4,2,5]);
+        assert_eq!(data_, (0..40).collect::<Vec<i16>>());
+        assert_eq!(fs::read(dir.join("slice_00.raw")).unwrap(), first_slice);
+
+        // wrong slice shape
+        let bad = [0i16;3];
+        assert!(matches!(
+            crate::append_slices(dir.join("stack.nhdr"), &[&bad]),
+            Err(NrrdError::DataLength {expected: 8, found: 3})
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dtype_is_block() {
+        let all = [
+            DType::int8, DType::uint8, DType::int16, DType::uint16, DType::int32,
+            DType::uint32, DType::int64, DType::uint64, DType::f32, DType::f64, DType::block,
+        ];
+        for dtype in all {
+            assert_eq!(dtype.to_string().parse::<DType>().unwrap(), dtype);
+            assert_eq!(dtype.is_block(), dtype.to_string() == "type: block");
+        }
+    }
+
+    #[test]
+    fn resampling_needed() {
+        let grid = |sizes:&[usize], directions:&str, origin:&str| {
+            let header = format!("NRRD0004\ntype: float\ndimension: 3\nspace: left-posterior-superior\nsizes: {}\nspace directions: {directions}\nspace origin: {origin}\nendian: little\nencoding: raw",
+                sizes.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(" "));
+            let mut lines = header.lines().collect::<Vec<&str>>();
+            NRRD::from_lines_full(&mut lines)
+        };
+        let a = grid(&[10,20,30], "(1,0,0) (0,2,0) (0,0,3)", "(0,0,0)");
+
+        assert_eq!(a.resampling_needed(&a.clone(), 1e-6), ResamplingPlan::None);
+
+        // first two axes swapped, with the new first axis running backwards
+        let b = grid(&[20,10,30], "(0,-2,0) (1,0,0) (0,0,3)", "(0,38,0)");
+        assert_eq!(a.resampling_needed(&b, 1e-6), ResamplingPlan::ReorientOnly {order: vec![1,0,2], flip: vec![0]});
+
+        let c = grid(&[10,20,30], "(1,0,0) (0,2,0) (0,0,1.5)", "(0,0,0)");
+        assert_eq!(a.resampling_needed(&c, 1e-6), ResamplingPlan::InterpolationRequired);
+        let d = grid(&[10,20,30], "(1,0,0) (0,2,0) (0,0,3)", "(0.5,0,0)");
+        assert_eq!(a.resampling_needed(&d, 1e-6), ResamplingPlan::InterpolationRequired);
+
+        // without an origin the directions are still compared, only the first sample isn't
+        let no_origin = |directions:&str| {
+            let mut h = NRRD::new_from_dims::<f32>(&[64,64,64]);
+            h.space = Some(Space::LPS);
+            h.space_directions = Some(format!("space directions: {directions}").parse().unwrap());
+            h
+        };
+        let fine = no_origin("(0.5,0,0) (0,0.5,0) (0,0,0.5)");
+        let coarse = no_origin("(2,0,0) (0,2,0) (0,0,2)");
+        assert_eq!(fine.resampling_needed(&fine.clone(), 1e-6), ResamplingPlan::None);
+        assert_eq!(fine.resampling_needed(&coarse, 1e-6), ResamplingPlan::InterpolationRequired);
+        assert!(!fine.same_grid(&coarse, 1e-6));
+        let swapped = no_origin("(0,-0.5,0) (0.5,0,0) (0,0,0.5)");
+        assert_eq!(fine.resampling_needed(&swapped, 1e-6), ResamplingPlan::ReorientOnly {order: vec![1,0,2], flip: vec![0]});
+
+        // spacings stand in for directions
+        let mut fine = NRRD::new_from_dims::<f32>(&[64,64,64]);
+        fine.spacings = Some("spacings: 0.5 0.5 0.5".parse().unwrap());
+        let mut coarse = fine.clone();
+        coarse.spacings = Some("spacings: 2 2 2".parse().unwrap());
+        assert_eq!(fine.resampling_needed(&coarse, 1e-6), ResamplingPlan::InterpolationRequired);
+        assert_eq!(fine.resampling_needed(&fine.clone(), 1e-6), ResamplingPlan::None);
+    }
+
+    #[cfg(feature = "f16")]
+    #[test]
+    fn f16_round_trip() {
+        use crate::half::Half;
+        assert_eq!(Half::from_f32(1.).to_bits(), 0x3c00);
+        assert_eq!(Half::from_f32(-2.).to_bits(), 0xc000);
+        assert_eq!(Half::from_f32(65504.).to_bits(), 0x7bff);
+        assert_eq!(Half::from_f32(1e6).to_bits(), 0x7c00);
+        assert_eq!(Half::from_f32(2f32.powi(-24)).to_bits(), 0x0001);
+        assert_eq!(Half::from_bits(0x3555).to_f32(), 0.33325195);
+        assert!(Half::from_f32(f32::NAN).to_f32().is_nan());
+
+        let h = NRRD::new_from_dims::<Half>(&[3,2]);
+        let data:Vec<Half> = [0.,0.5,-1.5,2.,1024.,-0.25].iter().map(|&x| Half::from_f32(x)).collect();
+        assert!(matches!(
+            h.write("test_out_f16", &data, true, Encoding::raw),
+            Err(NrrdError::NonStandardType(DType::f16))
+        ));
+
+        let options = WriteOptions {allow_nonstandard_types: true, endian: Endian::Big, ..WriteOptions::new(true, Encoding::raw)};
+        h.write_with_options("test_out_f16", &data, &options).unwrap();
+        let (read,hr) = read_nrrd_to::<f32>("test_out_f16.nrrd");
+        fs::remove_file("test_out_f16.nrrd").unwrap();
+        assert_eq!(hr.dtype, DType::f16);
+        assert_eq!(read, vec![0.,0.5,-1.5,2.,1024.,-0.25]);
+    }
+
+    #[test]
+    fn sample_units() {
+        let mut h = NRRD::new_from_dims::<f32>(&[2,2]);
+        assert!(h.sample_units().is_none());
+        h.set_sample_units("HU").unwrap();
+        h.write("test_out_sample_units", &[0f32;4], true, Encoding::raw).unwrap();
+        let h_ = crate::read_header("test_out_sample_units.nrrd");
+        fs::remove_file("test_out_sample_units.nrrd").unwrap();
+        assert_eq!(h_.sample_units(), Some("HU"));
+
+        assert!(matches!(h.set_sample_units("photons per voxel"), Err(NrrdError::InvalidValue {..})));
+        assert_eq!(h.sample_units(), Some("HU"));
+
+        let mut data = vec![1f32,2.,3.,4.];
+        h.set_sample_units("mm").unwrap();
+        h.convert_sample_units(&mut data, "um").unwrap();
+        assert_eq!(data, vec![1000.,2000.,3000.,4000.]);
+        assert_eq!(h.sample_units(), Some("um"));
+        assert!(matches!(h.convert_sample_units(&mut data, "ms"), Err(NrrdError::IncompatibleUnits {..})));
+    }
+
+    #[test]
+    fn key_value_parsing() {
+        let mut h = crate::read_header("test_nrrds/dti.nhdr");
+        assert_eq!(h.get_kv_parsed::<f64>("DWMRI_b-value"), Some(Ok(1000.)));
+        assert_eq!(h.get_kv_parsed::<String>("modality"), Some(Ok("DWMRI".to_string())));
+        assert!(h.get_kv_parsed::<u32>("modality").unwrap().is_err());
+        assert!(h.get_kv_parsed::<u32>("missing").is_none());
+        assert_eq!(h.get_kv_vec::<f64>("DWMRI_gradient_0001", None), Some(Ok(vec![1.,0.,0.])));
+
+        h.set_kv("DWMRI_b-value", 2000);
+        h.set_kv("bvals", "0,1000,2000");
+        assert_eq!(h.get_kv_parsed::<u32>("DWMRI_b-value"), Some(Ok(2000)));
+        assert_eq!(h.get_kv_vec::<u32>("bvals", Some(',')), Some(Ok(vec![0,1000,2000])));
+        assert!(h.to_string().contains("bvals:=0,1000,2000"));
+    }
+
+    #[test]
+    fn duplicate_key_values() {
+        let header = "NRRD0004\ntype: float\ndimension: 1\nsizes: 4\nendian: little\nencoding: raw\nscanner:=A\nscanner:=B\nsite:=X";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+        let values:Vec<&str> = h.duplicate_key_vals["scanner"].iter().map(|v| v.val.as_str()).collect();
+        assert_eq!(values, vec!["A","B"]);
+        assert!(!h.duplicate_key_vals.contains_key("site"));
+
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        assert!(matches!(NRRD::from_lines_strict(&mut lines), Err(NrrdError::DuplicateKeys(keys)) if keys == vec!["scanner"]));
+    }
+
+    #[test]
+    fn key_value_escaping() {
+        use crate::header_defs::Value;
+        assert_eq!("key:= one space".parse::<Value>().unwrap().val, "one space");
+        assert_eq!("key:=  two spaces".parse::<Value>().unwrap().val, " two spaces");
+        assert_eq!(r"key:=a\\b\tc".parse::<Value>().unwrap().val, "a\\b\tc");
+
+        let description = "first line\nsecond line\twith a tab\nand a \\ backslash";
+        let mut h = NRRD::new_from_dims::<u8>(&[2]);
+        h.set_kv("description", description);
+        let header = h.to_string();
+        assert!(header.contains(r"description:=first line\nsecond line\twith a tab\nand a \\ backslash"));
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h_ = NRRD::from_lines_full(&mut lines);
+        assert_eq!(h_.key_vals["description"].val, description);
+    }
+
+    #[test]
+    fn read_in_background() {
+        let dir = Path::new("test_out_background");
+        fs::create_dir_all(dir).unwrap();
+        let h = NRRD::new_from_dims::<u16>(&[4,3,5]);
+        let data:Vec<u16> = (0..60).collect();
+        write_nrrd_fileformat(dir.join("stack.nhdr"), &h, &data, "slice_%d.raw", 0, 4, 1, Encoding::raw).unwrap();
+
+        let handle = read_nrrd_async_thread::<u16>(dir.join("stack.nhdr"), None);
+        let (data_,h_) = handle.join().unwrap().unwrap();
+        assert_eq!(data_, data);
+        assert_eq!(h_.shape(), &[4,3,5]);
+
+        let token = CancelToken::new();
+        token.cancel();
+        let handle = read_nrrd_async_thread::<u16>(dir.join("stack.nhdr"), Some(token));
+        let result = handle.join().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        assert!(matches!(result, Err(NrrdError::Cancelled)));
+    }
+
+    #[test]
+    fn preserve_field_order() {
+        let header = "NRRD0004\n# first comment\ntype: float\nsizes: 2 3\nscanner:=A\n# second comment\ndimension: 2\nendian: little\nspacings: 1 2\nencoding: raw\nsite:=X\ndata file: LIST\na.raw\nb.raw\n";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let mut h = NRRD::from_lines_full(&mut lines);
+        assert_eq!(h.write_preserving_order(), header);
+
+        // new fields go before the data file
+        h.set_kv("added", 1);
+        let out = h.write_preserving_order();
+        assert!(out.contains("site:=X\nadded:=1\ndata file: LIST\n"));
+    }
+
+    #[test]
+    fn pyramid() {
+        use crate::header_defs::{Kind, Spacings};
+        let mut h = NRRD::new_from_dims::<f32>(&[3,8,4]);
+        h.kinds = Some(Kinds::from_vec(vec![Kind::list,Kind::domain,Kind::domain]));
+        h.spacings = Some(Spacings::new(&[f64::NAN,0.5,1.]));
+        let data:Vec<f32> = (0..96).map(|x| x as f32).collect();
+
+        let levels = h.pyramid(&data, 5);
+        let shapes:Vec<&[usize]> = levels.iter().map(|(_,h)| h.shape()).collect();
+        assert_eq!(shapes, vec![&[3,8,4][..], &[3,4,2], &[3,2,1]]);
+        assert_eq!(levels[1].1.spacings.as_ref().unwrap().values()[1..], [1.,2.]);
+        assert_eq!(levels[2].1.spacings.as_ref().unwrap().values()[1..], [2.,4.]);
+
+        // each level 1 sample is the mean of a 2x2 block of the same list entry
+        let (l1,..) = &levels[1];
+        assert_eq!(l1[0], (0. + 3. + 24. + 27.) / 4.);
+        assert_eq!(l1[1 + 3], (7. + 10. + 31. + 34.) / 4.);
+        assert_eq!(l1.len(), 3 * 4 * 2);
+    }
+
+    #[test]
+    fn duplicate_fields() {
+        let header = "NRRD0004\ntype: float\ndimension: 2\nsizes: 2 3\nsizes: 4 5\nendian: little\nencoding: raw";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        assert!(matches!(NRRD::from_lines_strict(&mut lines), Err(NrrdError::DuplicateFields(f)) if f == vec!["sizes"]));
+
+        let header = "NRRD0004\ntype: float\ndimension: 1\nsizes: 2\nendian: little\nencoding: raw\ndata file: LIST\na.raw\nb.raw";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        assert!(NRRD::from_lines_strict(&mut lines).is_ok());
+    }
+
+    #[test]
+    fn compact_vector_formatting() {
+        use crate::header_defs::SpaceOrigin;
+        for (origin,expected) in [
+            (vec![0.,0.,0.], "space origin: (0,0,0)"),
+            (vec![-12.5,0.,99.25], "space origin: (-12.5,0,99.25)"),
+            (vec![0.1,1./3.,-119.0625], "space origin: (0.1,0.3333333333333333,-119.0625)"),
+        ] {
+            let o = SpaceOrigin::new(&origin);
+            assert_eq!(o.to_string(), expected);
+            assert_eq!(o.to_string().parse::<SpaceOrigin>().unwrap().coords(), origin.as_slice());
+        }
+        let sd = SpaceDirections::from_spacing(&[0.03,0.03]);
+        assert_eq!(sd.to_string(), "space directions: (0.03,0) (0,0.03)");
+    }
+
+    #[test]
+    fn magic_versions() {
+        use crate::header_defs::Magic;
+        for version in 1..=5 {
+            let line = format!("NRRD000{version}");
+            let magic = line.parse::<Magic>().unwrap();
+            assert_eq!(magic.version, version);
+            assert_eq!(magic.to_string(), line);
+        }
+        assert!("NRRD0006".parse::<Magic>().is_err());
+        assert!("NRRD0000".parse::<Magic>().is_err());
+        assert!("NRRD4".parse::<Magic>().is_err());
+
+        fs::write("test_out_magic.nhdr", "NRRD0009\ntype: float\ndimension: 1\nsizes: 2\nendian: little\nencoding: raw\n").unwrap();
+        let result = read_header_checked("test_out_magic.nhdr");
+        fs::remove_file("test_out_magic.nhdr").unwrap();
+        assert!(matches!(result, Err(NrrdError::InvalidValue {field: "magic", ..})));
+    }
+
+    #[test]
+    fn quick_stat() {
+        let header = "NRRD0005\n# comment\ntype: unsigned short\ndimension: 3\nspacings: not a number\nkinds: bogus bogus bogus\nsizes: 4 5 6\nspace directions: garbage\nendian: little\nencoding: raw\n\n";
+        fs::write("test_out_quick_stat.nrrd", header).unwrap();
+        let stat = crate::quick_stat("test_out_quick_stat.nrrd");
+        fs::write("test_out_quick_stat.nrrd", header.replace("sizes: 4 5 6","sizes: 4 5")).unwrap();
+        let bad = crate::quick_stat("test_out_quick_stat.nrrd");
+        fs::remove_file("test_out_quick_stat.nrrd").unwrap();
+
+        assert_eq!(stat.unwrap(), QuickStat {version: 5, dimension: 3, dtype: DType::uint16, sizes: vec![4,5,6]});
+        assert!(matches!(bad, Err(NrrdError::FieldLength {field: "sizes", ..})));
+    }
+
+    #[test]
+    fn nan_per_axis() {
+        use crate::header_defs::Spacings;
+        let spacings = Spacings::from_options(&[None,Some(0.5),Some(2.)]);
+        assert_eq!(spacings.to_string(), "spacings: nan 0.5 2");
+        assert_eq!(spacings.get(0), None);
+        assert_eq!(spacings.get(1), Some(0.5));
+
+        let mins = AxisMins::from_options(&[None,Some(-1.25)]);
+        assert_eq!(mins.to_string(), "axis mins: nan -1.25");
+        let reparsed = mins.to_string().parse::<AxisMins>().unwrap();
+        assert_eq!(reparsed.to_string(), "axis mins: nan -1.25");
+        assert_eq!(reparsed.get(0), None);
+        assert_eq!(reparsed.get(1), Some(-1.25));
+
+        let header = "NRRD0004\ntype: float\ndimension: 2\nsizes: 3 4\nspacings: NaN 1\naxis maxs: nan 3\nendian: little\nencoding: raw";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+        let out = h.to_string();
+        assert!(out.contains("spacings: nan 1\n"));
+        assert!(out.contains("axis maxs: nan 3\n"));
+    }
+
+    #[test]
+    fn axis_mins_maxs_nan_inf() {
+        let mins = "axis mins: 0 nan 2".parse::<AxisMins>().unwrap();
+        assert_eq!(mins.to_string(), "axis mins: 0 nan 2");
+        assert_eq!(mins.get(1), None);
+
+        // open-ended axes are accepted and written back as inf/-inf
+        let maxs = "axis maxs: +inf 1 -inf".parse::<AxisMaxs>().unwrap();
+        assert_eq!(maxs.values()[0], f64::INFINITY);
+        assert_eq!(maxs.to_string(), "axis maxs: inf 1 -inf");
+        assert_eq!(maxs.to_string().parse::<AxisMaxs>().unwrap().to_string(), "axis maxs: inf 1 -inf");
+
+        assert!("axis mins: 0 abc".parse::<AxisMins>().is_err());
+    }
+
+    #[test]
+    fn minimal_float_formatting() {
+        let mut h = NRRD::new_from_dims::<f32>(&[4,4,4]);
+        h.spacings = Some("spacings: 0.03 0.03 0.1".parse().unwrap());
+        h.space_origin = Some(SpaceOrigin::new(&[0.03,-1.5,0.]));
+        h.min = Some("min: 0.1".parse().unwrap());
+        let s = h.to_string();
+        assert!(s.contains("spacings: 0.03 0.03 0.1\n"));
+        assert!(s.contains("space origin: (0.03,-1.5,0)\n"));
+        assert!(s.contains("min: 0.1\n"));
+    }
+
+    #[test]
+    fn detach_attach_round_trip() {
+        let data:Vec<u16> = (0..60).collect();
+        let mut h = NRRD::new_from_dims::<u16>(&[3,4,5]);
+        h.set_kv("origin", "detach test");
+        h.write("test_out_detach_src", &data, true, Encoding::raw).unwrap();
+
+        let src = crate::read_header("test_out_detach_src.nrrd");
+        src.detach("test_out_detach_src.nrrd", "test_out_detach_pair", Encoding::rawgz).unwrap();
+        let (detached,dh) = read_nrrd_to::<u16>("test_out_detach_pair.nhdr");
+        assert_eq!(detached, data);
+        assert_eq!(dh.encoding, Encoding::rawgz);
+        assert_eq!(dh.get_kv_parsed::<String>("origin"), Some(Ok("detach test".to_string())));
+
+        dh.attach("test_out_detach_pair.nhdr", "test_out_detach_back", Encoding::raw).unwrap();
+        let (attached,ah) = read_nrrd_to::<u16>("test_out_detach_back.nrrd");
+        assert_eq!(attached, data);
+        assert!(ah.data_file.is_none());
+
+        // the header must describe the same data
+        let other = NRRD::new_from_dims::<u16>(&[3,4]);
+        assert!(matches!(
+            other.detach("test_out_detach_src.nrrd", "test_out_detach_bad", Encoding::raw),
+            Err(NrrdError::DataLength {expected: 12, found: 60})
+        ));
+
+        for f in ["test_out_detach_src.nrrd","test_out_detach_pair.nhdr","test_out_detach_pair.raw.gz","test_out_detach_back.nrrd"] {
+            std::fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn resolved_data_paths() {
+        let data:Vec<u8> = (0..24).collect();
+        let h = NRRD::new_from_dims::<u8>(&[2,3,4]);
+        std::fs::create_dir_all("test_out_resolved").unwrap();
+        write_nrrd_fileformat("test_out_resolved/vol.nhdr", &h, &data, "slice_%02d.raw", 0, 3, 1, Encoding::raw).unwrap();
+
+        let h = crate::read_header("test_out_resolved/vol.nhdr");
+        let paths = h.resolved_data_paths("test_out_resolved/vol.nhdr").unwrap();
+        assert_eq!(paths.len(), 4);
+        assert_eq!(paths[2], Path::new("test_out_resolved/slice_02.raw"));
+
+        std::fs::remove_file("test_out_resolved/slice_01.raw").unwrap();
+        std::fs::remove_file("test_out_resolved/slice_03.raw").unwrap();
+        match h.resolved_data_paths("test_out_resolved/vol.nhdr") {
+            Err(NrrdError::MissingDataFiles(missing)) => assert_eq!(missing, vec![
+                PathBuf::from("test_out_resolved/slice_01.raw"),
+                PathBuf::from("test_out_resolved/slice_03.raw"),
+            ]),
+            other => panic!("expected missing data files, got {other:?}"),
+        }
+
+        std::fs::remove_dir_all("test_out_resolved").unwrap();
+    }
+
+    #[test]
+    fn comment_positions_preserved() {
+        let header = "NRRD0004\ntype: float\ndimension: 2\n# acquired at 30um\nspacings: 0.03 0.03\nsizes: 3 4\n# second comment\nendian: little\nencoding: raw";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+        let out = h.write_preserving_order();
+        let out_lines:Vec<&str> = out.lines().collect();
+        assert_eq!(out_lines, vec![
+            "NRRD0004",
+            "type: float",
+            "dimension: 2",
+            "# acquired at 30um",
+            "spacings: 0.03 0.03",
+            "sizes: 3 4",
+            "# second comment",
+            "endian: little",
+            "encoding: raw",
+        ]);
+
+        // comments added after parsing follow the ones from the source
+        let mut h = h;
+        h.comments.push("# added".to_string());
+        let out = h.write_preserving_order();
+        assert_eq!(out.lines().position(|l| l == "# added"), Some(9));
+    }
+
+    #[test]
+    fn multi_member_gzip() {
+        use flate2::write::GzEncoder;
+        let data:Vec<u16> = (0..48).collect();
+        let bytes:&[u8] = bytemuck::cast_slice(&data);
+
+        // two gzip members, each holding half of the volume, as produced by 'cat a.gz b.gz'
+        let mut gz = vec![];
+        let mut first_member_len = 0;
+        for half in bytes.chunks(bytes.len() / 2) {
+            let mut enc = GzEncoder::new(vec![], flate2::Compression::default());
+            enc.write_all(half).unwrap();
+            gz.extend(enc.finish().unwrap());
+            if first_member_len == 0 {
+                first_member_len = gz.len();
+            }
+        }
+        std::fs::write("test_out_multi_member.raw.gz", &gz).unwrap();
+
+        let mut h = NRRD::new_from_dims::<u16>(&[4,3,4]);
+        h.encoding = Encoding::rawgz;
+        h.data_file = Some(DataFile::SingleFile {filename: PathBuf::from("test_out_multi_member.raw.gz")});
+        std::fs::write("test_out_multi_member.nhdr", h.to_string()).unwrap();
+
+        let (read,_) = read_nrrd_to::<u16>("test_out_multi_member.nhdr");
+        assert_eq!(read, data);
+
+        // a stream that ends early is an error rather than zero-filled data
+        std::fs::write("test_out_multi_member.raw.gz", &gz[..first_member_len]).unwrap();
+        assert!(matches!(
+            crate::read_payload_cancellable("test_out_multi_member.nhdr", None),
+            Err(NrrdError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+
+        std::fs::remove_file("test_out_multi_member.nhdr").unwrap();
+        std::fs::remove_file("test_out_multi_member.raw.gz").unwrap();
+    }
+
+    #[test]
+    fn volume_diff() {
+        let h = NRRD::new_from_dims::<f32>(&[4,4,4]);
+        let a:Vec<f32> = (0..64).map(|x| x as f32).collect();
+        let mut b = a.clone();
+        b[3] += 0.5;
+        b[10] -= 2.;
+        let diff = crate::volume_diff(&a, &h, &b, &h).unwrap();
+        assert_eq!(diff.max_abs, 2.);
+        assert_eq!(diff.n_differing, 2);
+        assert!((diff.rms - (4.25f64 / 64.).sqrt()).abs() < 1e-12);
+
+        assert_eq!(crate::volume_diff(&a, &h, &a, &h).unwrap(), DiffStats {max_abs: 0., rms: 0., n_differing: 0});
+
+        let other = NRRD::new_from_dims::<f32>(&[8,8]);
+        assert!(matches!(crate::volume_diff(&a, &h, &a, &other), Err(NrrdError::GridMismatch)));
+    }
+
+    #[test]
+    fn short_data_file() {
+        let data:Vec<f32> = (0..24).map(|x| x as f32).collect();
+        let h = NRRD::new_from_dims::<f32>(&[2,3,4]);
+        h.write("test_out_short", &data, false, Encoding::raw).unwrap();
+
+        // drop the last element from the data file
+        let bytes = std::fs::read("test_out_short.raw").unwrap();
+        std::fs::write("test_out_short.raw", &bytes[..bytes.len() - 4]).unwrap();
+        match crate::read_payload_cancellable("test_out_short.nhdr", None) {
+            Err(NrrdError::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+                assert!(e.to_string().contains("ended after 92 of 96 bytes"), "{e}");
+            }
+            other => panic!("expected a short read error, got {other:?}"),
+        }
+
+        // attached data is checked the same way
+        h.write("test_out_short", &data, true, Encoding::raw).unwrap();
+        let bytes = std::fs::read("test_out_short.nrrd").unwrap();
+        std::fs::write("test_out_short.nrrd", &bytes[..bytes.len() - 10]).unwrap();
+        assert!(crate::read_payload_cancellable("test_out_short.nrrd", None).is_err());
+
+        for f in ["test_out_short.nhdr","test_out_short.raw","test_out_short.nrrd"] {
+            std::fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn streaming_writer() {
+        let h = NRRD::new_from_dims::<i16>(&[8,6,5]);
+        let data:Vec<i16> = (0..240).map(|x| x as i16 - 120).collect();
+
+        for (path,encoding) in [("test_out_stream_w.nrrd",Encoding::rawgz),("test_out_stream_w.nhdr",Encoding::raw)] {
+            let mut writer = NrrdWriter::<i16>::new(path, &h, encoding).unwrap();
+            for slice in data.chunks(48) {
+                writer.write_chunk(slice).unwrap();
+            }
+            assert_eq!(writer.elements_written(), 240);
+            writer.finish().unwrap();
+            let (read,rh) = read_nrrd_to::<i16>(path);
+            assert_eq!(read, data);
+            assert_eq!(rh.encoding, encoding);
+        }
+
+        // writing too little or too much is an error
+        let mut writer = NrrdWriter::<i16>::new("test_out_stream_w.nrrd", &h, Encoding::raw).unwrap();
+        writer.write_chunk(&data[..100]).unwrap();
+        assert!(matches!(writer.finish(), Err(NrrdError::DataLength {expected: 240, found: 100})));
+        let mut writer = NrrdWriter::<i16>::new("test_out_stream_w.nrrd", &h, Encoding::raw).unwrap();
+        writer.write_chunk(&data).unwrap();
+        assert!(writer.write_chunk(&data[..1]).is_err());
+
+        for f in ["test_out_stream_w.nrrd","test_out_stream_w.nhdr","test_out_stream_w.raw"] {
+            std::fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn streaming_reader() {
+        let h = NRRD::new_from_dims::<f32>(&[5,4,6]);
+        let data:Vec<f32> = (0..120).map(|x| x as f32 * 0.5).collect();
+        h.write("test_out_stream_r", &data, true, Encoding::rawbz2).unwrap();
+        write_nrrd_fileformat("test_out_stream_r.nhdr", &h, &data, "test_out_stream_r_%d.raw", 0, 2, 1, Encoding::raw).unwrap();
+
+        for path in ["test_out_stream_r.nrrd","test_out_stream_r.nhdr"] {
+            let (rh,reader) = NrrdReader::<f64>::open(path).unwrap();
+            assert_eq!(rh.shape(), &[5,4,6]);
+            assert_eq!(reader.remaining(), 6);
+            let mut total = 0.;
+            let mut n = 0;
+            for slice in reader {
+                let slice = slice.unwrap();
+                assert_eq!(slice.len(), 20);
+                total += slice.iter().sum::<f64>();
+                n += 1;
+            }
+            assert_eq!(n, 6);
+            let (full,_) = read_nrrd_to::<f64>(path);
+            assert_eq!(total, full.iter().sum::<f64>());
+        }
+
+        for f in ["test_out_stream_r.nrrd","test_out_stream_r.nhdr","test_out_stream_r_0.raw","test_out_stream_r_1.raw","test_out_stream_r_2.raw"] {
+            std::fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn read_from_bytes() {
+        let data:Vec<u16> = (0..30).collect();
+        let h = NRRD::new_from_dims::<u16>(&[5,6]);
+        h.write("test_out_from_bytes", &data, true, Encoding::rawgz).unwrap();
+        h.write("test_out_from_bytes", &data, false, Encoding::raw).unwrap();
+
+        let attached = std::fs::read("test_out_from_bytes.nrrd").unwrap();
+        let header = crate::read_header_from_bytes(&attached);
+        assert_eq!(header.shape(), &[5,6]);
+        assert_eq!(header.encoding, Encoding::rawgz);
+
+        let (bytes,rh) = read_payload_from_reader(&mut std::io::Cursor::new(attached), None).unwrap();
+        assert_eq!(bytemuck::cast_slice::<u8,u16>(&bytes), data.as_slice());
+        assert_eq!(rh.dtype, DType::uint16);
+
+        // detached data files are found relative to the base directory
+        let detached = std::fs::read("test_out_from_bytes.nhdr").unwrap();
+        let (bytes,_) = read_payload_from_reader(&mut std::io::Cursor::new(&detached), Some(Path::new("."))).unwrap();
+        assert_eq!(bytemuck::cast_slice::<u8,u16>(&bytes), data.as_slice());
+        assert!(matches!(
+            read_payload_from_reader(&mut std::io::Cursor::new(&detached), Some(Path::new("missing_dir"))),
+            Err(NrrdError::MissingDataFiles(_))
+        ));
+
+        for f in ["test_out_from_bytes.nrrd","test_out_from_bytes.nhdr","test_out_from_bytes.raw"] {
+            std::fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn nhdr_without_trailing_newline() {
+        let data:Vec<u8> = (0..12).collect();
+        std::fs::write("test_out_no_newline.raw", &data).unwrap();
+        let header = "NRRD0004\ntype: uint8\ndimension: 2\nsizes: 3 4\nendian: little\nencoding: raw\ndata file: test_out_no_newline.raw";
+
+        for (i,h) in [header.to_string(), header.replace('\n', "\r\n"), format!("{header}\n")].iter().enumerate() {
+            std::fs::write("test_out_no_newline.nhdr", h).unwrap();
+            let parsed = crate::read_header_checked("test_out_no_newline.nhdr").unwrap();
+            assert_eq!(parsed.shape(), &[3,4], "case {i}");
+            let (read,_) = read_nrrd_to::<u8>("test_out_no_newline.nhdr");
+            assert_eq!(read, data, "case {i}");
+        }
+
+        // errors in a detached header are reported as-is rather than as truncation
+        std::fs::write("test_out_no_newline.nhdr", header.replace("endian: little\n", "")).unwrap();
+        assert!(matches!(crate::read_header_checked("test_out_no_newline.nhdr"), Err(NrrdError::MissingField("endian"))));
+
+        std::fs::remove_file("test_out_no_newline.nhdr").unwrap();
+        std::fs::remove_file("test_out_no_newline.raw").unwrap();
+    }
+
+    #[test]
+    fn sample_world_coord() {
+        let mut h = NRRD::new_from_dims::<f32>(&[2,4,6]);
+        h.space_directions = Some("space directions: none (0.5,0,0) (0,2,0)".parse().unwrap());
+        h.space_origin = Some(SpaceOrigin::new(&[10.,20.]));
+        h.centerings = Some("centers: ??? node cell".parse().unwrap());
+
+        // the origin is the first sample of both axes, as in teem
+        assert_eq!(h.sample_world_coord(&[0,0,0]), Some(vec![10.,20.]));
+        assert_eq!(h.sample_world_coord(&[1,3,2]), Some(vec![11.5,24.]));
+        assert_eq!(h.sample_world_coord(&[0,0]), None);
+
+        // from axis mins, the cell axis is shifted from the edge of the first cell to its center
+        h.space_origin = None;
+        assert_eq!(h.sample_world_coord(&[0,0,0]), None);
+        h.space_directions = None;
+        h.spacings = Some("spacings: nan 0.5 2".parse().unwrap());
+        h.axis_mins = Some("axis mins: nan 10 20".parse().unwrap());
+        assert_eq!(h.sample_world_coord(&[0,0,0]), Some(vec![10.,21.]));
+        assert_eq!(h.sample_world_coord(&[1,3,2]), Some(vec![11.5,25.]));
+        assert_eq!(h.axis_coordinates(2).unwrap(), vec![21.,23.,25.,27.,29.,31.]);
+    }
+
+    #[test]
+    fn dicom_orientation() {
+        let mut h = NRRD::new_from_dims::<i16>(&[4,4,3]);
+        h.space = Some(Space::RAS);
+        h.space_directions = Some("space directions: (0.5,0,0) (0,0,-0.5) (0,3,0)".parse().unwrap());
+        h.space_origin = Some(SpaceOrigin::new(&[10.,-20.,30.]));
+        h.thicknesses = Some("thicknesses: nan nan 2.5".parse().unwrap());
+
+        // RAS x and y are negated to get LPS
+        let g = h.dicom_orientation().unwrap();
+        assert_eq!(g.row_cosines, [-1.,0.,0.]);
+        assert_eq!(g.column_cosines, [0.,0.,-1.]);
+        assert_eq!(g.slice_normal, [0.,-1.,0.]);
+        assert_eq!(g.pixel_spacing, [0.5,0.5]);
+        assert_eq!(g.slice_spacing, 3.);
+        assert_eq!(g.slice_thickness, Some(2.5));
+        assert_eq!(g.image_position, [-10.,20.,30.]);
+
+        // the same geometry expressed in LPS gives the same result
+        h.space = Some(Space::LPS);
+        h.space_directions = Some("space directions: (-0.5,0,0) (0,0,-0.5) (0,-3,0)".parse().unwrap());
+        h.space_origin = Some(SpaceOrigin::new(&[-10.,20.,30.]));
+        assert_eq!(h.dicom_orientation(), Some(g));
+
+        h.space = Some(Space::scanner_xyz);
+        assert_eq!(h.dicom_orientation(), None);
+    }
+
+    #[test]
+    fn convert_space() {
+        let mut h = NRRD::new_from_dims::<f32>(&[2,4,4,3]);
+        h.space = Some(Space::LPS);
+        h.space_directions = Some("space directions: none (0.5,0.1,0) (0,-0.5,0) (0,0,2)".parse().unwrap());
+        h.space_origin = Some(SpaceOrigin::new(&[10.,-20.,30.]));
+        h.measurement_frame = Some("measurement frame: (1,0,0) (0,1,0) (0,0,1)".parse().unwrap());
+
+        h.convert_space(Space::RAS).unwrap();
+        assert_eq!(h.space, Some(Space::RAS));
+        assert_eq!(h.space_directions.as_ref().unwrap().to_string(), "space directions: none (-0.5,-0.1,0) (0,0.5,0) (0,0,2)");
+        assert_eq!(h.space_origin.as_ref().unwrap().coords(), &[-10.,20.,30.]);
+        assert_eq!(h.measurement_frame.as_ref().unwrap().to_string(), "measurement frame: (-1,0,0) (0,-1,0) (0,0,1)");
+
+        // LAS only differs from RAS in x
+        h.convert_space(Space::LAS).unwrap();
+        assert_eq!(h.space_origin.as_ref().unwrap().coords(), &[10.,20.,30.]);
+
+        // the world position of every voxel is unchanged, only its description
+        let mut back = h.clone();
+        back.convert_space(Space::LPS).unwrap();
+        assert_eq!(back.space_origin.as_ref().unwrap().coords(), &[10.,-20.,30.]);
+
+        assert!(matches!(h.convert_space(Space::LPST), Err(NrrdError::Unsupported(_))));
+        h.space = None;
+        assert!(matches!(h.convert_space(Space::LPS), Err(NrrdError::MissingField("space"))));
+    }
+
+    #[test]
+    fn compressed_reverse_byte_skip() {
+        use flate2::write::GzEncoder;
+        let data:Vec<i32> = (0..24).map(|x| x * 1000 - 7).collect();
+
+        // the decompressed stream starts with junk that byte skip -1 must skip over
+        let mut enc = GzEncoder::new(vec![], flate2::Compression::default());
+        enc.write_all(b"some leading junk").unwrap();
+        enc.write_all(bytemuck::cast_slice(&data)).unwrap();
+        std::fs::write("test_out_rev_skip.raw.gz", enc.finish().unwrap()).unwrap();
+
+        let mut h = NRRD::new_from_dims::<i32>(&[4,6]);
+        h.encoding = Encoding::rawgz;
+        h.byte_skip = Some("byte skip: -1".parse().unwrap());
+        h.data_file = Some(DataFile::SingleFile {filename: PathBuf::from("test_out_rev_skip.raw.gz")});
+        std::fs::write("test_out_rev_skip.nhdr", h.to_string()).unwrap();
+
+        let (read,_) = read_nrrd_to::<i32>("test_out_rev_skip.nhdr");
+        assert_eq!(read, data);
+
+        std::fs::remove_file("test_out_rev_skip.nhdr").unwrap();
+        std::fs::remove_file("test_out_rev_skip.raw.gz").unwrap();
+    }
+
+    #[test]
+    fn line_skip_before_gzip() {
+        use flate2::write::GzEncoder;
+        let data:Vec<u16> = (0..20).map(|x| x * 3).collect();
+
+        // two uncompressed text lines come before the gzip magic. Byte skip applies to the
+        // decompressed stream
+        let mut file = b"scanner export v2\nsession 17\n".to_vec();
+        let mut enc = GzEncoder::new(vec![], flate2::Compression::default());
+        enc.write_all(&[0u8;6]).unwrap();
+        enc.write_all(bytemuck::cast_slice(&data)).unwrap();
+        file.extend(enc.finish().unwrap());
+        std::fs::write("test_out_line_skip.raw.gz", file).unwrap();
+
+        let mut h = NRRD::new_from_dims::<u16>(&[4,5]);
+        h.encoding = Encoding::rawgz;
+        h.line_skip = Some("line skip: 2".parse().unwrap());
+        h.byte_skip = Some("byte skip: 6".parse().unwrap());
+        h.data_file = Some(DataFile::SingleFile {filename: PathBuf::from("test_out_line_skip.raw.gz")});
+        std::fs::write("test_out_line_skip.nhdr", h.to_string()).unwrap();
+
+        let (read,_) = read_nrrd_to::<u16>("test_out_line_skip.nhdr");
+        assert_eq!(read, data);
+        let (_,reader) = NrrdReader::<u16>::open("test_out_line_skip.nhdr").unwrap();
+        assert_eq!(reader.map(|s| s.unwrap()).collect::<Vec<_>>().concat(), data);
+        h.verify_data_file_encodings("test_out_line_skip.nhdr").unwrap();
+
+        std::fs::remove_file("test_out_line_skip.nhdr").unwrap();
+        std::fs::remove_file("test_out_line_skip.raw.gz").unwrap();
+    }
+
+    #[test]
+    fn read_dynamic_keeps_file_type() {
+        let data:Vec<f64> = vec![0.25, -1.5, 1e300, 3.];
+        let h = NRRD::new_from_dims::<f64>(&[4]);
+        write_nrrd("test_out_dynamic_f64", &h, &data, true, Encoding::raw);
+        let (read,h_) = read_nrrd_dynamic("test_out_dynamic_f64.nrrd");
+        assert_eq!(read.dtype(), h_.dtype);
+        assert_eq!(read, NrrdData::F64(data));
+        std::fs::remove_file("test_out_dynamic_f64.nrrd").unwrap();
+
+        let data:Vec<i8> = vec![-128, 0, 127];
+        let h = NRRD::new_from_dims::<i8>(&[3]);
+        write_nrrd("test_out_dynamic_i8", &h, &data, true, Encoding::rawgz);
+        let (read,_) = read_nrrd_dynamic("test_out_dynamic_i8.nrrd");
+        match read {
+            NrrdData::I8(x) => assert_eq!(x, data),
+            other => panic!("expected int8 data, found {:?}", other.dtype()),
+        }
+        std::fs::remove_file("test_out_dynamic_i8.nrrd").unwrap();
+    }
+
+    #[test]
+    fn read_checked_rejects_out_of_range() {
+        let data:Vec<u16> = vec![12, 300, 7];
+        let h = NRRD::new_from_dims::<u16>(&[3]);
+        write_nrrd("test_out_checked", &h, &data, true, Encoding::raw);
+
+        match read_nrrd_to_checked::<u8>("test_out_checked.nrrd") {
+            Err(NrrdError::OutOfRange {index, from, to}) => {
+                assert_eq!(index, 1);
+                assert_eq!(from, DType::uint16);
+                assert_eq!(to, DType::uint8);
+            }
+            other => panic!("expected an out of range error, found {other:?}"),
+        }
+        let (read,_) = read_nrrd_to_checked::<i32>("test_out_checked.nrrd").unwrap();
+        assert_eq!(read, vec![12, 300, 7]);
+        std::fs::remove_file("test_out_checked.nrrd").unwrap();
+    }
+
+    #[test]
+    fn swap_bytes_in_place_per_element() {
+        let data:Vec<u32> = vec![1, 0x01020304, u32::MAX - 5];
+        let mut bytes = bytemuck::cast_slice::<u32,u8>(&data).to_vec();
+        let mut h = NRRD::new_from_dims::<u32>(&[3]);
+
+        let target = match h.endian {
+            Endian::Big => Endian::Little,
+            Endian::Little => Endian::Big,
+        };
+        h.convert_endian(&mut bytes, target);
+        assert_eq!(h.endian, target);
+
+        // the swapped bytes decode to the same values under the updated header
+        write_bytes(&mut h.clone(), "test_out_swap", &bytes, true, false).unwrap();
+        let (decoded,_) = read_nrrd_to::<u32>("test_out_swap.nrrd");
+        assert_eq!(decoded, data);
+        std::fs::remove_file("test_out_swap.nrrd").unwrap();
+
+        // converting to the same order is a no-op
+        let before = bytes.clone();
+        h.convert_endian(&mut bytes, target);
+        assert_eq!(bytes, before);
+
+        h.swap_bytes_in_place(&mut bytes);
+        assert_eq!(bytemuck::cast_slice::<u8,u32>(&bytes), data.as_slice());
+
+        let mut single = vec![1u8, 2, 3];
+        NRRD::new_from_dims::<u8>(&[3]).swap_bytes_in_place(&mut single);
+        assert_eq!(single, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn list_sub_dim_split() {
+        // 2 x 3 x 2 volume stored as one file per row along axis 0, so there is a file for each
+        // (y,z) pair. File k = y + 3z holds the values 100k + x
+        let dir = Path::new("test_out_sub_dim");
+        fs::create_dir_all(dir).unwrap();
+        let rows:Vec<Vec<u16>> = (0..6).map(|k| vec![100 * k, 100 * k + 1]).collect();
+        let files:Vec<PathBuf> = (0..6).map(|i| PathBuf::from(format!("row_{i}.raw"))).collect();
+        for (row,file) in rows.iter().zip(&files) {
+            fs::write(dir.join(file), bytemuck::cast_slice(row)).unwrap();
+        }
+        let value = |x:u16, y:u16, z:u16| 100 * (y + 3 * z) + x;
+        let at = |data:&[u16], x:usize, y:usize, z:usize| data[x + 2 * y + 6 * z];
+
+        let mut h = NRRD::new_from_dims::<u16>(&[2,3,2]);
+        h.data_file = Some(DataFile::List {file_paths: files.clone(), sub_dim: Some(1)});
+        fs::write(dir.join("rows.nhdr"), h.to_string()).unwrap();
+        let (read,_) = read_nrrd_to::<u16>(dir.join("rows.nhdr"));
+        let (_,reader) = NrrdReader::<u16>::open(dir.join("rows.nhdr")).unwrap();
+        let streamed = reader.map(|s| s.unwrap()).collect::<Vec<_>>().concat();
+        assert_eq!(at(&read, 1, 2, 1), value(1,2,1));
+        assert_eq!(at(&read, 0, 1, 0), value(0,1,0));
+        assert!((0..2).all(|x| (0..3).all(|y| (0..2).all(|z| at(&read, x, y, z) == value(x as u16, y as u16, z as u16)))));
+        assert_eq!(streamed, read);
+
+        // the same rows named by a format string
+        h.data_file = Some(DataFile::FileFormat {fmt_string: "row_%d.raw".to_string(), min: 0, max: 5, step: 1, sub_dim: Some(1)});
+        fs::write(dir.join("rows_fmt.nhdr"), h.to_string()).unwrap();
+        let (read_fmt,_) = read_nrrd_to::<u16>(dir.join("rows_fmt.nhdr"));
+        assert_eq!(read_fmt, read);
+
+        // three files would split the 12 elements evenly, but a sub-dim of 1 needs one file per row
+        h.data_file = Some(DataFile::List {file_paths: files[..3].to_vec(), sub_dim: Some(1)});
+        let too_few = h.elements_per_file(3);
+        h.data_file = Some(DataFile::List {file_paths: files[..3].to_vec(), sub_dim: None});
+        assert_eq!(h.elements_per_file(3).unwrap(), 4);
+        // a sub-dim of 2 means one file per z slice
+        h.data_file = Some(DataFile::FileFormat {fmt_string: "row_%d.raw".to_string(), min: 0, max: 5, step: 1, sub_dim: Some(2)});
+        let mismatch = h.elements_per_file(6);
+        fs::remove_dir_all(dir).unwrap();
+
+        assert!(matches!(too_few, Err(NrrdError::SubDimMismatch {sub_dim: 1, expected_files: 6, n_files: 3})));
+        assert!(matches!(mismatch, Err(NrrdError::SubDimMismatch {sub_dim: 2, expected_files: 2, n_files: 6})));
+    }
+
+    #[test]
+    fn crop_keeps_world_positions() {
+        let dims = [4,3,2];
+        let data:Vec<u16> = (0..24).collect();
+        let mut h = NRRD::new_from_dims::<u16>(&dims);
+        h.space = Some(Space::LPS);
+        h.space_directions = Some(SpaceDirections::from_spacing(&[0.5,2.,-1.]));
+        h.space_origin = Some(SpaceOrigin::new(&[10.,-4.,1.]));
+
+        let (cropped,hc) = h.crop(&data, &[1,1,1], &[2,2,1]);
+        assert_eq!(cropped, vec![17, 18, 21, 22]);
+        assert_eq!(hc.shape(), &[2,2,1]);
+        assert_eq!(hc.space_origin.as_ref().unwrap().coords(), &[10.5,-2.,0.]);
+        assert_eq!(hc.sample_world_coord(&[0,0,0]), h.sample_world_coord(&[1,1,1]));
+        assert_eq!(hc.sample_world_coord(&[1,1,0]), h.sample_world_coord(&[2,2,1]));
+
+        let mut h = NRRD::new_from_dims::<u16>(&dims);
+        h.spacings = Some(Spacings::new(&[0.5,f64::NAN,1.]));
+        h.axis_mins = Some(AxisMins::new(&[1.,0.,0.]));
+        h.axis_maxs = Some(AxisMaxs::new(&[2.5,2.,1.]));
+        let (_,hc) = h.crop(&data, &[1,0,1], &[2,3,1]);
+        assert_eq!(hc.axis_mins.unwrap().values(), &[1.5,0.,1.]);
+        assert_eq!(hc.axis_maxs.unwrap().values(), &[2.,2.,1.]);
+    }
+
+    #[test]
+    fn permute_axes_moves_data_and_geometry() {
+        let dims = [2,3,4];
+        let data:Vec<i32> = (0..24).collect();
+        let mut h = NRRD::new_from_dims::<i32>(&dims);
+        h.space = Some(Space::RAS);
+        h.space_directions = Some(SpaceDirections::from_spacing(&[0.5,2.,-1.]));
+        h.space_origin = Some(SpaceOrigin::new(&[10.,-4.,1.]));
+        h.labels = Some(Labels::from_list(&["x","y","z"]));
+
+        let order = [2,0,1];
+        let (permuted,hp) = h.permute_axes(&data, &order);
+        assert_eq!(hp.shape(), &[4,2,3]);
+        assert_eq!(hp.labels.as_ref().unwrap().values(), &["z","x","y"]);
+
+        // new index (k,i,j) holds old index (i,j,k) and sits at the same world position
+        for (i,j,k) in [(0,0,0),(1,2,3),(0,1,2),(1,0,3)] {
+            let old = i + 2 * j + 6 * k;
+            let new = k + 4 * i + 8 * j;
+            assert_eq!(permuted[new], data[old]);
+            assert_eq!(hp.sample_world_coord(&[k,i,j]), h.sample_world_coord(&[i,j,k]));
+        }
+
+        let (back,hb) = hp.permute_axes(&permuted, &[1,2,0]);
+        assert_eq!(back, data);
+        assert_eq!(hb.shape(), &dims);
+    }
+
+    #[test]
+    fn flip_axis_round_trip() {
+        let dims = [2,3,2];
+        let data:Vec<u8> = (0..12).collect();
+        let mut h = NRRD::new_from_dims::<u8>(&dims);
+        h.space = Some(Space::LPS);
+        h.space_directions = Some(SpaceDirections::from_spacing(&[0.5,2.,-1.]));
+        h.space_origin = Some(SpaceOrigin::new(&[10.,-4.,1.]));
+
+        let (flipped,hf) = h.flip_axis(&data, 1);
+        assert_eq!(&flipped[0..6], &[4,5,2,3,0,1]);
+        assert_eq!(hf.space_origin.as_ref().unwrap().coords(), &[10.,0.,1.]);
+        assert_eq!(hf.space_direction(1).unwrap(), &[0.,-2.,0.]);
+        assert_eq!(hf.sample_world_coord(&[1,0,1]), h.sample_world_coord(&[1,2,1]));
+
+        let (back,hb) = hf.flip_axis(&flipped, 1);
+        assert_eq!(back, data);
+        assert_eq!(hb.to_string(), h.to_string());
+    }
+
+    #[test]
+    fn min_max_content_accessors() {
+        let mut h = NRRD::new_from_dims::<f32>(&[2]);
+        h.content = Some(Content::new("t1 map"));
+        h.min = Some(Min::new(-0.5));
+        h.max = Some(Max::new(1200.));
+        h.old_min = Some(OldMin::new(0.));
+        h.old_max = Some(OldMax::new(4095.));
+
+        let h_ = crate::read_header_from_bytes(h.to_string().as_bytes());
+        assert_eq!(h_.content.as_ref().unwrap().text(), "t1 map");
+        assert_eq!(h_.min.unwrap().value(), -0.5);
+        assert_eq!(h_.max.unwrap().value(), 1200.);
+        assert_eq!(h_.old_min.unwrap().value(), 0.);
+        assert_eq!(h_.old_max.unwrap().value(), 4095.);
+    }
+
+    #[test]
+    fn scalar_is_one_axis_of_size_one() {
+        let h = NRRD::new_from_dims::<f64>(&[1]);
+        assert!(h.validate().is_ok());
+        assert_eq!(h.sizes.n_elements(), 1);
+        write_nrrd("test_out_scalar", &h, &[2.5f64], true, Encoding::raw);
+        let (x,_) = read_nrrd_to::<f64>("test_out_scalar.nrrd");
+        std::fs::remove_file("test_out_scalar.nrrd").unwrap();
+        assert_eq!(x, vec![2.5]);
+
+        let header = "NRRD0004\ntype: float\ndimension: 0\nsizes: \nendian: little\nencoding: raw";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+        assert!(h.sizes.shape().is_empty());
+        assert!(matches!(h.validate(), Err(NrrdError::InvalidValue {field: "dimension", ..})));
+    }
+
+    #[test]
+    fn try_new_checks_block_size() {
+        let h = NRRD::try_new(DType::block, &[4,2], Some(12)).unwrap();
+        assert_eq!(h.element_size(), 12);
+        assert_eq!(h.expected_bytes(), 96);
+        let h = NRRD::try_new(DType::int16, &[4,2], None).unwrap();
+        assert_eq!(h.element_size(), 2);
+
+        assert!(matches!(NRRD::try_new(DType::block, &[4,2], None), Err(NrrdError::MissingField("block size"))));
+        assert!(matches!(NRRD::try_new(DType::block, &[4,2], Some(0)), Err(NrrdError::InvalidValue {field: "block size", ..})));
+        assert!(matches!(NRRD::try_new(DType::f32, &[4,2], Some(4)), Err(NrrdError::InvalidValue {field: "block size", ..})));
+        assert!(matches!(NRRD::try_new(DType::f32, &[], None), Err(NrrdError::InvalidValue {field: "dimension", ..})));
+        assert!(matches!(NRRD::try_new(DType::f32, &[4,0], None), Err(NrrdError::InvalidValue {field: "sizes", ..})));
+    }
+
+    #[test]
+    fn crlf_and_tab_separated_header() {
+        let header = [
+            "NRRD0005",
+            "type:float",
+            "dimension:\t3",
+            "sizes:2 3 4",
+            "space:\tleft-posterior-superior",
+            "space directions:\t(0.5,0,0)\t(0,0.5,0)   (0,0,2)",
+            "space origin:(1,2,3)",
+            "kinds:domain\tdomain\tdomain",
+            "centerings:\tcell cell\tcell",
+            "labels:\"x\"\t\"y\" \"z\"",
+            "endian:\t\tlittle",
+            "encoding:raw",
+            "spacings:\tnan\tnan nan",
+            "modality:=MRI",
+            "",
+            "",
+        ].join("\r\n");
+        let data:Vec<f32> = (0..24).map(|x| x as f32).collect();
+        let mut bytes = header.into_bytes();
+        bytes.extend_from_slice(bytemuck::cast_slice(&data));
+
+        let h = crate::read_header_from_bytes(&bytes);
+        h.validate().unwrap();
+        assert_eq!(h.dtype, DType::f32);
+        assert_eq!(h.shape(), &[2,3,4]);
+        assert_eq!(h.space, Some(Space::LPS));
+        assert_eq!(h.space_direction(2).unwrap(), &[0.,0.,2.]);
+        assert_eq!(h.space_origin.as_ref().unwrap().coords(), &[1.,2.,3.]);
+        assert_eq!(h.kinds.as_ref().unwrap().len(), 3);
+        assert_eq!(h.centerings.as_ref().unwrap().values(), &[Centering::Cell;3]);
+        assert_eq!(h.labels.as_ref().unwrap().values(), &["x","y","z"]);
+        assert_eq!(h.endian, Endian::Little);
+        assert_eq!(h.key_vals["modality"].val, "MRI");
+        assert!(h.key_vals.keys().all(|k| !k.contains(':')));
+
+        let (read,_) = read_payload_from_reader(&mut std::io::Cursor::new(&bytes), None).unwrap();
+        assert_eq!(read, bytemuck::cast_slice::<f32,u8>(&data));
+
+        // canonical spacing is written back
+        let written = h.to_string();
+        assert!(written.contains("sizes: 2 3 4"));
+        assert!(written.contains("endian: little"));
+    }
+
+    #[test]
+    fn normalize_header_from_other_tools() {
+        let header = [
+            "NRRD0004",
+            "type: short",
+            "dimension: 3",
+            "space dimension: 3",
+            "space: LPS",
+            "sizes: 4 4 2",
+            "spacings: 0.5 nan 2",
+            "space directions: (0.5,0,0) (0,0.5,0) (0,0,-2)",
+            "kinds: Domain DOMAIN domain",
+            "centerings: Cell cell CELL",
+            "endian: little",
+            "encoding: raw",
+            "content: exported   ",
+            "patient:= anonymous  ",
+        ].join("\n");
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let mut h = NRRD::from_lines_full(&mut lines);
+        assert_eq!(h.kinds.as_ref().unwrap().kinds, vec![crate::header_defs::Kind::domain;3]);
+        assert_eq!(h.centerings.as_ref().unwrap().values(), &[Centering::Cell;3]);
+
+        h.normalize();
+        assert!(h.space_dimension.is_none());
+        assert!(h.spacings.is_none());
+        assert_eq!(h.content.as_ref().unwrap().text(), "exported");
+        assert_eq!(h.key_vals["patient"].val, "anonymous");
+        h.validate().unwrap();
+
+        // spacings that disagree with the directions are kept, and the space dimension is
+        // filled in from the directions when there is no space
+        let mut h = NRRD::new_from_dims::<u8>(&[2,2]);
+        h.space_directions = Some(SpaceDirections::from_spacing(&[1.,1.]));
+        h.spacings = Some(Spacings::new(&[1.,3.]));
+        h.normalize();
+        assert_eq!(h.space_dimension.as_ref().unwrap().dim(), 2);
+        assert!(h.spacings.is_some());
+    }
+
+    #[test]
+    fn write_computes_range() {
+        let data = [3.5f32, f32::NAN, -2., 7.25];
+        let h = NRRD::new_from_dims::<f32>(&[4]);
+        let options = WriteOptions {compute_range: true, ..WriteOptions::new(true, Encoding::raw)};
+        h.write_with_options("test_out_range", &data, &options).unwrap();
+        let h_ = crate::read_header("test_out_range.nrrd");
+        assert_eq!(h_.min.unwrap().value(), -2.);
+        assert_eq!(h_.max.unwrap().value(), 7.25);
+
+        // off by default
+        h.write("test_out_range", &[1u16, 9, 4, 2], true, Encoding::raw).unwrap();
+        let h_ = crate::read_header("test_out_range.nrrd");
+        assert!(h_.min.is_none() && h_.max.is_none());
+
+        h.write_with_options("test_out_range", &[1u16, 9, 4, 2], &options).unwrap();
+        let h_ = crate::read_header("test_out_range.nrrd");
+        assert_eq!((h_.min.unwrap().value(), h_.max.unwrap().value()), (1., 9.));
+        std::fs::remove_file("test_out_range.nrrd").unwrap();
+    }
+
+    #[test]
+    fn histogram_of_ramp_is_flat() {
+        let data:Vec<u16> = (0..100).collect();
+        let h = NRRD::new_from_dims::<u16>(&[10,10]);
+        assert_eq!(h.histogram(&data, 10, None), vec![10;10]);
+        assert_eq!(h.histogram(&data, 4, Some((20.,80.))), vec![35,15,15,35]);
+
+        let data = [0.5f32, f32::NAN, 1.5, -3.];
+        let h = NRRD::new_from_dims::<f32>(&[4]);
+        assert_eq!(h.histogram(&data, 2, Some((0.,2.))), vec![2,1]);
+        assert!(h.histogram(&data, 0, None).is_empty());
+    }
+
+    #[test]
+    fn downsample_by_two() {
+        let data:Vec<u8> = (0..16).collect();
+        let mut h = NRRD::new_from_dims::<u8>(&[4,4]);
+        h.space_directions = Some(SpaceDirections::from_spacing(&[0.5,1.5]));
+        h.space_origin = Some(SpaceOrigin::new(&[0.,0.]));
+
+        let (avg,ha) = h.downsample(&data, &[2,2], DownsampleMode::Average);
+        // blocks {0,1,4,5} {2,3,6,7} {8,9,12,13} {10,11,14,15}, rounded
+        assert_eq!(avg, vec![3, 5, 11, 13]);
+        assert_eq!(ha.shape(), &[2,2]);
+        assert_eq!(ha.space_direction(0).unwrap(), &[1.,0.]);
+        assert_eq!(ha.space_direction(1).unwrap(), &[0.,3.]);
+        assert_eq!(ha.space_origin.as_ref().unwrap().coords(), &[0.25,0.75]);
+
+        let (sub,hs) = h.downsample(&data, &[2,1], DownsampleMode::Subsample);
+        assert_eq!(sub, vec![0,2,4,6,8,10,12,14]);
+        assert_eq!(hs.shape(), &[2,4]);
+        assert_eq!(hs.space_direction(0).unwrap(), &[1.,0.]);
+        assert_eq!(hs.space_origin.as_ref().unwrap().coords(), &[0.,0.]);
+        assert_eq!(hs.sample_world_coord(&[1,3]), h.sample_world_coord(&[2,3]));
+    }
+
+    #[test]
+    fn convert_dtype_rescale_round_trip() {
+        let data = [0f32, 0.25, 0.5, 1., 1.5, -1.];
+        let h = NRRD::new_from_dims::<f32>(&[6]);
+
+        let (q,hq) = h.convert_dtype::<f32,u16>(&data, Some((0.,1.))).unwrap();
+        assert_eq!(q, vec![0, 16384, 32768, 65535, 65535, 0]);
+        assert_eq!(hq.dtype, DType::uint16);
+        assert_eq!((hq.old_min.unwrap().value(), hq.old_max.unwrap().value()), (0., 1.));
+
+        let (x,hx) = hq.dequantize::<u16,f32>(&q).unwrap();
+        assert_eq!(hx.dtype, DType::f32);
+        assert!(hx.old_min.is_none());
+        for (x,expected) in x.iter().zip([0., 0.25, 0.5, 1., 1., 0.]) {
+            assert!((x - expected).abs() < 1e-4, "{x} != {expected}");
+        }
+
+        // a plain cast truncates and rejects values that don't fit
+        let (cast,_) = h.convert_dtype::<f32,i8>(&data, None).unwrap();
+        assert_eq!(cast, vec![0, 0, 0, 1, 1, -1]);
+        assert!(matches!(h.convert_dtype::<f32,u8>(&data, None), Err(NrrdError::OutOfRange {index: 5, ..})));
+        assert!(matches!(h.convert_dtype::<f32,f64>(&data, Some((0.,1.))), Err(NrrdError::Unsupported(_))));
+    }
+
+    #[test]
+    fn quoted_labels_with_escapes() {
+        let labels:Labels = r#"labels: "x" "" "z\"q""#.parse().unwrap();
+        assert_eq!(labels.values(), &["x", "", "z\"q"]);
+        assert_eq!(labels.to_string(), r#"labels: "x" "" "z\"q""#);
+
+        let units:Units = r#"units: "a\\b" """#.parse().unwrap();
+        assert_eq!(units.values(), &["a\\b", ""]);
+        let round_trip:Units = units.to_string().parse().unwrap();
+        assert_eq!(round_trip.values(), units.values());
+    }
+
+    #[test]
+    fn data_file_format_needs_conversion() {
+        let df:DataFile = "data file: img_%03d.raw 0 9 1".parse().unwrap();
+        assert!(matches!(df, DataFile::FileFormat {min: 0, max: 9, step: 1, sub_dim: None, ..}));
+
+        let df:DataFile = "data file: img_%03d.raw 0 9 1 2".parse().unwrap();
+        assert!(matches!(df, DataFile::FileFormat {sub_dim: Some(2), ..}));
+
+        for name in ["my file.raw", "scan 1 2 3.raw", "scan 1 2 3"] {
+            let df:DataFile = format!("data file: {name}").parse().unwrap();
+            assert!(matches!(&df, DataFile::SingleFile {filename} if filename == Path::new(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn data_file_format_signed_range() {
+        let df:DataFile = "data file: slice%d.raw -5 5 1".parse().unwrap();
+        let names:Vec<_> = df.paths().iter().map(|p| p.display().to_string()).collect();
+        assert_eq!(names.len(), 11);
+        assert_eq!((names[0].as_str(), names[5].as_str(), names[10].as_str()), ("slice-5.raw", "slice0.raw", "slice5.raw"));
+
+        let df:DataFile = "data file: img_%03i.raw 9 0 -3".parse().unwrap();
+        assert_eq!(df.paths(), vec![PathBuf::from("img_009.raw"), PathBuf::from("img_006.raw"), PathBuf::from("img_003.raw"), PathBuf::from("img_000.raw")]);
+        assert!(df.check().is_ok());
+
+        for bad in ["data file: img_%s.raw 0 9 1", "data file: img_%d_%d.raw 0 9 1", "data file: img_%03d.raw 0 9 -1", "data file: img_%03d.raw 0 9 0"] {
+            let df:DataFile = bad.parse().unwrap();
+            assert!(matches!(df.check(), Err(NrrdError::InvalidValue {field: "data file", ..})), "{bad}");
+        }
+    }
+
+    #[test]
+    fn detached_keeps_preset_data_file_name() {
+        let data:Vec<u16> = (0..24).collect();
+        let mut h = NRRD::new_from_dims::<u16>(&[2,3,4]);
+        h.data_file = Some(DataFile::SingleFile {filename: PathBuf::from("test_out_preset_data.bin")});
+        write_nrrd("test_out_preset", &h, &data, false, Encoding::rawgz);
+
+        let header = fs::read_to_string("test_out_preset.nhdr").unwrap();
+        assert!(header.lines().any(|l| l == "data file: test_out_preset_data.bin"));
+        assert!(!Path::new("test_out_preset.raw.gz").exists());
+        // the encoding comes from the header, not the extension
+        assert!(fs::read("test_out_preset_data.bin").unwrap().starts_with(&io::GZIP_MAGIC));
+
+        let (read,_) = read_nrrd_to::<u16>("test_out_preset.nhdr");
+        assert_eq!(read, data);
+
+        fs::remove_file("test_out_preset.nhdr").unwrap();
+        fs::remove_file("test_out_preset_data.bin").unwrap();
+    }
+
+    #[test]
+    fn header_equality_and_diff() {
+        let mut a = NRRD::new_from_dims::<u8>(&[2,3,4]);
+        a.spacings = Some(Spacings::new(&[f64::NAN, 1., 1.]));
+        a.set_kv("modality", "mr");
+
+        let mut b = a.clone();
+        assert_eq!(a, b);
+
+        // byte order has no meaning for 1-byte data
+        b.endian = if a.endian == Endian::Big {Endian::Little} else {Endian::Big};
+        assert_eq!(a, b);
+
+        b.spacings = Some(Spacings::new(&[f64::NAN, 2., 1.]));
+        b.set_kv("modality", "ct");
+        b.set_kv("site", "a");
+        let diffs = a.diff(&b);
+        let fields:Vec<&str> = diffs.iter().map(|d| d.field.as_str()).collect();
+        assert_eq!(fields, vec!["spacings", "modality", "site"]);
+        assert_eq!(diffs[0].right.as_deref(), Some("spacings: nan 2 1"));
+        assert_eq!(diffs[2].left, None);
+        assert_ne!(a, b);
+
+        let c = NRRD::new_from_dims::<u16>(&[2,3,4]);
+        let mut d = c.clone();
+        d.endian = if c.endian == Endian::Big {Endian::Little} else {Endian::Big};
+        assert_eq!(c.diff(&d).len(), 1);
+    }
+
+    #[test]
+    fn strip_geometry_leaves_shape() {
+        let mut h = NRRD::new_from_dims::<f32>(&[2,3,4]);
+        assert!(!h.has_geometry());
+
+        h.space = Some(Space::LPS);
+        h.space_directions = Some(SpaceDirections::from_spacing(&[1., 1., 2.]));
+        h.space_origin = Some(SpaceOrigin::new(&[0., 0., 0.]));
+        h.space_units = Some(SpaceUnits::new_mm(3));
+        h.spacings = Some(Spacings::new(&[1., 1., 2.]));
+        h.kinds = Some(Kinds::from_vec(vec![crate::header_defs::Kind::space, crate::header_defs::Kind::domain, crate::header_defs::Kind::time]));
+        assert!(h.has_geometry());
+
+        h.strip_geometry();
+        assert!(!h.has_geometry());
+        assert_eq!(h.shape(), &[2,3,4]);
+        assert_eq!(h.kinds.as_ref().unwrap().to_string(), "kinds: domain domain time");
+        h.validate().unwrap();
+    }
+
+    #[test]
+    fn append_stack_axis() {
+        let mut h = NRRD::new_from_dims::<f32>(&[2,3,4]);
+        h.space_directions = Some(SpaceDirections::from_spacing(&[1., 1., 2.]));
+        h.space_dimension = Some(SpaceDimension::new(3));
+        h.centerings = Some(Centerings::new(Centering::Cell, 3));
+
+        h.append_axis(6, crate::header_defs::Kind::list, Some("gradient")).unwrap();
+        assert_eq!(h.dimension.dim(), 4);
+        assert_eq!(h.shape(), &[2,3,4,6]);
+        assert!(h.space_directions.as_ref().unwrap().vector(3).is_none());
+        assert_eq!(h.kinds.as_ref().unwrap().to_string(), "kinds: none none none list");
+        assert_eq!(h.labels.as_ref().unwrap().values(), &["", "", "", "gradient"]);
+        assert_eq!(h.centerings.as_ref().unwrap().to_string(), "centerings: cell cell cell none");
+        h.validate().unwrap();
+
+        // a header that is already inconsistent is left alone
+        h.spacings = Some(Spacings::new(&[1.]));
+        assert!(h.append_axis(2, crate::header_defs::Kind::list, None).is_err());
+        assert_eq!(h.dimension.dim(), 4);
+    }
+
+    #[test]
+    fn concat_along_middle_axis() {
+        // 2x2x2 and 2x1x2 volumes joined along axis 1 give 2x3x2
+        let ha = NRRD::new_from_dims::<i32>(&[2,2,2]);
+        let hb = NRRD::new_from_dims::<i32>(&[2,1,2]);
+        let a:Vec<i32> = (0..8).collect();
+        let b:Vec<i32> = vec![100,101,102,103];
+        let (joined,h) = concat(&[ha.clone(), hb.clone()], &[&a, &b], 1).unwrap();
+        assert_eq!(h.shape(), &[2,3,2]);
+        assert_eq!(joined, vec![0,1,2,3,100,101, 4,5,6,7,102,103]);
+
+        // joining past the last axis stacks the volumes
+        let (stacked,h) = concat(&[ha.clone(), ha.clone()], &[&a, &a], 3).unwrap();
+        assert_eq!(h.shape(), &[2,2,2,2]);
+        assert_eq!(&stacked[8..], &a[..]);
+
+        // the other axes must match
+        assert!(matches!(concat(&[ha, hb], &[&a, &b], 0), Err(NrrdError::GridMismatch)));
+    }
+
+    #[test]
+    fn squeeze_stub_axes() {
+        use crate::header_defs::Kind;
+        let mut h = NRRD::new_from_dims::<u8>(&[1,128,128,1]);
+        // a single slice with a leading stub axis
+        h.kinds = Some(Kinds::from_vec(vec![Kind::stub, Kind::domain, Kind::domain, Kind::domain]));
+        h.space_dimension = Some(SpaceDimension::new(3));
+        let mut sd = SpaceDirections::new();
+        sd.extend_none();
+        sd.extend_from_spacing(&[0.5, 0.5, 2.]);
+        h.space_directions = Some(sd);
+        h.validate().unwrap();
+        let data = vec![7u8; 128 * 128];
+
+        let (squeezed,hs) = h.squeeze(&data);
+        assert_eq!(squeezed, data);
+        assert_eq!(hs.shape(), &[128,128]);
+        assert_eq!(hs.dimension.dim(), 2);
+        assert_eq!(hs.kinds.as_ref().unwrap().to_string(), "kinds: domain domain");
+        assert_eq!(hs.space_directions.as_ref().unwrap().len(), 2);
+        hs.validate().unwrap();
+
+        let (_,h1) = h.squeeze_axis(&data, 0);
+        assert_eq!(h1.shape(), &[128,128,1]);
+    }
+
+    #[test]
+    fn voxel_spacing_from_either_field() {
+        let mut h = NRRD::new_from_dims::<f32>(&[2,3,4,5]);
+        assert!(h.voxel_spacing().is_none());
+
+        h.spacings = Some(Spacings::new(&[0.5, 0.5, 2., f64::NAN]));
+        let spacing = h.voxel_spacing().unwrap();
+        assert_eq!(&spacing[..3], &[0.5, 0.5, 2.]);
+        assert!(spacing[3].is_nan());
+
+        // an oblique direction of length 5 and a non-spatial last axis
+        let mut sd = SpaceDirections::new();
+        sd.extend_from_spacing(&[0.5, 0.5]);
+        sd.directions.push(Some(crate::header_defs::NrrdVec::new(&[0., 3., 4.])));
+        sd.extend_none();
+        h.space_directions = Some(sd);
+        h.spacings = None;
+        let spacing = h.voxel_spacing().unwrap();
+        assert_eq!(&spacing[..3], &[0.5, 0.5, 5.]);
+        assert!(spacing[3].is_nan());
+
+        // with both fields the directions win, even for the spacing of the non-spatial axis
+        h.spacings = Some(Spacings::new(&[0.5, 0.5, 5., 1.]));
+        let both = h.voxel_spacing().unwrap();
+        assert_eq!(&both[..3], &spacing[..3]);
+        assert!(both[3].is_nan());
+    }
+
+    #[test]
+    fn write_keeps_comment_between_fields() {
+        let header = "NRRD0004\ntype: uint8\n# stored unsigned to save space\nencoding: raw\ndimension: 1\nsizes: 4\nendian: little\nsite:=lab 2";
+        let mut lines = header.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut lines);
+
+        let options = WriteOptions {preserve_order: true, ..WriteOptions::default()};
+        h.write_with_options("test_out_comment_order", &[1u8, 2, 3, 4], &options).unwrap();
+
+        let (data,h2) = read_nrrd_to::<u8>("test_out_comment_order.nrrd");
+        assert_eq!(data, vec![1, 2, 3, 4]);
+        let written:Vec<&str> = h2.source_lines.iter().map(|l| l.as_str()).collect();
+        let type_line = written.iter().position(|l| l.starts_with("type:")).unwrap();
+        assert_eq!(&written[type_line..type_line + 3], &["type: uint8", "# stored unsigned to save space", "encoding: raw"]);
+        assert_eq!(written.last(), Some(&"site:=lab 2"));
+
+        fs::remove_file("test_out_comment_order.nrrd").unwrap();
+    }
+
+    #[test]
+    fn field_aliases() {
+        type Check = fn(&NRRD) -> bool;
+        let cases:Vec<(&str, Check)> = vec![
+            ("min: 1", |h| h.min.is_some() && h.old_min.is_none()),
+            ("old min: 1", |h| h.old_min.is_some() && h.min.is_none()),
+            ("oldmin: 1", |h| h.old_min.is_some() && h.min.is_none()),
+            ("max: 1", |h| h.max.is_some() && h.old_max.is_none()),
+            ("old max: 1", |h| h.old_max.is_some() && h.max.is_none()),
+            ("oldmax: 1", |h| h.old_max.is_some() && h.max.is_none()),
+            ("centerings: cell cell", |h| h.centerings.is_some()),
+            ("centers: cell cell", |h| h.centerings.is_some()),
+            ("axis mins: 0 0", |h| h.axis_mins.is_some()),
+            ("axismins: 0 0", |h| h.axis_mins.is_some()),
+            ("axis maxs: 1 1", |h| h.axis_maxs.is_some()),
+            ("axismaxs: 1 1", |h| h.axis_maxs.is_some()),
+            ("line skip: 1", |h| h.line_skip.is_some()),
+            ("lineskip: 1", |h| h.line_skip.is_some()),
+            ("byte skip: 1", |h| h.byte_skip.is_some()),
+            ("byteskip: 1", |h| h.byte_skip.is_some()),
+            ("sample units: mm", |h| h.sample_units.is_some()),
+            ("sampleunits: mm", |h| h.sample_units.is_some()),
+            ("space dimension: 2", |h| h.space_dimension.is_some()),
+            ("spacedimension: 2", |h| h.space_dimension.is_some()),
+            ("space units: \"mm\" \"mm\"", |h| h.space_units.is_some()),
+            ("spaceunits: \"mm\" \"mm\"", |h| h.space_units.is_some()),
+            ("space origin: (0,0)", |h| h.space_origin.is_some()),
+            ("spaceorigin: (0,0)", |h| h.space_origin.is_some()),
+            ("space directions: (1,0) (0,1)", |h| h.space_directions.is_some()),
+            ("spacedirections: (1,0) (0,1)", |h| h.space_directions.is_some()),
+            ("measurement frame: (1,0) (0,1)", |h| h.measurement_frame.is_some()),
+            ("measurementframe: (1,0) (0,1)", |h| h.measurement_frame.is_some()),
+            ("data file: a.raw", |h| h.data_file.is_some()),
+            ("datafile: a.raw", |h| h.data_file.is_some()),
+        ];
+        for (line,check) in cases {
+            let header = format!("NRRD0004\ntype: float\ndimension: 2\nsizes: 2 2\nendian: little\nencoding: raw\n{line}");
+            let mut lines = header.lines().collect::<Vec<&str>>();
+            let h = NRRD::from_lines_strict(&mut lines).unwrap_or_else(|e| panic!("'{line}': {e}"));
+            assert!(check(&h), "'{line}' wasn't parsed into its field");
+        }
+
+        let mut lines = vec!["NRRD0004", "type: block", "dimension: 1", "sizes: 2", "endian: little", "encoding: raw", "blocksize: 4"];
+        assert_eq!(NRRD::from_lines_full(&mut lines).block_size.unwrap().size(), 4);
+    }
+
+    #[test]
+    fn read_into_reused_buffer() {
+        let h = NRRD::new_from_dims::<u16>(&[3,4]);
+        let data:Vec<u16> = (0..12).collect();
+        h.write_with_options("test_out_buf_le", &data, &WriteOptions {endian: Endian::Little, ..WriteOptions::default()}).unwrap();
+        h.write_with_options("test_out_buf_be", &data, &WriteOptions {endian: Endian::Big, ..WriteOptions::default()}).unwrap();
+
+        let mut buf:Vec<u16> = Vec::with_capacity(64);
+        let ptr = buf.as_ptr();
+        for file in ["test_out_buf_le.nrrd", "test_out_buf_be.nrrd"] {
+            let hb = read_nrrd_to_buf(file, &mut buf).unwrap();
+            assert_eq!(buf, data, "{file}");
+            assert_eq!(hb.shape(), &[3,4]);
+        }
+        // the buffer was big enough, so it was never reallocated
+        assert_eq!(buf.as_ptr(), ptr);
+
+        // other types are converted
+        let mut wide:Vec<f64> = vec![];
+        read_nrrd_to_buf("test_out_buf_be.nrrd", &mut wide).unwrap();
+        assert_eq!(wide[11], 11.);
+
+        fs::remove_file("test_out_buf_le.nrrd").unwrap();
+        fs::remove_file("test_out_buf_be.nrrd").unwrap();
+    }
+
+    #[test]
+    fn read_native_type() {
+        let h = NRRD::new_from_dims::<f32>(&[5,2]);
+        let data:Vec<f32> = (0..10).map(|x| x as f32 * 0.5).collect();
+        h.write_with_options("test_out_native_be", &data, &WriteOptions {endian: Endian::Big, ..WriteOptions::default()}).unwrap();
+
+        let (x,_) = read_nrrd_native::<f32>("test_out_native_be.nrrd").unwrap();
+        assert_eq!(x, data);
+        // the generic path agrees with the native one
+        assert_eq!(read_nrrd_to::<f32>("test_out_native_be.nrrd").0, data);
+
+        assert!(matches!(
+            read_nrrd_native::<f64>("test_out_native_be.nrrd"),
+            Err(NrrdError::TypeMismatch {..})
+        ));
+
+        fs::remove_file("test_out_native_be.nrrd").unwrap();
+    }
+
+    #[test]
+    fn truncated_compressed_data() {
+        // values that don't compress to almost nothing, so the cut lands inside the stream
+        let data:Vec<u32> = (0..4096u32).map(|x| x.wrapping_mul(2654435761)).collect();
+        let h = NRRD::new_from_dims::<u32>(&[4096]);
+        for (name,encoding) in [("test_out_trunc_gz",Encoding::rawgz),("test_out_trunc_bz",Encoding::rawbz2)] {
+            h.write(name, &data, false, encoding).unwrap();
+            let data_file = format!("{name}.{}", encoding.file_ext());
+            let bytes = fs::read(&data_file).unwrap();
+            fs::write(&data_file, &bytes[..bytes.len() / 2]).unwrap();
+
+            match crate::read_payload_cancellable(format!("{name}.nhdr"), None) {
+                Err(NrrdError::Io(e)) => {
+                    assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+                    assert!(e.to_string().contains(&data_file), "{e}");
+                    assert!(e.to_string().contains("of 16384 bytes"), "{e}");
+                }
+                other => panic!("expected a short read error, got {other:?}"),
+            }
+            fs::remove_file(format!("{name}.nhdr")).unwrap();
+            fs::remove_file(data_file).unwrap();
+        }
+    }
+
+    #[test]
+    fn detached_ascii_two_files() {
+        fs::write("test_out_ascii_0.txt", "1 -2 3\n4 5 6.5\n").unwrap();
+        fs::write("test_out_ascii_1.txt", "  7\t8e1 -9\n10 11 12\n").unwrap();
+        fs::write("test_out_ascii.nhdr", "NRRD0004\ntype: float\ndimension: 3\nsizes: 3 2 2\n\
+            encoding: ascii\ndata file: test_out_ascii_%d.txt 0 1 1\n").unwrap();
+        // text needs no endian field
+        assert!(crate::read_header_checked("test_out_ascii.nhdr").is_ok());
+        let (x,h) = read_nrrd_to::<f32>("test_out_ascii.nhdr");
+        assert_eq!(h.encoding, Encoding::txt);
+        assert_eq!(x, vec![1.,-2.,3.,4.,5.,6.5,7.,80.,-9.,10.,11.,12.]);
+
+        // a file with fewer values than its share is a short read
+        fs::write("test_out_ascii_1.txt", "7 8 9\n").unwrap();
+        assert!(matches!(
+            crate::read_payload_cancellable("test_out_ascii.nhdr", None),
+            Err(NrrdError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+        fs::write("test_out_ascii_1.txt", "7 8 nine 10 11 12\n").unwrap();
+        assert!(matches!(
+            crate::read_payload_cancellable("test_out_ascii.nhdr", None),
+            Err(NrrdError::InvalidValue {field: "data", value}) if value == "nine"
+        ));
+
+        // text can be streamed slice by slice, each file decoded as a whole
+        fs::write("test_out_ascii_1.txt", "7 8 9\n10 11 12\n").unwrap();
+        let (_,reader) = NrrdReader::<f32>::open("test_out_ascii.nhdr").unwrap();
+        let slices:Vec<Vec<f32>> = reader.map(|s| s.unwrap()).collect();
+        assert_eq!(slices, vec![vec![1.,-2.,3.,4.,5.,6.5], vec![7.,8.,9.,10.,11.,12.]]);
+
+        // hex data follows the header byte order
+        fs::write("test_out_ascii.nrrd", "NRRD0004\ntype: ushort\ndimension: 1\nsizes: 3\n\
+            endian: big\nencoding: hex\n\n0001 00ff\n1234\n").unwrap();
+        assert_eq!(read_nrrd_to::<u16>("test_out_ascii.nrrd").0, vec![1,255,0x1234]);
+
+        for f in ["test_out_ascii.nhdr","test_out_ascii_0.txt","test_out_ascii_1.txt","test_out_ascii.nrrd"] {
+            fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn volume_open_index_save() {
+        let dims = [4,3,2];
+        let data:Vec<i16> = (0..24).collect();
+        let vol = Volume::new(NRRD::new_from_dims::<i16>(&dims), data).unwrap();
+        vol.save("test_out_volume", true, Encoding::rawgz).unwrap();
+
+        let vol = Volume::<i32>::open("test_out_volume.nrrd").unwrap();
+        fs::remove_file("test_out_volume.nrrd").unwrap();
+        assert_eq!(vol.shape(), &dims);
+        // the first axis varies fastest
+        assert_eq!(vol.get(&[1,0,0]), Some(&1));
+        assert_eq!(vol.get(&[0,1,0]), Some(&4));
+        assert_eq!(vol.get(&[3,2,1]), Some(&23));
+        assert_eq!(vol.get(&[4,0,0]), None);
+        assert_eq!(vol.get(&[0,0]), None);
+        assert_eq!(vol.as_slice().len(), 24);
+
+        assert!(matches!(
+            Volume::new(NRRD::new_from_dims::<u8>(&dims), vec![0u8;5]),
+            Err(NrrdError::DataLength {expected: 24, found: 5})
+        ));
+    }
+
+    #[test]
+    fn volume_indexed_access() {
+        let dims = [2,3,4];
+        let mut vol = Volume::new(NRRD::new_from_dims::<f32>(&dims), vec![0f32;24]).unwrap();
+        assert_eq!(vol.set(&[1,2,3], 7.5), Some(0.));
+        *vol.get_mut(&[0,1,0]).unwrap() = -1.;
+        vol.save("test_out_volume_idx", false, Encoding::raw).unwrap();
+
+        let vol = Volume::<f32>::open("test_out_volume_idx.nhdr").unwrap();
+        fs::remove_file("test_out_volume_idx.nhdr").unwrap();
+        fs::remove_file("test_out_volume_idx.raw").unwrap();
+        assert_eq!(vol.get(&[1,2,3]), Some(&7.5));
+        // [1,2,3] is the last element and [0,1,0] is one row in
+        assert_eq!(vol.as_slice()[23], 7.5);
+        assert_eq!(vol.as_slice()[2], -1.);
+
+        let mut vol = vol;
+        for idx in [[2,0,0],[0,3,0],[0,0,4],[9,9,9]] {
+            assert_eq!(vol.get(&idx), None);
+            assert_eq!(vol.get_mut(&idx), None);
+            assert_eq!(vol.set(&idx, 1.), None);
+        }
+        assert_eq!(vol.get(&[0,0,0,0]), None);
+
+        let indexed:Vec<_> = vol.iter_indexed().collect();
+        assert_eq!(indexed.len(), 24);
+        assert_eq!(indexed[1], (vec![1,0,0], &0.));
+        assert_eq!(indexed[2], (vec![0,1,0], &-1.));
+        assert_eq!(indexed[23], (vec![1,2,3], &7.5));
+        assert!(indexed.iter().all(|(idx,x)| vol.get(idx) == Some(x)));
+    }
+
+    #[test]
+    fn add_comments_and_key_values() {
+        let mut h = NRRD::new_from_dims::<u8>(&[2,2]);
+        h.add_comment("first").unwrap();
+        h.add_comment("second").unwrap();
+        h.set_key_value("zeta", "last").unwrap();
+        h.set_key_value("alpha", "1 2 3").unwrap();
+
+        let out = h.to_string();
+        let lines:Vec<&str> = out.lines().collect();
+        let pos = |line:&str| lines.iter().position(|l| *l == line).unwrap();
+        assert_eq!(pos("# first") + 1, pos("# second"));
+        assert!(pos("alpha:=1 2 3") < pos("zeta:=last"));
+        assert!(pos("# second") < pos("alpha:=1 2 3"));
+
+        // the pairs and comments read back as written
+        let mut lines = lines;
+        let parsed = NRRD::from_lines_full(&mut lines);
+        assert_eq!(parsed.comments, h.comments);
+        assert_eq!(parsed.key_vals["alpha"].val, "1 2 3");
+
+        assert!(h.add_comment("two\nlines").is_err());
+        assert!(h.set_key_value("a:=b", "c").is_err());
+        assert!(h.set_key_value("", "c").is_err());
+        assert!(h.set_key_value("key", "line\nbreak").is_err());
+        assert_eq!(h.comments.len(), 2);
+        assert_eq!(h.key_vals.len(), 2);
+
+        // fields set directly are checked when validating
+        h.comments.push("# bad\ncomment".to_string());
+        assert!(matches!(h.validate(), Err(NrrdError::InvalidValue {field: "comment", ..})));
+
+        let built = NrrdBuilder::new(DType::uint8, &[2,2]).comment("first").key_value("alpha", "1").build().unwrap();
+        assert_eq!(built.comments, vec!["# first"]);
+        assert_eq!(built.key_vals["alpha"].val, "1");
+        assert!(NrrdBuilder::new(DType::uint8, &[2,2]).key_value("a:=b", "1").build().is_err());
+    }
+
+    #[test]
+    fn header_cut_inside_field_value() {
+        let full = "NRRD0004\ntype: float\ndimension: 3\nsizes: 2 2 2\nendian: little\nencoding: raw\n\
+            space: right-anterior-superior\nkinds: domain domain domain\nspacings: 1 1 0.5\n\
+            space directions: (1,0,0) (0,1,0) (0,0,1)\n\n";
+        // cut inside a vector, a list of numbers and a list of names
+        for cut in ["space directions: (1,0,0) (0,1", "spacings: 1 1 0.", "kinds: domain dom"] {
+            let end = full.find(cut).unwrap() + cut.len();
+            fs::write("test_out_cut_field.nrrd", &full[..end]).unwrap();
+            match crate::read_header_checked("test_out_cut_field.nrrd") {
+                Err(NrrdError::TruncatedHeader {partial_line}) => assert_eq!(partial_line, cut),
+                r => panic!("expected a truncated header for '{cut}', got {r:?}"),
+            }
+        }
 
-#[cfg(test)]
-mod tests {
-    use std::fs;
-    use std::fs::File;
-    use crate::header_defs::Encoding;
-    use super::*;
+        // a malformed value in a complete header is an error, not a panic
+        fs::write("test_out_cut_field.nrrd", full.replace("(0,1,0)", "(0,1")).unwrap();
+        assert!(matches!(
+            crate::read_header_checked("test_out_cut_field.nrrd"),
+            Err(NrrdError::InvalidValue {field: "header", ..})
+        ));
+        fs::remove_file("test_out_cut_field.nrrd").unwrap();
+    }
 
     #[test]
-    pub fn read_header() {
-        let test_header = "test_nrrds/detached_list.nhdr";
-        let mut f = File::open(test_header).unwrap();
-        let (header_bytes,..) = io::read_until_blank(&mut f).expect("failed to read header");
-        let header_str = String::from_utf8(header_bytes).expect("failed to convert bytes to string");
-        let mut header_lines = header_str.lines().collect::<Vec<&str>>();
-        let _ = NRRD::from_lines_full(&mut header_lines);
-        // this means we accounted for every line in the string
-        assert!(header_lines.is_empty());
+    fn flip_is_a_reorientation_for_any_centering() {
+        for centering in [Centering::Node, Centering::Cell] {
+            let g = NrrdBuilder::new(DType::uint8, &[4,3,2])
+                .space(Space::RAS)
+                .space_directions_from_spacing(&[1.,2.,3.])
+                .space_origin(&[10.,20.,30.])
+                .centering(centering)
+                .build().unwrap();
+            let data = vec![0u8;24];
+            let (_,flipped) = g.flip_axis(&data, 0);
+            assert_eq!(g.resampling_needed(&flipped, 1e-6), ResamplingPlan::ReorientOnly {order: vec![0,1,2], flip: vec![0]}, "{centering:?}");
+            assert_eq!(flipped.resampling_needed(&g, 1e-6), ResamplingPlan::ReorientOnly {order: vec![0,1,2], flip: vec![0]}, "{centering:?}");
+
+            // the flipped grid samples the same world positions
+            let first = flipped.sample_world_coord(&[0,0,0]).unwrap();
+            assert_eq!(first, g.sample_world_coord(&[3,0,0]).unwrap(), "{centering:?}");
+        }
+
+        // the origin is the first sample for either centering, so they sample the same positions
+        let node = NrrdBuilder::new(DType::uint8, &[4,3,2]).space(Space::RAS)
+            .space_directions_from_spacing(&[1.,1.,1.]).space_origin(&[0.,0.,0.]).centering(Centering::Node).build().unwrap();
+        let mut cell = node.clone();
+        cell.set_uniform_centering(Centering::Cell);
+        assert_eq!(node.resampling_needed(&cell, 1e-6), ResamplingPlan::None);
+        let (_,flipped) = cell.flip_axis(&[0u8;24], 0);
+        assert_eq!(flipped.space_origin.unwrap().coords(), &[3.,0.,0.]);
     }
 
     #[test]
-    pub fn resolve_detached() {
+    fn downsample_cell_centered_origin() {
+        let mut h = NrrdBuilder::new(DType::f32, &[8,4])
+            .space_directions_from_spacing(&[1.,2.])
+            .space_origin(&[10.,20.])
+            .centering(Centering::Cell)
+            .build().unwrap();
+        h.spacings = Some(Spacings::new(&[1.,2.]));
+        h.axis_mins = Some(AxisMins::new(&[9.5,19.]));
+
+        // each new sample sits at the center of the block of old samples it averages
+        let avg = h.downsampled_header(&[4,2], DownsampleMode::Average);
+        let block_center = |x:usize, y:usize| {
+            let a = h.sample_world_coord(&[4 * x, 2 * y]).unwrap();
+            let b = h.sample_world_coord(&[4 * x + 3, 2 * y + 1]).unwrap();
+            vec![(a[0] + b[0]) / 2., (a[1] + b[1]) / 2.]
+        };
+        assert_eq!(avg.sample_world_coord(&[0,0]).unwrap(), block_center(0,0));
+        assert_eq!(avg.sample_world_coord(&[1,1]).unwrap(), block_center(1,1));
+        // the origin is the first new sample, while the outer edge of the cells doesn't move
+        assert_eq!(avg.space_origin.as_ref().unwrap().coords(), &[11.5,21.]);
+        assert_eq!(avg.axis_mins.as_ref().unwrap().values(), &[9.5,19.]);
+
+        // subsampled samples keep the position of the sample they were taken from, and the axis
+        // mins stay half a new step before them
+        let sub = h.downsampled_header(&[4,2], DownsampleMode::Subsample);
+        assert_eq!(sub.sample_world_coord(&[1,1]).unwrap(), h.sample_world_coord(&[4,2]).unwrap());
+        assert_eq!(sub.space_origin.as_ref().unwrap().coords(), &[10.,20.]);
+        assert_eq!(sub.axis_mins.as_ref().unwrap().values(), &[8.,18.]);
+    }
 
-        let test_header = "test_nrrds/detached_multi.nhdr";
-        let mut f = File::open(test_header).unwrap();
-        let (header_bytes,..) = io::read_until_blank(&mut f).expect("failed to read header");
-        let header_str = String::from_utf8(header_bytes).expect("failed to convert bytes to string");
-        let mut header_lines = header_str.lines().collect::<Vec<&str>>();
-        let h = NRRD::from_lines_full(&mut header_lines);
+    #[test]
+    fn convert_dtype_rescale_64_bit() {
+        let h = NRRD::new_from_dims::<f32>(&[3]);
+        let data = [0f32, 0.5, 1.];
+        let (x,_) = h.convert_dtype::<f32,u64>(&data, Some((0.,1.))).unwrap();
+        assert_eq!(x[0], 0);
+        assert_eq!(x[2], u64::MAX);
+        let (x,_) = h.convert_dtype::<f32,i64>(&data, Some((0.,1.))).unwrap();
+        assert_eq!(x[0], i64::MIN);
+        assert_eq!(x[2], i64::MAX);
+        // values past the top of the range clamp to the largest value too
+        let (x,_) = h.convert_dtype::<f32,u64>(&[0.,2.,5.], Some((0.,1.))).unwrap();
+        assert_eq!(x[1..], [u64::MAX, u64::MAX]);
+    }
 
-        assert!(header_lines.is_empty());
+    #[test]
+    fn multi_file_read() {
+        // more files than most machines have cores, so that threads read several files each when
+        // built with the 'rayon' feature
+        let n_files = 37;
+        let h = NRRD::new_from_dims::<i32>(&[5,4,n_files]);
+        let data:Vec<i32> = (0..h.sizes.n_elements() as i32).map(|x| x * 7 - 300).collect();
+        let mut hw = h.clone();
+        hw.data_file = Some(DataFile::FileFormat {
+            fmt_string: "test_out_multi_%02d.raw.gz".to_string(),
+            min: 0,
+            max: n_files as i32 - 1,
+            step: 1,
+            sub_dim: None,
+        });
+        for (i,chunk) in data.chunks_exact(20).enumerate() {
+            let mut f = File::create(format!("test_out_multi_{i:02}.raw.gz")).unwrap();
+            io::write_gzip(&mut f, bytemuck::cast_slice(chunk));
+        }
+        hw.endian = Endian::native();
+        hw.encoding = Encoding::rawgz;
+        fs::write("test_out_multi.nhdr", hw.to_string()).unwrap();
+
+        let (x,_) = read_nrrd_to::<i32>("test_out_multi.nhdr");
+        assert_eq!(x, data);
+
+        // a bad file in the middle fails the whole read with its name
+        fs::write("test_out_multi_20.raw.gz", b"").unwrap();
+        match crate::read_payload_cancellable("test_out_multi.nhdr", None) {
+            Err(NrrdError::Io(e)) => assert!(e.to_string().contains("test_out_multi_20.raw.gz"), "{e}"),
+            r => panic!("expected a short read error, got {r:?}"),
+        }
 
-        println!("{h}");
-        let paths = h.data_file.as_ref().unwrap().paths();
-        println!("{paths:?}");
+        fs::remove_file("test_out_multi.nhdr").unwrap();
+        for i in 0..n_files {
+            fs::remove_file(format!("test_out_multi_{i:02}.raw.gz")).unwrap();
+        }
     }
 
     #[test]
@@ -81,7 +2449,7 @@ mod tests {
 
         for encoding in encodings {
             write_nrrd("test_out", &nrrd, &data, attached, encoding);
-            let (data_,nrrd) = read_nrrd_to::<i8>("test_out.nhdr");
+            let (data_,..) = read_nrrd_to::<i8>("test_out.nhdr");
             let data_ = data_.into_iter().map(|x| x as f64).collect::<Vec<f64>>();
             assert_eq!(data_,data);
 
@@ -96,24 +2464,241 @@ mod tests {
     }
 }
 
+/// reads the nrrd and converts the data to T. Floating point values are truncated toward zero when
+/// T is an integer type, and this panics if any value doesn't fit in T. See 'read_nrrd_to_checked'
+/// for a version that returns an error instead, or 'read_nrrd_dynamic' to avoid converting at all
 pub fn read_nrrd_to<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> (Vec<T>, NRRD) {
 
     // read bytes and header from nrrd
     let (bytes,h) = read_payload(filepath);
+    let x = decode_payload(bytes, &h);
+    (x,h)
+}
+
+/// reads the nrrd and converts the data to T, failing with 'NrrdError::OutOfRange' on the first value
+/// that doesn't fit in T. Floating point values are still truncated toward zero for integer types
+pub fn read_nrrd_to_checked<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> Result<(Vec<T>, NRRD),NrrdError> {
+    let (bytes,h) = read_payload_cancellable(filepath, None)?;
+    let x = try_decode_payload(bytes, &h)?;
+    Ok((x,h))
+}
+
+/// reads the nrrd into 'out', resizing it to the number of elements, so that one buffer can be
+/// reused across many files. When the file stores T, the data is read straight into the buffer
+/// without an intermediate byte vector. Other types are converted as in 'read_nrrd_to_checked'
+pub fn read_nrrd_to_buf<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>, out:&mut Vec<T>) -> Result<NRRD,NrrdError> {
+
+    let filepath = filepath.as_ref();
+    let base_dir = filepath.parent().unwrap();
+    let mut f = File::open(filepath)?;
+    let h = read_header_from(&mut f)?;
+
+    if h.dtype.is_block() {
+        return Err(NrrdError::TypeMismatch {expected: h.dtype, found: T::dtype()});
+    }
+
+    if h.dtype != T::dtype() {
+        let mut bytes = vec![0u8;h.expected_bytes()];
+        read_data_into(&mut f, &h, base_dir, filepath, None, &mut bytes)?;
+        let mut converted = try_decode_payload(bytes, &h)?;
+        out.clear();
+        out.append(&mut converted);
+        return Ok(h);
+    }
+
+    read_native_into(&mut f, &h, base_dir, filepath, out)?;
+    Ok(h)
+}
+
+/// reads the nrrd as the type stored in the file, failing with 'NrrdError::TypeMismatch' if that
+/// isn't T. The data is read straight into the returned vector and only byte-swapped if the file
+/// endianness isn't native, skipping the per-value conversion of 'read_nrrd_to'
+pub fn read_nrrd_native<T:NRRDType>(filepath:impl AsRef<Path>) -> Result<(Vec<T>, NRRD),NrrdError> {
+    let filepath = filepath.as_ref();
+    let mut f = File::open(filepath)?;
+    let h = read_header_from(&mut f)?;
+    if h.dtype != T::dtype() {
+        return Err(NrrdError::TypeMismatch {expected: h.dtype, found: T::dtype()});
+    }
+    let mut x = vec![];
+    read_native_into(&mut f, &h, filepath.parent().unwrap(), filepath, &mut x)?;
+    Ok((x,h))
+}
+
+/// reads the data into 'out' as its stored type, resizing it to the number of elements
+fn read_native_into<T:NRRDType, R:Read + Seek>(f:&mut R, h:&NRRD, base_dir:&Path, source:&Path, out:&mut Vec<T>) -> Result<(),NrrdError> {
+    out.clear();
+    out.resize(h.sizes.n_elements(), bytemuck::Zeroable::zeroed());
+    let bytes:&mut [u8] = bytemuck::cast_slice_mut(out.as_mut_slice());
+    read_data_into(f, h, base_dir, source, None, bytes)?;
+    if h.endian != Endian::native() {
+        h.swap_bytes_in_place(bytes);
+    }
+    Ok(())
+}
+
+/// data decoded as the type stored in the file, with no conversion
+#[derive(Debug,Clone,PartialEq)]
+pub enum NrrdData {
+    I8(Vec<i8>),
+    U8(Vec<u8>),
+    I16(Vec<i16>),
+    U16(Vec<u16>),
+    I32(Vec<i32>),
+    U32(Vec<u32>),
+    I64(Vec<i64>),
+    U64(Vec<u64>),
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+    #[cfg(feature = "f16")]
+    F16(Vec<half::Half>),
+    /// raw bytes of every block, back to back
+    Block(Vec<u8>),
+}
+
+impl NrrdData {
+    /// returns the data type of the values
+    pub fn dtype(&self) -> DType {
+        match self {
+            NrrdData::I8(_) => DType::int8,
+            NrrdData::U8(_) => DType::uint8,
+            NrrdData::I16(_) => DType::int16,
+            NrrdData::U16(_) => DType::uint16,
+            NrrdData::I32(_) => DType::int32,
+            NrrdData::U32(_) => DType::uint32,
+            NrrdData::I64(_) => DType::int64,
+            NrrdData::U64(_) => DType::uint64,
+            NrrdData::F32(_) => DType::f32,
+            NrrdData::F64(_) => DType::f64,
+            #[cfg(feature = "f16")]
+            NrrdData::F16(_) => DType::f16,
+            NrrdData::Block(_) => DType::block,
+        }
+    }
+
+    /// returns the number of values, or the number of bytes for block data
+    pub fn len(&self) -> usize {
+        match self {
+            NrrdData::I8(x) => x.len(),
+            NrrdData::U8(x) => x.len(),
+            NrrdData::I16(x) => x.len(),
+            NrrdData::U16(x) => x.len(),
+            NrrdData::I32(x) => x.len(),
+            NrrdData::U32(x) => x.len(),
+            NrrdData::I64(x) => x.len(),
+            NrrdData::U64(x) => x.len(),
+            NrrdData::F32(x) => x.len(),
+            NrrdData::F64(x) => x.len(),
+            #[cfg(feature = "f16")]
+            NrrdData::F16(x) => x.len(),
+            NrrdData::Block(x) => x.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// reads the nrrd with the data in the type declared by the header, so nothing is lost to a
+/// conversion. Block data is returned as raw bytes
+pub fn read_nrrd_dynamic(filepath:impl AsRef<Path>) -> (NrrdData, NRRD) {
+    let (bytes,h) = read_payload(filepath);
+    let data = match h.dtype {
+        DType::int8 => NrrdData::I8(decode_payload(bytes, &h)),
+        DType::uint8 => NrrdData::U8(decode_payload(bytes, &h)),
+        DType::int16 => NrrdData::I16(decode_payload(bytes, &h)),
+        DType::uint16 => NrrdData::U16(decode_payload(bytes, &h)),
+        DType::int32 => NrrdData::I32(decode_payload(bytes, &h)),
+        DType::uint32 => NrrdData::U32(decode_payload(bytes, &h)),
+        DType::int64 => NrrdData::I64(decode_payload(bytes, &h)),
+        DType::uint64 => NrrdData::U64(decode_payload(bytes, &h)),
+        DType::f32 => NrrdData::F32(decode_payload(bytes, &h)),
+        DType::f64 => NrrdData::F64(decode_payload(bytes, &h)),
+        #[cfg(feature = "f16")]
+        DType::f16 => NrrdData::F16(decode_payload(bytes, &h)),
+        DType::block => NrrdData::Block(bytes),
+    };
+    (data,h)
+}
+
+/// a flag shared with a background read to ask it to stop early
+#[derive(Debug,Clone,Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// reads the nrrd on a background thread, returning a handle to join for the result. If a cancel
+/// token is given, it is checked before each data file is read and the read stops with
+/// 'NrrdError::Cancelled' once it is set
+pub fn read_nrrd_async_thread<T:NRRDType + FromPrimitive + Send + 'static>(filepath:impl AsRef<Path>, cancel:Option<CancelToken>) -> JoinHandle<Result<(Vec<T>, NRRD),NrrdError>> {
+    let filepath = filepath.as_ref().to_path_buf();
+    std::thread::spawn(move ||{
+        let (bytes,h) = read_payload_cancellable(filepath, cancel.as_ref())?;
+        let x = decode_payload(bytes, &h);
+        Ok((x,h))
+    })
+}
+
+/// converts payload bytes described by the header to type T
+fn decode_payload<T:NRRDType + FromPrimitive>(bytes:Vec<u8>, h:&NRRD) -> Vec<T> {
+    if h.dtype.is_block() {
+        panic!("cannot read block data into primitive type")
+    }
+    try_decode_payload(bytes, h).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// converts each value with 'f', returning the index of the first value that doesn't fit
+fn convert_values<S,T>(values:impl IntoIterator<Item = S>, f:impl Fn(S) -> Option<T>) -> Result<Vec<T>,usize> {
+    values.into_iter().enumerate().map(|(i,x)| f(x).ok_or(i)).collect()
+}
 
-    let n = h.sizes.n_elements();
+/// reverses the bytes of each N-byte element
+fn swap_elements<const N:usize>(bytes:&mut [u8]) {
+    for element in bytes.chunks_exact_mut(N) {
+        let element:&mut [u8;N] = element.try_into().unwrap();
+        element.reverse();
+    }
+}
+
+/// decodes the bytes and converts them to T, failing on the first value T can't represent
+fn try_decode_payload<T:NRRDType + FromPrimitive>(bytes:Vec<u8>, h:&NRRD) -> Result<Vec<T>,NrrdError> {
+
+    // the bytes may hold only part of the volume
+    let n = bytes.len() / h.dtype.size();
+
+    // values of the stored type only need their bytes copied out and swapped
+    if h.dtype == T::dtype() {
+        let mut x:Vec<T> = bytemuck::pod_collect_to_vec(&bytes[..n * h.dtype.size()]);
+        if h.endian != Endian::native() {
+            h.swap_bytes_in_place(bytemuck::cast_slice_mut(x.as_mut_slice()));
+        }
+        return Ok(x);
+    }
 
     // convert bytes to type T
-    let x:Vec<T> = match h.dtype {
-        DType::int8 => bytes.into_iter().map(|byte| T::from_i8(byte as i8).unwrap()).collect(),
-        DType::uint8 => bytes.into_iter().map(|byte| T::from_u8(byte).unwrap()).collect(),
+    let x = match h.dtype {
+        DType::int8 => convert_values(bytes, |byte| T::from_i8(byte as i8)),
+        DType::uint8 => convert_values(bytes, T::from_u8),
         DType::int16 => {
             let mut buf = vec![0i16;n];
             match h.endian {
                 Endian::Big => BigEndian::read_i16_into(&bytes, &mut buf),
                 Endian::Little => LittleEndian::read_i16_into(&bytes, &mut buf),
             }
-            buf.into_iter().map(|x| T::from_i16(x).unwrap()).collect()
+            convert_values(buf, T::from_i16)
         }
         DType::uint16 => {
             let mut buf = vec![0u16;n];
@@ -121,7 +2706,7 @@ pub fn read_nrrd_to<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> (V
                 Endian::Big => BigEndian::read_u16_into(&bytes, &mut buf),
                 Endian::Little => LittleEndian::read_u16_into(&bytes, &mut buf),
             }
-            buf.into_iter().map(|x| T::from_u16(x).unwrap()).collect()
+            convert_values(buf, T::from_u16)
         }
         DType::int32 => {
             let mut buf = vec![0i32;n];
@@ -129,7 +2714,7 @@ pub fn read_nrrd_to<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> (V
                 Endian::Big => BigEndian::read_i32_into(&bytes, &mut buf),
                 Endian::Little => LittleEndian::read_i32_into(&bytes, &mut buf),
             }
-            buf.into_iter().map(|x| T::from_i32(x).unwrap()).collect()
+            convert_values(buf, T::from_i32)
         }
         DType::uint32 => {
             let mut buf = vec![0u32;n];
@@ -137,7 +2722,7 @@ pub fn read_nrrd_to<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> (V
                 Endian::Big => BigEndian::read_u32_into(&bytes, &mut buf),
                 Endian::Little => LittleEndian::read_u32_into(&bytes, &mut buf),
             }
-            buf.into_iter().map(|x| T::from_u32(x).unwrap()).collect()
+            convert_values(buf, T::from_u32)
         }
         DType::int64 => {
             let mut buf = vec![0i64;n];
@@ -145,7 +2730,7 @@ pub fn read_nrrd_to<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> (V
                 Endian::Big => BigEndian::read_i64_into(&bytes, &mut buf),
                 Endian::Little => LittleEndian::read_i64_into(&bytes, &mut buf),
             }
-            buf.into_iter().map(|x| T::from_i64(x).unwrap()).collect()
+            convert_values(buf, T::from_i64)
         }
         DType::uint64 => {
             let mut buf = vec![0u64;n];
@@ -153,7 +2738,7 @@ pub fn read_nrrd_to<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> (V
                 Endian::Big => BigEndian::read_u64_into(&bytes, &mut buf),
                 Endian::Little => LittleEndian::read_u64_into(&bytes, &mut buf),
             }
-            buf.into_iter().map(|x| T::from_u64(x).unwrap()).collect()
+            convert_values(buf, T::from_u64)
         }
         DType::f32 => {
             let mut buf = vec![0f32;n];
@@ -161,7 +2746,7 @@ pub fn read_nrrd_to<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> (V
                 Endian::Big => BigEndian::read_f32_into(&bytes, &mut buf),
                 Endian::Little => LittleEndian::read_f32_into(&bytes, &mut buf),
             }
-            buf.into_iter().map(|x| T::from_f32(x).unwrap()).collect()
+            convert_values(buf, T::from_f32)
         }
         DType::f64 => {
             let mut buf = vec![0f64;n];
@@ -169,16 +2754,150 @@ pub fn read_nrrd_to<T:NRRDType + FromPrimitive>(filepath:impl AsRef<Path>) -> (V
                 Endian::Big => BigEndian::read_f64_into(&bytes, &mut buf),
                 Endian::Little => LittleEndian::read_f64_into(&bytes, &mut buf),
             }
-            buf.into_iter().map(|x| T::from_f64(x).unwrap()).collect()
+            convert_values(buf, T::from_f64)
+        }
+        #[cfg(feature = "f16")]
+        DType::f16 => {
+            let mut buf = vec![0u16;n];
+            match h.endian {
+                Endian::Big => BigEndian::read_u16_into(&bytes, &mut buf),
+                Endian::Little => LittleEndian::read_u16_into(&bytes, &mut buf),
+            }
+            convert_values(buf, |x| T::from_f32(half::Half::from_bits(x).to_f32()))
         }
         DType::block => {
-            panic!("cannot read block data into primitive type")
+            return Err(NrrdError::TypeMismatch {expected: h.dtype, found: T::dtype()});
         }
     };
-    (x,h)
+    x.map_err(|index| NrrdError::OutOfRange {index, from: h.dtype, to: T::dtype()})
 }
 
-pub fn write_nrrd<T:NRRDType>(filepath:impl AsRef<Path>, ref_header:&NRRD, data:&[T], attached:bool, encoding:Encoding) {
+/// returns the optional cargo features compiled into this build
+pub fn supported_features() -> &'static [&'static str] {
+    &[
+        #[cfg(feature = "npy")]
+        "npy",
+        #[cfg(feature = "f16")]
+        "f16",
+        #[cfg(feature = "nifti")]
+        "nifti",
+        #[cfg(feature = "rayon")]
+        "rayon",
+        #[cfg(feature = "ndarray")]
+        "ndarray",
+        #[cfg(feature = "tokio")]
+        "tokio",
+        #[cfg(feature = "serde")]
+        "serde",
+    ]
+}
+
+/// returns true if data with the encoding can be read by this build
+pub fn supports_reading(encoding:Encoding) -> bool {
+    match encoding {
+        Encoding::raw | Encoding::rawgz | Encoding::rawbz2 | Encoding::txt | Encoding::hex => true,
+    }
+}
+
+/// returns true if data with the encoding can be written by this build
+pub fn supports_writing(encoding:Encoding) -> bool {
+    match encoding {
+        Encoding::raw | Encoding::rawgz | Encoding::rawbz2 => true,
+        Encoding::txt | Encoding::hex => false,
+    }
+}
+
+/// options controlling how data is written
+#[derive(Debug,Clone,Copy)]
+pub struct WriteOptions {
+    /// write the header and data to a single .nrrd file, otherwise write a .nhdr + data file
+    pub attached: bool,
+    pub encoding: Encoding,
+    /// byte order of the written data. Data is byte-swapped when this differs from native
+    pub endian: Endian,
+    /// allow writing data types that are not part of the NRRD spec (such as f16). Other NRRD
+    /// readers may not be able to read them
+    pub allow_nonstandard_types: bool,
+    /// scan the data and set the header's 'min' and 'max' to its range. NaNs are skipped. Off by
+    /// default since it takes an extra pass over the data
+    pub compute_range: bool,
+    /// write the header lines, including comments and key-values, in the order of the header they
+    /// were parsed from instead of the canonical order. Has no effect on headers that weren't parsed
+    pub preserve_order: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            attached: true,
+            encoding: Encoding::raw,
+            endian: Endian::native(),
+            allow_nonstandard_types: false,
+            compute_range: false,
+            preserve_order: false,
+        }
+    }
+}
+
+impl WriteOptions {
+    pub fn new(attached:bool, encoding:Encoding) -> WriteOptions {
+        WriteOptions {
+            attached,
+            encoding,
+            ..Default::default()
+        }
+    }
+}
+
+/// returns the smallest and largest values of the data, skipping NaNs. Returns None if there are
+/// no values other than NaN
+fn value_range<T:NRRDType>(data:&[T]) -> Option<(f64,f64)> {
+    fn range(values:impl Iterator<Item = f64>) -> Option<(f64,f64)> {
+        values.filter(|x| !x.is_nan()).fold(None, |range, x| match range {
+            None => Some((x,x)),
+            Some((min,max)) => Some((min.min(x), max.max(x))),
+        })
+    }
+    let values:&[u8] = bytemuck::cast_slice(data);
+    match T::dtype() {
+        DType::int8 => range(bytemuck::cast_slice::<u8,i8>(values).iter().map(|&x| x as f64)),
+        DType::uint8 => range(values.iter().map(|&x| x as f64)),
+        DType::int16 => range(bytemuck::cast_slice::<u8,i16>(values).iter().map(|&x| x as f64)),
+        DType::uint16 => range(bytemuck::cast_slice::<u8,u16>(values).iter().map(|&x| x as f64)),
+        DType::int32 => range(bytemuck::cast_slice::<u8,i32>(values).iter().map(|&x| x as f64)),
+        DType::uint32 => range(bytemuck::cast_slice::<u8,u32>(values).iter().map(|&x| x as f64)),
+        DType::int64 => range(bytemuck::cast_slice::<u8,i64>(values).iter().map(|&x| x as f64)),
+        DType::uint64 => range(bytemuck::cast_slice::<u8,u64>(values).iter().map(|&x| x as f64)),
+        DType::f32 => range(bytemuck::cast_slice::<u8,f32>(values).iter().map(|&x| x as f64)),
+        DType::f64 => range(bytemuck::cast_slice::<u8,f64>(values).iter().cloned()),
+        #[cfg(feature = "f16")]
+        DType::f16 => range(bytemuck::cast_slice::<u8,half::Half>(values).iter().map(|x| x.to_f32() as f64)),
+        DType::block => None,
+    }
+}
+
+/// returns the bytes of the data in the byte order 'B'
+fn encode_bytes<B:ByteOrder,T:NRRDType>(data:&[T]) -> Vec<u8> {
+    let mut buf = vec![0u8;size_of_val(data)];
+    match T::dtype() {
+        DType::int8 | DType::uint8 | DType::block => buf.copy_from_slice(bytemuck::cast_slice(data)),
+        DType::int16 => B::write_i16_into(bytemuck::cast_slice(data), &mut buf),
+        DType::uint16 => B::write_u16_into(bytemuck::cast_slice(data), &mut buf),
+        DType::int32 => B::write_i32_into(bytemuck::cast_slice(data), &mut buf),
+        DType::uint32 => B::write_u32_into(bytemuck::cast_slice(data), &mut buf),
+        DType::int64 => B::write_i64_into(bytemuck::cast_slice(data), &mut buf),
+        DType::uint64 => B::write_u64_into(bytemuck::cast_slice(data), &mut buf),
+        DType::f32 => B::write_f32_into(bytemuck::cast_slice(data), &mut buf),
+        DType::f64 => B::write_f64_into(bytemuck::cast_slice(data), &mut buf),
+        #[cfg(feature = "f16")]
+        DType::f16 => B::write_u16_into(bytemuck::cast_slice(data), &mut buf),
+    }
+    buf
+}
+
+/// returns a copy of the reference header set up to describe native-endian data of type T with the
+/// given encoding
+fn prepare_write_header<T:NRRDType>(ref_header:&NRRD, encoding:Encoding) -> NRRD {
 
     let mut h = ref_header.clone();
 
@@ -188,13 +2907,6 @@ pub fn write_nrrd<T:NRRDType>(filepath:impl AsRef<Path>, ref_header:&NRRD, data:
     // we write in native endianness to avoid overhead of byte swapping
     h.endian = Endian::native();
 
-    // this cast is valid only for native endianness
-    let bytes:&[u8] = bytemuck::cast_slice(data);
-
-    // assert that the number of bytes is as expected
-    let expected_bytes = h.expected_bytes();
-    assert_eq!(bytes.len(),expected_bytes);
-
     // set the encoding
     h.encoding = encoding;
 
@@ -202,41 +2914,204 @@ pub fn write_nrrd<T:NRRDType>(filepath:impl AsRef<Path>, ref_header:&NRRD, data:
     h.byte_skip = None;
     h.line_skip = None;
 
+    h
+}
+
+/// writes the header and its already-encoded data bytes, either attached (.nrrd) or detached
+/// (.nhdr + data file). A detached header that already names a single data file keeps that name,
+/// resolved against the directory of the header if it's relative. Otherwise the data file is named
+/// after the header with the extension of the encoding. With 'preserve_order' the header lines
+/// keep the order of the header they were parsed from (see 'NRRD::write_preserving_order')
+fn write_bytes(h:&mut NRRD, filepath:impl AsRef<Path>, bytes:&[u8], attached:bool, preserve_order:bool) -> Result<(),NrrdError> {
+
+    let encoding = h.encoding;
+    let header_text = |h:&NRRD| if preserve_order {h.write_preserving_order()} else {h.to_string()};
+
     if attached {
 
         h.data_file = None;
         let data_p = filepath.as_ref().with_extension("nrrd");
-        let mut f = File::create(data_p).unwrap();
-        f.write_all(h.to_string().as_bytes()).unwrap();
-        write!(&mut f,"\n").unwrap();
+        let mut f = File::create(data_p)?;
+        f.write_all(header_text(h).as_bytes())?;
+        writeln!(&mut f)?;
         encoding.write_payload(&mut f, bytes);
 
     }else {
 
-        let ext = encoding.file_ext();
+        let header_p = filepath.as_ref().with_extension("nhdr");
 
-        let df = Path::new(
-            filepath.as_ref().file_name().unwrap().to_str().unwrap()
-        ).with_extension(ext);
+        let df = match &h.data_file {
+            Some(DataFile::SingleFile {filename}) => filename.clone(),
+            _=> Path::new(
+                filepath.as_ref().file_name().unwrap().to_str().unwrap()
+            ).with_extension(encoding.file_ext()),
+        };
+        let data_p = header_p.parent().unwrap().join(&df);
         h.data_file = Some(DataFile::SingleFile {
             filename: df,
         });
-        let data_p = filepath.as_ref().with_extension(ext);
-        let header_p = filepath.as_ref().with_extension("nhdr");
-
-        let mut f = File::create(data_p).unwrap();
 
+        let mut f = File::create(data_p)?;
         encoding.write_payload(&mut f, bytes);
 
-        // match encoding {
-        //     Encoding::raw => io::write_raw(&mut f, bytes),
-        //     Encoding::rawgz => io::write_gzip(&mut f, bytes),
-        //     Encoding::rawbz2 => io::write_bzip2(&mut f, bytes),
-        //     _=> panic!("encoding {} not yet supported",h.encoding)
-        // };
-        let mut f = File::create(header_p).unwrap();
-        f.write_all(h.to_string().as_bytes()).unwrap();
+        let mut f = File::create(header_p)?;
+        f.write_all(header_text(h).as_bytes())?;
+    };
+
+    Ok(())
+}
+
+/// writes the data with the reference header, either attached (.nrrd) or detached (.nhdr + data
+/// file). Panics on failure, see 'NRRD::write' for a fallible version
+pub fn write_nrrd<T:NRRDType>(filepath:impl AsRef<Path>, ref_header:&NRRD, data:&[T], attached:bool, encoding:Encoding) {
+    ref_header.write(filepath, data, attached, encoding).unwrap();
+}
+
+/// writes block data (records of 'block size' bytes each) with the reference header, either
+/// attached (.nrrd) or detached (.nhdr + data file). The block size is taken from the reference
+/// header and every block must have exactly that many bytes
+pub fn write_blocks(filepath:impl AsRef<Path>, ref_header:&NRRD, blocks:&[Vec<u8>], attached:bool, encoding:Encoding) -> Result<(),NrrdError> {
+
+    let block_size = ref_header.block_size.as_ref().ok_or(NrrdError::MissingField("block size"))?.size();
+
+    let n_elements = ref_header.sizes.n_elements();
+    if blocks.len() != n_elements {
+        return Err(NrrdError::DataLength {expected: n_elements, found: blocks.len()});
+    }
+
+    if let Some((index,block)) = blocks.iter().enumerate().find(|(_,b)| b.len() != block_size) {
+        return Err(NrrdError::BlockLength {index, expected: block_size, found: block.len()});
+    }
+
+    let mut h = prepare_write_header::<u8>(ref_header, encoding);
+    h.dtype = DType::block;
+    write_bytes(&mut h, filepath, &blocks.concat(), attached, false)
+}
+
+/// writes a detached header with a 'data file: LIST' entry, splitting the data evenly across the
+/// listed files in order. Relative file paths are written relative to the directory of the header
+pub fn write_nrrd_list<T:NRRDType>(header_path:impl AsRef<Path>, ref_header:&NRRD, data:&[T], slice_files:&[PathBuf], encoding:Encoding) -> Result<(),NrrdError> {
+
+    let mut h = prepare_write_header::<T>(ref_header, encoding);
+    h.data_file = Some(DataFile::List {
+        file_paths: slice_files.to_vec(),
+        sub_dim: None,
+    });
+    write_detached(header_path, &h, data)
+}
+
+/// writes a detached header with a sprintf-style 'data file: <fmt> <min> <max> <step>' entry,
+/// splitting the data evenly across the files generated by the format string. Relative file paths
+/// are written relative to the directory of the header
+#[allow(clippy::too_many_arguments)]
+pub fn write_nrrd_fileformat<T:NRRDType>(header_path:impl AsRef<Path>, ref_header:&NRRD, data:&[T], fmt_string:&str, min:i32, max:i32, step:i32, encoding:Encoding) -> Result<(),NrrdError> {
+
+    let mut h = prepare_write_header::<T>(ref_header, encoding);
+    h.data_file = Some(DataFile::FileFormat {
+        fmt_string: fmt_string.to_string(),
+        min,
+        max,
+        step,
+        sub_dim: None,
+    });
+    write_detached(header_path, &h, data)
+}
+
+/// appends slices along the slowest axis of a detached header with a sprintf-style data file
+/// ('data file: <fmt> <min> <max> <step>') holding one slice per file. Each new slice is written to
+/// the next file in the numbering, and the header's last size and 'max' are updated. Existing data
+/// files are left untouched and are never overwritten
+pub fn append_slices<T:NRRDType>(header_path:impl AsRef<Path>, new_slices:&[&[T]]) -> Result<(),NrrdError> {
+
+    let header_path = header_path.as_ref();
+    let mut h = read_header_checked(header_path)?;
+
+    if h.dtype != T::dtype() {
+        return Err(NrrdError::TypeMismatch {expected: h.dtype, found: T::dtype()});
+    }
+
+    let n_slices = *h.shape().last().expect("header must have at least one axis");
+    let slice_len = h.sizes.n_elements() / n_slices;
+    if let Some(slice) = new_slices.iter().find(|s| s.len() != slice_len) {
+        return Err(NrrdError::DataLength {expected: slice_len, found: slice.len()});
+    }
+
+    let (fmt_string, max, step) = match &h.data_file {
+        Some(DataFile::FileFormat {fmt_string, max, step, sub_dim, ..}) => {
+            if sub_dim.is_some_and(|d| d + 1 != h.dimension.dim()) {
+                return Err(NrrdError::UnsupportedDataFile("each data file must hold exactly one slice".to_string()));
+            }
+            (fmt_string.clone(), *max, *step)
+        }
+        _=> return Err(NrrdError::UnsupportedDataFile("slices can only be appended to a sprintf-style data file".to_string())),
+    };
+
+    let datafile = h.data_file.as_ref().unwrap();
+    if datafile.paths().len() != n_slices {
+        return Err(NrrdError::UnsupportedDataFile("each data file must hold exactly one slice".to_string()));
+    }
+
+    // the new files continue the numbering after the current max
+    let new_max = max + step * new_slices.len() as i32;
+    let new_files = DataFile::FileFormat {
+        fmt_string: fmt_string.clone(),
+        min: max + step,
+        max: new_max,
+        step,
+        sub_dim: None,
     };
+    let paths = resolve_data_paths(&new_files, header_path);
+
+    if let Some(existing) = paths.iter().find(|p| p.exists()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", existing.display())
+        ).into());
+    }
+
+    for (slice,path) in new_slices.iter().zip(&paths) {
+        let bytes = match h.endian {
+            Endian::Big => encode_bytes::<BigEndian,T>(slice),
+            Endian::Little => encode_bytes::<LittleEndian,T>(slice),
+        };
+        let mut f = File::create(path)?;
+        h.encoding.write_payload(&mut f, &bytes);
+    }
+
+    *h.sizes.entries_mut().last_mut().unwrap() += new_slices.len();
+    if let Some(DataFile::FileFormat {max, ..}) = &mut h.data_file {
+        *max = new_max;
+    }
+
+    let mut f = File::create(header_path)?;
+    f.write_all(h.to_string().as_bytes())?;
+    Ok(())
+}
+
+/// writes the header and splits the data across the files listed in its data file field
+fn write_detached<T:NRRDType>(header_path:impl AsRef<Path>, h:&NRRD, data:&[T]) -> Result<(),NrrdError> {
+
+    if !h.dtype.is_standard() {
+        return Err(NrrdError::NonStandardType(h.dtype));
+    }
+
+    let n_elements = h.sizes.n_elements();
+    if data.len() != n_elements {
+        return Err(NrrdError::DataLength {expected: n_elements, found: data.len()});
+    }
+
+    let datafile = h.data_file.as_ref().expect("data file must be defined for detached headers");
+    let paths = resolve_data_paths(datafile, header_path.as_ref());
+    let per_file = h.elements_per_file(paths.len())?;
+
+    for (chunk,path) in data.chunks_exact(per_file).zip(&paths) {
+        let mut f = File::create(path)?;
+        h.encoding.write_payload(&mut f, bytemuck::cast_slice(chunk));
+    }
+
+    let mut f = File::create(header_path)?;
+    f.write_all(h.to_string().as_bytes())?;
+    Ok(())
 }
 
 /// reads only the header of the nhdr or nrrd
@@ -248,91 +3123,389 @@ pub fn read_header(nrrd:impl AsRef<Path>) -> NRRD {
     NRRD::from_lines_full(&mut header_lines)
 }
 
-/// reads the nrrd header and all associated data bytes into a single vector
+/// parses the header at the start of an in-memory nhdr or nrrd, stopping at the first blank line
+pub fn read_header_from_bytes(bytes:&[u8]) -> NRRD {
+    let (header_bytes,..) = io::read_until_blank(&mut std::io::Cursor::new(bytes)).expect("failed to read header");
+    let header_str = String::from_utf8(header_bytes).expect("failed to convert bytes to string");
+    let mut header_lines = header_str.lines().collect::<Vec<&str>>();
+    NRRD::from_lines_full(&mut header_lines)
+}
+
+/// reads only the header of the nhdr or nrrd and checks that all per-axis fields are consistent
+/// with the dimension
+pub fn read_header_validated(nrrd:impl AsRef<Path>) -> Result<NRRD,NrrdError> {
+    let h = read_header(nrrd);
+    h.validate()?;
+    Ok(h)
+}
+
+/// reads the header of the nhdr or nrrd, returning an error instead of panicking when required
+/// fields are missing. A header that hits the end of the file part way through a line is reported
+/// as truncated if it is also incomplete or inconsistent. A complete header that simply lacks a
+/// final newline is accepted
+pub fn read_header_checked(nrrd:impl AsRef<Path>) -> Result<NRRD,NrrdError> {
+    let mut f = File::open(nrrd.as_ref())?;
+    let (header_bytes,blank_offset) = io::read_until_blank(&mut f)?;
+    let header_str = String::from_utf8_lossy(&header_bytes);
+    let mut header_lines = header_str.lines().collect::<Vec<&str>>();
+
+    // detached headers commonly end at EOF without a final newline, so only a header that isn't
+    // detached can be cut off mid-line
+    let detached = header_lines.iter().any(|l| DataFile::matches(l));
+    let partial_line = if blank_offset.is_none() && !detached && !header_bytes.is_empty() && !header_bytes.ends_with(b"\n") {
+        header_lines.last().map(|l| l.to_string())
+    }else {
+        None
+    };
+
+    let result = missing_required_field(&header_lines)
+        .map_or(Ok(()), |field| Err(NrrdError::MissingField(field)))
+        .and_then(|_| check_magic(&header_lines))
+        .and_then(|_| check_duplicate_fields(&header_lines))
+        .and_then(|_| parse_lines_caught(&mut header_lines))
+        .and_then(|h| h.validate().map(|_| h));
+
+    match (result, partial_line) {
+        (Err(_), Some(partial_line)) => Err(NrrdError::TruncatedHeader {partial_line}),
+        (result, _) => result,
+    }
+}
+
+/// parses the header lines, returning the panic of a malformed field value as an error. Field values
+/// are parsed by panicking 'FromStr' implementations, so this is the only way to recover from them
+fn parse_lines_caught(lines:&mut Vec<&str>) -> Result<NRRD,NrrdError> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| NRRD::from_lines_full(lines))).map_err(|payload|{
+        let msg = payload.downcast_ref::<String>().cloned()
+            .or(payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default();
+        NrrdError::InvalidValue {field: "header", value: msg}
+    })
+}
+
+/// the basic facts about a nrrd, see 'quick_stat'
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct QuickStat {
+    pub version: u8,
+    pub dimension: usize,
+    pub dtype: DType,
+    pub sizes: Vec<usize>,
+}
+
+/// reads only the magic, dimension, type and sizes of a nhdr or nrrd. Reading stops as soon as
+/// those lines are found, and no other field is parsed, so malformed optional fields are ignored
+pub fn quick_stat(nrrd:impl AsRef<Path>) -> Result<QuickStat,NrrdError> {
+
+    let invalid = |field:&'static str, line:&str| NrrdError::InvalidValue {field, value: line.trim().to_string()};
+
+    let mut reader = std::io::BufReader::new(File::open(nrrd.as_ref())?);
+    let mut line = String::new();
+    let mut version = None;
+    let mut dimension = None;
+    let mut dtype = None;
+    let mut sizes = None;
+
+    loop {
+        line.clear();
+        if std::io::BufRead::read_line(&mut reader, &mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let l = line.trim_end_matches(['\r','\n']);
+        if version.is_none() {
+            // the magic must be the first line
+            version = Some(Magic::from_str(l).map_err(|_| invalid("magic", l))?.version);
+        }else if Dimension::matches(l) {
+            let idx = Dimension::idx(l).unwrap();
+            dimension = Some(l[idx..].trim().parse::<usize>().map_err(|_| invalid("dimension", l))?);
+        }else if DType::matches(l) {
+            let idx = DType::idx(l).unwrap();
+            dtype = Some(DType::from_name(&l[idx..]).ok_or_else(|| invalid("type", l))?);
+        }else if Sizes::matches(l) {
+            let idx = Sizes::idx(l).unwrap();
+            let parsed = l[idx..].split_whitespace()
+                .map(|s| s.parse::<usize>().ok().filter(|&s| s > 0))
+                .collect::<Option<Vec<usize>>>()
+                .ok_or_else(|| invalid("sizes", l))?;
+            sizes = Some(parsed);
+        }
+        if dimension.is_some() && dtype.is_some() && sizes.is_some() {
+            break;
+        }
+    }
+
+    let stat = QuickStat {
+        version: version.ok_or(NrrdError::MissingField("magic"))?,
+        dimension: dimension.ok_or(NrrdError::MissingField("dimension"))?,
+        dtype: dtype.ok_or(NrrdError::MissingField("type"))?,
+        sizes: sizes.ok_or(NrrdError::MissingField("sizes"))?,
+    };
+    check_len("sizes", stat.dimension, stat.sizes.len())?;
+    Ok(stat)
+}
+
+/// returns the name of the first required field not found in the header lines
+fn missing_required_field(lines:&[&str]) -> Option<&'static str> {
+    let has = |matches:fn(&str) -> bool| lines.iter().any(|l| matches(l));
+    if !has(Magic::matches) {
+        Some("magic")
+    }else if !has(Dimension::matches) {
+        Some("dimension")
+    }else if !has(DType::matches) {
+        Some("type")
+    }else if !has(Encoding::matches) {
+        Some("encoding")
+    }else if !has(Endian::matches) && !is_text(lines) {
+        Some("endian")
+    }else if !has(Sizes::matches) {
+        Some("sizes")
+    }else {
+        None
+    }
+}
+
+/// returns an error if the comment would break its header line
+fn check_comment(comment:&str) -> Result<(),NrrdError> {
+    if comment.contains(['\n','\r']) {
+        return Err(NrrdError::InvalidValue {field: "comment", value: comment.to_string()});
+    }
+    Ok(())
+}
+
+/// returns an error if the key-value pair can't be written and read back as the same pair
+fn check_key_value(key:&str, value:&str) -> Result<(),NrrdError> {
+    if key.is_empty() || key.contains(":=") || key.contains(['\n','\r']) {
+        return Err(NrrdError::InvalidValue {field: "key", value: key.to_string()});
+    }
+    if value.contains(['\n','\r']) {
+        return Err(NrrdError::InvalidValue {field: "value", value: value.to_string()});
+    }
+    Ok(())
+}
+
+/// returns true if the header lines declare text encoding, which needs no endian field
+fn is_text(lines:&[&str]) -> bool {
+    lines.iter().filter(|l| Encoding::matches(l)).any(|l|{
+        let value = l[Encoding::idx(l).unwrap()..].trim().to_ascii_lowercase();
+        matches!(value.as_str(), "txt" | "text" | "ascii")
+    })
+}
+
+/// reads the nrrd header and all associated data bytes into a single vector. With the 'rayon'
+/// feature, the files of multi-file detached data are read concurrently on the rayon thread pool
 pub fn read_payload(filepath:impl AsRef<Path>) -> (Vec<u8>, NRRD) {
+    read_payload_cancellable(filepath, None).unwrap()
+}
 
-    let mut f = File::open(&filepath).unwrap();
-    let (header_bytes,_offset) = io::read_until_blank(&mut f).expect("failed to read header");
+/// reads the header and data bytes from any byte source positioned at the start of the header, such
+/// as a 'Cursor' over a downloaded file. Relative detached data files are resolved against
+/// 'base_dir', or the working directory if it is None
+pub fn read_payload_from_reader<R:Read + Seek>(reader:&mut R, base_dir:Option<&Path>) -> Result<(Vec<u8>, NRRD),NrrdError> {
+    read_payload_from(reader, base_dir.unwrap_or(Path::new("")), Path::new("reader"), None)
+}
+
+/// reads the header and data bytes, checking the cancel token before each data file is read
+fn read_payload_cancellable(filepath:impl AsRef<Path>, cancel:Option<&CancelToken>) -> Result<(Vec<u8>, NRRD),NrrdError> {
+    let filepath = filepath.as_ref();
+    let mut f = File::open(filepath)?;
+    read_payload_from(&mut f, filepath.parent().unwrap(), filepath, cancel)
+}
+
+/// reads the header and data bytes from the reader. 'source' names the reader in error messages
+fn read_payload_from<R:Read + Seek>(f:&mut R, base_dir:&Path, source:&Path, cancel:Option<&CancelToken>) -> Result<(Vec<u8>, NRRD),NrrdError> {
+    let h = read_header_from(f)?;
+    let mut bytes = vec![0u8;h.expected_bytes()];
+    read_data_into(f, &h, base_dir, source, cancel, &mut bytes)?;
+    Ok((bytes,h))
+}
+
+/// parses the header from the reader, leaving it positioned after the blank line that ends it
+fn read_header_from<R:Read + Seek>(f:&mut R) -> Result<NRRD,NrrdError> {
+    let (header_bytes,_offset) = io::read_until_blank(f)?;
     let header_str = String::from_utf8(header_bytes).expect("failed to convert bytes to string");
     let mut header_lines = header_str.lines().collect::<Vec<&str>>();
-    let h = NRRD::from_lines_full(&mut header_lines);
+    Ok(NRRD::from_lines_full(&mut header_lines))
+}
+
+/// reads the data described by the header into 'bytes', which must hold exactly the expected number
+/// of bytes. For attached headers the reader must be positioned after the header
+fn read_data_into<R:Read + Seek>(f:&mut R, h:&NRRD, base_dir:&Path, source:&Path, cancel:Option<&CancelToken>, bytes:&mut [u8]) -> Result<(),NrrdError> {
 
-    let n_expected_bytes = h.expected_bytes();
-    let mut bytes = vec![0u8;n_expected_bytes];
+    let check_cancel = || match cancel {
+        Some(token) if token.is_cancelled() => Err(NrrdError::Cancelled),
+        _=> Ok(()),
+    };
+
+    let n_expected_bytes = bytes.len();
     let line_skip = h.line_skip.as_ref().map(|ls| ls.to_skip()).unwrap_or(0);
     let (byte_skip,read_tail) = h.byte_skip.as_ref().map(|bs| (bs.to_skip(),bs.read_tail())).unwrap_or((0,false));
 
-    if let Some(datafile) = h.data_file.as_ref() {
+    if h.data_file.is_some() {
         // this means the header is detached
 
-        // resolve full paths if necessary
-        let resolved_paths = datafile.paths().into_iter().map(|p|{
-            if p.is_relative() {
-                filepath.as_ref().parent().unwrap().join(p)
-            }else {
-                p
-            }
-        }).collect::<Vec<PathBuf>>();
-
-        // check that all exist before attempting to read
-        resolved_paths.iter().for_each(|file| {
-            if !file.exists() {
-                panic!("{} does not exist", file.display());
-            }
-        });
-
+        // resolve full paths and check that all exist before attempting to read
+        let resolved_paths = h.resolved_data_paths_in(base_dir)?;
+
         let n_files = resolved_paths.len();
-        let (bytes_per_file,rem) = n_expected_bytes.div_rem_euclid(&n_files);
-        assert_eq!(rem,0,"number of files ({n_files}) doesn't divide total number of bytes evenly ({n_expected_bytes})");
+        let bytes_per_file = h.elements_per_file(n_files)? * h.element_size();
 
-        bytes.chunks_exact_mut(bytes_per_file).zip(&resolved_paths).for_each(|(chunk,file)|{
-            let mut f = File::open(file).unwrap();
+        let read_file = |chunk:&mut [u8], file:&PathBuf| -> Result<(),NrrdError> {
+            check_cancel()?;
+            let mut f = File::open(file)?;
             io::skip_lines(&mut f, line_skip);
-            match h.encoding {
-                Encoding::raw => io::read_raw(&mut f, None, chunk, byte_skip),
-                Encoding::rawgz => io::read_gzip(&mut f, None, chunk, byte_skip),
-                Encoding::rawbz2 => io::read_bzip2(&mut f, None, chunk, byte_skip),
-                _=> panic!("unsupported encoding ({}) for now", h.encoding)
-            };
-        });
+            let n = read_data(&mut f, h, chunk, byte_skip, read_tail)?;
+            check_read_len(file, n, chunk.len())?;
+            Ok(())
+        };
+
+        #[cfg(not(feature = "rayon"))]
+        for (chunk,file) in bytes.chunks_exact_mut(bytes_per_file).zip(&resolved_paths) {
+            read_file(chunk, file)?;
+        }
+
+        // every file decodes into its own disjoint chunk, so the files can be read concurrently
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            // set once any file fails, so that files not yet started are skipped
+            let failed = AtomicBool::new(false);
+            let results:Vec<Result<(),NrrdError>> = bytes.par_chunks_exact_mut(bytes_per_file)
+                .zip(resolved_paths.par_iter())
+                .map(|(chunk,file)|{
+                    if failed.load(Ordering::Relaxed) {
+                        return Err(NrrdError::Cancelled);
+                    }
+                    // a reader that panicked is reported as an error rather than unwinding the pool
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| read_file(chunk, file)))
+                        .unwrap_or_else(|_| Err(std::io::Error::other(format!("reading {} panicked", file.display())).into()));
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    result
+                })
+                .collect();
+            // report the first real failure, not the files that were skipped because of it
+            if let Some(e) = results.into_iter().filter_map(Result::err).find(|e| !matches!(e, NrrdError::Cancelled)) {
+                return Err(e);
+            }
+            check_cancel()?;
+        }
 
-        (bytes,h)
+        Ok(())
 
     } else {
         // this means the header is attached
-        io::skip_lines(&mut f,line_skip);
-
-        match h.encoding {
-            Encoding::raw => {
-                if read_tail {
-                    io::read_tail(&mut f, &mut bytes);
-                }else {
-                    io::read_raw(&mut f, None, &mut bytes, byte_skip);
+        check_cancel()?;
+        io::skip_lines(f,line_skip);
+
+        let n = read_data(f, h, bytes, byte_skip, read_tail)?;
+        check_read_len(source, n, n_expected_bytes)?;
+        Ok(())
+
+    }
+
+}
+
+/// decodes data from a reader positioned after any skipped lines. With 'read_tail' (byte skip -1)
+/// the data is the last bytes of the file, or of the decompressed stream for compressed encodings.
+/// Text data is parsed one value at a time, so a file only has to hold the values of its share of
+/// the buffer. Returns the number of bytes read
+pub(crate) fn read_data<R:Read + Seek>(f:&mut R, h:&NRRD, bytes:&mut [u8], byte_skip:usize, read_tail:bool) -> Result<usize,NrrdError> {
+    let n = match (h.encoding, read_tail) {
+        (Encoding::raw, true) => io::read_tail(f, bytes),
+        (Encoding::raw, false) => io::read_raw(f, None, bytes, byte_skip),
+        (Encoding::rawgz, true) => io::read_gzip_tail(f, bytes),
+        (Encoding::rawgz, false) => io::read_gzip(f, None, bytes, byte_skip),
+        (Encoding::rawbz2, true) => io::read_bzip2_tail(f, bytes),
+        (Encoding::rawbz2, false) => io::read_bzip2(f, None, bytes, byte_skip),
+        (Encoding::txt | Encoding::hex, true) => {
+            return Err(NrrdError::Unsupported(format!("byte skip -1 can't be used with {} encoding", h.encoding.name())))
+        }
+        (Encoding::hex, false) => io::read_hex(f, bytes, byte_skip)?,
+        (Encoding::txt, false) => read_text(f, h, bytes, byte_skip)?,
+    };
+    Ok(n)
+}
+
+/// parses whitespace-separated values of the header data type and stores them in the header byte
+/// order. Returns the number of bytes filled, which is less than the buffer length if the text runs
+/// out of values
+fn read_text<R:Read>(f:&mut R, h:&NRRD, bytes:&mut [u8], byte_skip:usize) -> Result<usize,NrrdError> {
+
+    if h.dtype.is_block() {
+        return Err(NrrdError::Unsupported("block data can't be stored as text".to_string()));
+    }
+
+    let mut text = vec![];
+    f.read_to_end(&mut text)?;
+    let text = String::from_utf8_lossy(text.get(byte_skip..).unwrap_or_default());
+
+    let size = h.dtype.size();
+    let invalid = |token:&str| NrrdError::InvalidValue {field: "data", value: token.to_string()};
+    let mut n = 0;
+    for (element,token) in bytes.chunks_exact_mut(size).zip(text.split_ascii_whitespace()) {
+        macro_rules! store {
+            ($t:ty) => {{
+                let x:$t = token.parse().map_err(|_| invalid(token))?;
+                match h.endian {
+                    Endian::Big => element.copy_from_slice(&x.to_be_bytes()),
+                    Endian::Little => element.copy_from_slice(&x.to_le_bytes()),
+                }
+            }};
+        }
+        match h.dtype {
+            DType::int8 => store!(i8),
+            DType::uint8 => store!(u8),
+            DType::int16 => store!(i16),
+            DType::uint16 => store!(u16),
+            DType::int32 => store!(i32),
+            DType::uint32 => store!(u32),
+            DType::int64 => store!(i64),
+            DType::uint64 => store!(u64),
+            DType::f32 => store!(f32),
+            DType::f64 => store!(f64),
+            #[cfg(feature = "f16")]
+            DType::f16 => {
+                let x:f32 = token.parse().map_err(|_| invalid(token))?;
+                let x = half::Half::from_f32(x).to_bits();
+                match h.endian {
+                    Endian::Big => element.copy_from_slice(&x.to_be_bytes()),
+                    Endian::Little => element.copy_from_slice(&x.to_le_bytes()),
                 }
-                (bytes,h)
-            }
-            Encoding::rawgz => {
-                io::read_gzip(&mut f,None, &mut bytes, byte_skip);
-                (bytes,h)
-            }
-            Encoding::rawbz2 => {
-                io::read_bzip2(&mut f,None, &mut bytes, byte_skip);
-                (bytes,h)
             }
-            _=> panic!("unsupported encoding ({}) for now",h.encoding)
+            DType::block => unreachable!(),
         }
-
+        n += size;
     }
+    Ok(n)
+}
 
+/// returns an error if a data file or stream ended before producing the expected number of bytes
+fn check_read_len(path:&Path, n_read:usize, expected:usize) -> Result<usize,NrrdError> {
+    if n_read < expected {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("data in {} ended after {n_read} of {expected} bytes", path.display())
+        ).into())
+    }else {
+        Ok(n_read)
+    }
 }
 
+/// reads block data (dtype 'block') as one byte vector of 'block size' bytes per element
+pub fn read_blocks(filepath:impl AsRef<Path>) -> (Vec<Vec<u8>>, NRRD) {
+    let (bytes,h) = read_payload(filepath);
+    assert!(h.dtype.is_block(), "data type must be 'block' to read blocks");
+    let blocks = bytes.chunks_exact(h.element_size()).map(|b| b.to_vec()).collect();
+    (blocks,h)
+}
 
 #[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NRRD {
 
     /* BASIC FIELDS */
     pub magic: Magic,
     pub dimension: Dimension,
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
     pub dtype: DType,
     pub block_size: Option<BlockSize>,
     pub encoding: Encoding,
@@ -364,12 +3537,35 @@ pub struct NRRD {
     pub space_units: Option<SpaceUnits>,
     pub space_origin: Option<SpaceOrigin>,
     pub space_directions: Option<SpaceDirections>,
+    pub measurement_frame: Option<MeasurementFrame>,
 
     /* EXTRA KEY-VALUE DATA */
+    #[cfg_attr(feature = "serde", serde(default))]
     pub key_vals: HashMap<String, Value>,
+    /// every value, in header order, of keys that appeared more than once. 'key_vals' holds the
+    /// last value of these keys
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "HashMap::is_empty"))]
+    pub duplicate_key_vals: HashMap<String, Vec<Value>>,
 
     /* COMMENTS */
+    #[cfg_attr(feature = "serde", serde(default))]
     pub comments:Vec<String>,
+
+    /// the lines of the header this was parsed from, used by 'write_preserving_order'. Empty for
+    /// headers that weren't parsed
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub source_lines: Vec<String>,
+}
+
+/// identifies a header line, used to match lines between headers
+#[derive(Debug,Clone,PartialEq,Eq)]
+enum HeaderLine {
+    /// a field, identified by its canonical pattern (e.g. "sizes: ")
+    Field(&'static str),
+    /// the n-th comment
+    Comment(usize),
+    /// the key-value pair with this key
+    KeyValue(String),
 }
 
 
@@ -379,6 +3575,29 @@ impl NRRD {
         self.sizes.shape()
     }
 
+    /// creates a header for a data type and dimensions, checking that they describe a valid array.
+    /// 'block_size' must be given for block data and only for block data, there must be at least
+    /// one axis, and every size must be at least 1
+    pub fn try_new(dtype:DType, dims:&[usize], block_size:Option<usize>) -> Result<NRRD,NrrdError> {
+        match (dtype.is_block(), block_size) {
+            (true, None) => return Err(NrrdError::MissingField("block size")),
+            (true, Some(0)) => return Err(NrrdError::InvalidValue {field: "block size", value: 0.to_string()}),
+            (false, Some(bs)) => {
+                return Err(NrrdError::InvalidValue {field: "block size", value: format!("{bs} for type '{}'", dtype.name())});
+            }
+            _=> {}
+        }
+        if dims.is_empty() {
+            return Err(NrrdError::InvalidValue {field: "dimension", value: 0.to_string()});
+        }
+        if dims.contains(&0) {
+            return Err(NrrdError::InvalidValue {field: "sizes", value: format!("{dims:?}")});
+        }
+        let mut h = NRRD::new_from_type_dims(dtype, dims);
+        h.block_size = block_size.map(BlockSize::new);
+        Ok(h)
+    }
+
     pub fn new_from_type_dims(t:DType,dims:&[usize]) -> NRRD {
         let mut nhdr = NRRD::new_from_dims::<u8>(dims);
         nhdr.dtype = t;
@@ -417,11 +3636,241 @@ impl NRRD {
             space_units: None,
             space_origin: None,
             space_directions: None,
+            measurement_frame: None,
             key_vals: Default::default(),
+            duplicate_key_vals: Default::default(),
             comments: vec![],
+            source_lines: vec![],
+        }
+
+
+    }
+
+    /// returns the paths of all data files, with relative paths resolved against the directory of
+    /// 'header_path'. Attached headers return 'header_path' itself. Fails with a list of any files
+    /// that don't exist
+    pub fn resolved_data_paths(&self, header_path:impl AsRef<Path>) -> Result<Vec<PathBuf>,NrrdError> {
+        match &self.data_file {
+            Some(_) => self.resolved_data_paths_in(header_path.as_ref().parent().unwrap()),
+            None => existing_paths(vec![header_path.as_ref().to_path_buf()]),
+        }
+    }
+
+    /// resolves the data file paths against 'base_dir', failing if any don't exist
+    fn resolved_data_paths_in(&self, base_dir:&Path) -> Result<Vec<PathBuf>,NrrdError> {
+        let paths = self.data_file.as_ref().map(|df| resolve_data_paths_in(df, base_dir)).unwrap_or_default();
+        existing_paths(paths)
+    }
+
+    /// checks the leading bytes of every data file against the declared encoding. For attached
+    /// headers the data following the header in 'header_path' is checked. Line skips are honored
+    /// before checking. Files that don't match are reported along with their apparent encoding
+    pub fn verify_data_file_encodings(&self, header_path:impl AsRef<Path>) -> Result<(),NrrdError> {
+
+        let line_skip = self.line_skip.as_ref().map(|ls| ls.to_skip()).unwrap_or(0);
+
+        let sniff = |f:&mut File| -> Result<Encoding,NrrdError> {
+            io::skip_lines(f, line_skip);
+            Ok(Encoding::sniff(&io::peek(f, 3)?))
+        };
+
+        let mut detected = vec![];
+        if self.data_file.is_some() {
+            for path in self.resolved_data_paths(header_path.as_ref())? {
+                let mut f = File::open(&path)?;
+                detected.push((path, sniff(&mut f)?));
+            }
+        }else {
+            let mut f = File::open(header_path.as_ref())?;
+            io::read_until_blank(&mut f)?;
+            detected.push((header_path.as_ref().to_path_buf(), sniff(&mut f)?));
+        }
+
+        // uncompressed encodings can't be told apart by their leading bytes
+        let files:Vec<_> = detected.into_iter().filter(|(_,e)|{
+            if self.encoding.is_compressed() {
+                *e != self.encoding
+            }else {
+                e.is_compressed()
+            }
+        }).collect();
+
+        if files.is_empty() {
+            Ok(())
+        }else {
+            Err(NrrdError::EncodingMismatch {declared: self.encoding, files})
+        }
+    }
+
+    /// writes the data with this header, either attached (.nrrd) or detached (.nhdr + data file). The
+    /// data type is taken from T and the data is written in native endianness. A detached data file
+    /// named by a 'SingleFile' data file field is used as-is, whatever its extension
+    pub fn write<T:NRRDType>(&self, filepath:impl AsRef<Path>, data:&[T], attached:bool, encoding:Encoding) -> Result<(),NrrdError> {
+        self.write_with_options(filepath, data, &WriteOptions::new(attached, encoding))
+    }
+
+    /// writes the data with this header according to the write options. The data type is taken
+    /// from T
+    pub fn write_with_options<T:NRRDType>(&self, filepath:impl AsRef<Path>, data:&[T], options:&WriteOptions) -> Result<(),NrrdError> {
+
+        let attached = options.attached;
+        let encoding = options.encoding;
+
+        let mut h = prepare_write_header::<T>(self, encoding);
+        h.endian = options.endian;
+
+        if !h.dtype.is_standard() && !options.allow_nonstandard_types {
+            return Err(NrrdError::NonStandardType(h.dtype));
+        }
+
+        let n_elements = h.sizes.n_elements();
+        if data.len() != n_elements {
+            return Err(NrrdError::DataLength {expected: n_elements, found: data.len()});
+        }
+
+        if options.compute_range {
+            let range = value_range(data);
+            h.min = range.map(|(min,_)| Min::new(min));
+            h.max = range.map(|(_,max)| Max::new(max));
+        }
+
+        // the cast is only valid for native endianness, otherwise the data is byte-swapped
+        let swapped;
+        let bytes:&[u8] = if h.endian == Endian::native() {
+            bytemuck::cast_slice(data)
+        }else {
+            swapped = match h.endian {
+                Endian::Big => encode_bytes::<BigEndian,T>(data),
+                Endian::Little => encode_bytes::<LittleEndian,T>(data),
+            };
+            &swapped
+        };
+
+        write_bytes(&mut h, filepath, bytes, attached, options.preserve_order)
+    }
+
+    /// reads the data of the attached nrrd at 'src_path' and writes it with this header as a
+    /// detached pair ('out_stem'.nhdr + data file) with the given encoding
+    pub fn detach(&self, src_path:impl AsRef<Path>, out_stem:impl AsRef<Path>, encoding:Encoding) -> Result<(),NrrdError> {
+        self.copy_payload(src_path, out_stem, false, encoding)
+    }
+
+    /// reads the data of the detached header at 'src_nhdr' and writes it with this header as a
+    /// single attached .nrrd with the given encoding
+    pub fn attach(&self, src_nhdr:impl AsRef<Path>, out_path:impl AsRef<Path>, encoding:Encoding) -> Result<(),NrrdError> {
+        self.copy_payload(src_nhdr, out_path, true, encoding)
+    }
+
+    /// copies the data bytes of the source as-is under this header. The source must have the same
+    /// data type and number of elements. Its byte order is kept so no swapping is needed
+    fn copy_payload(&self, src:impl AsRef<Path>, out:impl AsRef<Path>, attached:bool, encoding:Encoding) -> Result<(),NrrdError> {
+
+        let (bytes,src_h) = read_payload_cancellable(src, None)?;
+
+        if src_h.dtype != self.dtype {
+            return Err(NrrdError::TypeMismatch {expected: self.dtype, found: src_h.dtype});
+        }
+        let n_elements = self.sizes.n_elements();
+        if src_h.sizes.n_elements() != n_elements {
+            return Err(NrrdError::DataLength {expected: n_elements, found: src_h.sizes.n_elements()});
         }
 
+        let mut h = self.clone();
+        h.endian = src_h.endian;
+        h.encoding = encoding;
+        h.byte_skip = None;
+        h.line_skip = None;
+
+        write_bytes(&mut h, out, &bytes, attached, false)
+    }
+
+    /// sets the centering of all axes to 'centering'
+    pub fn set_uniform_centering(&mut self, centering:Centering) {
+        self.centerings = Some(Centerings::new(centering, self.dimension.dim()));
+    }
+
+    /// returns the units of the data values, if set
+    pub fn sample_units(&self) -> Option<&str> {
+        self.sample_units.as_ref().map(|u| u.units())
+    }
+
+    /// sets the units of the data values. The units must be a single token, such as 'HU'
+    pub fn set_sample_units(&mut self, units:&str) -> Result<(),NrrdError> {
+        self.sample_units = Some(SampleUnits::new(units)?);
+        Ok(())
+    }
+
+    /// parses the value of a key-value pair. Returns None if the key isn't present
+    pub fn get_kv_parsed<T:FromStr>(&self, key:&str) -> Option<Result<T, T::Err>> {
+        self.key_vals.get(key).map(|v| v.val.trim().parse::<T>())
+    }
+
+    /// parses a key-value pair holding a list of values, such as a DWMRI gradient direction. Values
+    /// are split on 'separator', or on whitespace if it is None. Returns None if the key isn't present
+    pub fn get_kv_vec<T:FromStr>(&self, key:&str, separator:Option<char>) -> Option<Result<Vec<T>, T::Err>> {
+        let val = self.key_vals.get(key)?.val.trim();
+        let parsed = match separator {
+            Some(sep) => val.split(sep).map(|x| x.trim().parse::<T>()).collect(),
+            None => val.split_whitespace().map(|x| x.parse::<T>()).collect(),
+        };
+        Some(parsed)
+    }
+
+    /// sets a key-value pair, replacing any existing value for the key
+    pub fn set_kv<T:Display>(&mut self, key:&str, value:T) {
+        self.key_vals.insert(key.to_string(), Value::new(&value.to_string()));
+    }
+
+    /// sets a key-value pair like 'set_kv', failing if the key contains ':=' or either part spans
+    /// more than one line. Key-values are written sorted by key
+    pub fn set_key_value(&mut self, key:&str, value:&str) -> Result<(),NrrdError> {
+        check_key_value(key, value)?;
+        self.key_vals.insert(key.to_string(), Value::new(value));
+        self.duplicate_key_vals.remove(key);
+        Ok(())
+    }
+
+    /// adds a comment after any existing ones, failing if it spans more than one line. Comments are
+    /// written in the order they were added
+    pub fn add_comment(&mut self, comment:&str) -> Result<(),NrrdError> {
+        let comment = Comment {val: comment.to_string()}.to_string();
+        check_comment(&comment)?;
+        self.comments.push(comment);
+        Ok(())
+    }
+
+    /// formats the header like 'Display', but with the lines in the order they had in the header
+    /// this was parsed from, including the interleaving of comments and key-values. Comments are
+    /// matched by their index in 'comments', so a comment written next to a field stays next to it.
+    /// Lines that weren't in the original header are placed before the data file, which is always
+    /// last
+    pub fn write_preserving_order(&self) -> String {
+        let canonical = self.to_string();
+        let lines:Vec<&str> = canonical.lines().collect();
+        let tags = header_line_tags(&lines);
+        let data_file = HeaderLine::Field(DataFile::patterns()[0]);
+        let source_lines:Vec<&str> = self.source_lines.iter().map(|l| l.as_str()).collect();
+        let line_order = header_line_tags(&source_lines);
+
+        let mut ordered:Vec<(usize,&str,&HeaderLine)> = lines.iter().cloned().zip(&tags).enumerate()
+            .map(|(i,(line,tag))| (i,line,tag))
+            .collect();
+        ordered.sort_by_key(|(i,_,tag)|{
+            if **tag == data_file {
+                (2,*i)
+            }else if let Some(pos) = line_order.iter().position(|t| t == *tag) {
+                (0,pos)
+            }else {
+                (1,*i)
+            }
+        });
 
+        let mut out = String::with_capacity(canonical.len());
+        for (_,line,_) in ordered {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
     }
 
     fn expected_bytes(&self) -> usize {
@@ -430,7 +3879,7 @@ impl NRRD {
 
     /// returns the size of each element as determined by 'type' and 'block size' if necessary
     pub fn element_size(&self) -> usize {
-        if let DType::block = self.dtype {
+        if self.dtype.is_block() {
             let bs = self.block_size.as_ref().expect("block size must be defined for data type of 'block'");
             bs.size()
         }else {
@@ -438,9 +3887,191 @@ impl NRRD {
         }
     }
 
+    /// returns the number of elements in each of the 'n_files' detached data files. Without a
+    /// sub-dim the elements are split evenly across the files. With one, each file holds the first
+    /// 'sub_dim' axes and the number of files must match the size of the remaining axes. Either way
+    /// the files hold contiguous runs of the data, in file order
+    pub fn elements_per_file(&self, n_files:usize) -> Result<usize,NrrdError> {
+        let n_elements = self.sizes.n_elements();
+        match self.data_file.as_ref().and_then(|df| df.sub_dim()) {
+            Some(sub_dim) => {
+                let shape = self.shape();
+                if sub_dim == 0 || sub_dim > shape.len() {
+                    return Err(NrrdError::InvalidValue {field: "data file", value: format!("sub-dim {sub_dim}")});
+                }
+                let expected_files = shape[sub_dim..].iter().product::<usize>();
+                if n_files != expected_files {
+                    return Err(NrrdError::SubDimMismatch {sub_dim, expected_files, n_files});
+                }
+                Ok(shape[..sub_dim].iter().product())
+            }
+            None => {
+                if n_files == 0 || !n_elements.is_multiple_of(n_files) {
+                    return Err(NrrdError::UnevenFileSplit {n_files, n_elements});
+                }
+                Ok(n_elements / n_files)
+            }
+        }
+    }
+
+    /// reverses the byte order of every element in a raw data buffer according to 'type'. Single
+    /// byte types and blocks are left as they are. The header's 'endian' is not changed, see
+    /// 'convert_endian' for that
+    pub fn swap_bytes_in_place(&self, bytes:&mut [u8]) {
+        let size = self.dtype.size();
+        if self.dtype.is_block() || size < 2 {
+            return;
+        }
+        assert!(bytes.len().is_multiple_of(size), "buffer length must be a multiple of the type size ({size})");
+        // a fixed element size lets the compiler batch the swaps into vector shuffles
+        match size {
+            2 => swap_elements::<2>(bytes),
+            4 => swap_elements::<4>(bytes),
+            8 => swap_elements::<8>(bytes),
+            _ => bytes.chunks_exact_mut(size).for_each(|element| element.reverse()),
+        }
+    }
+
+    /// converts a raw data buffer described by this header to the target byte order and updates
+    /// 'endian' to match. Nothing is swapped if the data is already in that order
+    pub fn convert_endian(&mut self, bytes:&mut [u8], target:Endian) {
+        if self.endian != target {
+            self.swap_bytes_in_place(bytes);
+            self.endian = target;
+        }
+    }
+
+    /// rewrites the header in a canonical form, for headers written by other tools. Kinds,
+    /// centerings, space and encoding names are already matched case-insensitively when a header is
+    /// parsed, and NaN values are always written as 'nan'. On top of that this:
+    /// - drops 'space dimension' when 'space' is given, since the space implies it
+    /// - sets 'space dimension' from the length of the space directions when neither is given
+    /// - drops 'spacings' when every axis with a spacing has a space direction of the same length,
+    ///   as the directions already hold that information. Conflicting spacings are kept
+    /// - trims whitespace from the ends of the content, key-value values and comments
+    pub fn normalize(&mut self) {
+
+        if self.space.is_some() {
+            self.space_dimension = None;
+        }else if self.space_dimension.is_none() {
+            let dim = self.space_directions.as_ref()
+                .and_then(|sd| sd.vectors().iter().flatten().next().map(|v| v.values().len()));
+            self.space_dimension = dim.map(SpaceDimension::new);
+        }
+
+        if let Some(spacings) = &self.spacings {
+            let redundant = spacings.values().iter().enumerate().all(|(axis,&spacing)| {
+                if spacing.is_nan() {
+                    return true;
+                }
+                self.space_direction(axis).is_some_and(|dir| {
+                    let norm = dir.iter().map(|d| d * d).sum::<f64>().sqrt();
+                    (norm - spacing.abs()).abs() <= 1e-9 * norm.max(1.)
+                })
+            });
+            if redundant {
+                self.spacings = None;
+            }
+        }
+
+        if let Some(content) = &mut self.content {
+            *content = Content::new(content.text().trim());
+        }
+        for value in self.key_vals.values_mut() {
+            value.val = value.val.trim().to_string();
+        }
+        for comment in self.comments.iter_mut() {
+            *comment = comment.trim_end().to_string();
+        }
+    }
+
+    /// checks that there is at least one axis, that every per-axis field has one entry per axis,
+    /// and that every space field has one entry per space axis. The space dimension is taken from
+    /// 'space dimension' or implied by 'space'. A single value is stored as one axis of size 1, as
+    /// the format has no 0-dimensional arrays
+    pub fn validate(&self) -> Result<(),NrrdError> {
+
+        let dim = self.dimension.dim();
+        if dim == 0 {
+            return Err(NrrdError::InvalidValue {field: "dimension", value: dim.to_string()});
+        }
+
+        check_len("sizes", dim, self.sizes.shape().len())?;
+
+        if let Some(data_file) = &self.data_file {
+            data_file.check()?;
+        }
+
+        for comment in &self.comments {
+            check_comment(comment)?;
+        }
+        for (key,value) in &self.key_vals {
+            check_key_value(key, &value.val)?;
+        }
+
+        if let Some(spacings) = &self.spacings {
+            check_len("spacings", dim, spacings.len())?;
+        }
+        if let Some(thicknesses) = &self.thicknesses {
+            check_len("thicknesses", dim, thicknesses.len())?;
+        }
+        if let Some(axis_mins) = &self.axis_mins {
+            check_len("axis mins", dim, axis_mins.len())?;
+        }
+        if let Some(axis_maxs) = &self.axis_maxs {
+            check_len("axis maxs", dim, axis_maxs.len())?;
+        }
+        if let Some(centerings) = &self.centerings {
+            check_len("centerings", dim, centerings.len())?;
+        }
+        if let Some(labels) = &self.labels {
+            check_len("labels", dim, labels.len())?;
+        }
+        if let Some(units) = &self.units {
+            check_len("units", dim, units.len())?;
+        }
+        if let Some(kinds) = &self.kinds {
+            check_len("kinds", dim, kinds.len())?;
+        }
+        if let Some(space_directions) = &self.space_directions {
+            check_len("space directions", dim, space_directions.len())?;
+        }
+
+        let space_dim = self.space_dimension.as_ref().map(|sd| sd.dim())
+            .or(self.space.map(|s| s.dim()));
+
+        if let Some(space_dim) = space_dim {
+
+            if let Some(space) = &self.space {
+                check_len("space dimension", space.dim(), space_dim)?;
+            }
+            if let Some(space_units) = &self.space_units {
+                check_len("space units", space_dim, space_units.len())?;
+            }
+            if let Some(space_origin) = &self.space_origin {
+                check_len("space origin", space_dim, space_origin.len())?;
+            }
+            if let Some(space_directions) = &self.space_directions {
+                for dir in space_directions.directions.iter().flatten() {
+                    check_len("space directions", space_dim, dir.len())?;
+                }
+            }
+            if let Some(measurement_frame) = &self.measurement_frame {
+                check_len("measurement frame", space_dim, measurement_frame.vectors().len())?;
+                for v in measurement_frame.vectors() {
+                    check_len("measurement frame", space_dim, v.len())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn from_lines_full(lines:&mut Vec<&str>) -> NRRD {
 
+        let source_lines = lines.iter().map(|l| l.to_string()).collect();
         let mut h = Self::from_lines_minimal(lines);
+        h.source_lines = source_lines;
 
         h.content = read_header_def(lines);
         h.min = read_header_def(lines);
@@ -466,8 +4097,10 @@ impl NRRD {
         h.space_units = read_header_def(lines);
         h.space_origin = read_header_def(lines);
         h.space_directions = read_header_def(lines);
+        h.measurement_frame = read_header_def(lines);
+        h.reconcile_space_directions();
 
-        h.key_vals = read_key_values(lines);
+        (h.key_vals, h.duplicate_key_vals) = read_key_values(lines);
 
         h.comments = read_comments(lines);
 
@@ -477,6 +4110,23 @@ impl NRRD {
         h
     }
 
+    /// same as 'from_lines_full', but returns an error if any line remains that is not a recognized
+    /// field, key-value pair or comment. This catches typos in field names that would otherwise be
+    /// silently dropped
+    pub fn from_lines_strict(lines:&mut Vec<&str>) -> Result<NRRD,NrrdError> {
+        check_duplicate_fields(lines)?;
+        let h = Self::from_lines_full(lines);
+        if !lines.is_empty() {
+            return Err(NrrdError::UnrecognizedLines(lines.iter().map(|l| l.to_string()).collect()));
+        }
+        if !h.duplicate_key_vals.is_empty() {
+            let mut keys:Vec<String> = h.duplicate_key_vals.keys().cloned().collect();
+            keys.sort();
+            return Err(NrrdError::DuplicateKeys(keys));
+        }
+        Ok(h)
+    }
+
     /// construct a minimal NHDR from a string
     pub fn from_lines_minimal(lines:&mut Vec<&str>) -> NRRD {
 
@@ -486,14 +4136,19 @@ impl NRRD {
         let dimension:Dimension = read_header_def(lines).expect("failed to get dimension field");
         let dtype:DType = read_header_def(lines).expect("failed to get dtype field");
 
-        let block_size:Option<BlockSize> = if dtype == DType::block {
+        let block_size:Option<BlockSize> = if dtype.is_block() {
             Some(read_header_def(lines).expect("failed to get block size field"))
         }else {
             None
         };
 
         let encoding:Encoding = read_header_def(lines).expect("failed to get encoding field");
-        let endian:Endian = read_header_def(lines).expect("failed to get endian field");
+        // text data has no byte order, so the field is optional for it
+        let endian:Endian = match read_header_def(lines) {
+            Some(endian) => endian,
+            None if encoding == Encoding::txt => Endian::native(),
+            None => panic!("failed to get endian field"),
+        };
         let sizes:Sizes = read_header_def(lines).expect("failed to get sizes field");
 
 
@@ -527,10 +4182,13 @@ impl NRRD {
             space_units: None,
             space_origin: None,
             space_directions: None,
+            measurement_frame: None,
 
             key_vals: HashMap::new(),
+            duplicate_key_vals: HashMap::new(),
 
             comments: vec![],
+            source_lines: vec![],
 
             data_file: None,
         }
@@ -538,6 +4196,102 @@ impl NRRD {
     }
 }
 
+/// a header field that differs between two headers, with the field as it would be written in each.
+/// The value is None where a header doesn't have the field
+#[derive(Debug,Clone,PartialEq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+/// adds a diff for the field if the values don't match
+fn diff_field<F:Display + PartialEq>(diffs:&mut Vec<FieldDiff>, field:&str, left:Option<&F>, right:Option<&F>) {
+    if left != right {
+        diffs.push(FieldDiff {
+            field: field.to_string(),
+            left: left.map(|f| f.to_string()),
+            right: right.map(|f| f.to_string()),
+        });
+    }
+}
+
+impl NRRD {
+
+    /// lists the fields that differ between the headers, in header order. Floating point values
+    /// compare NaN as equal to NaN. The byte order is ignored if both headers have 1-byte elements,
+    /// where it has no meaning. Key-values are compared by key, and the comments as a whole. The
+    /// lines a header was parsed from and values of repeated keys are not compared
+    pub fn diff(&self, other:&NRRD) -> Vec<FieldDiff> {
+        let mut d = vec![];
+        diff_field(&mut d, "magic", Some(&self.magic), Some(&other.magic));
+        diff_field(&mut d, "dimension", Some(&self.dimension), Some(&other.dimension));
+        diff_field(&mut d, "type", Some(&self.dtype), Some(&other.dtype));
+        diff_field(&mut d, "block size", self.block_size.as_ref(), other.block_size.as_ref());
+        diff_field(&mut d, "encoding", Some(&self.encoding), Some(&other.encoding));
+        let single_byte = |h:&NRRD| !h.dtype.is_block() && h.dtype.size() == 1;
+        if !(single_byte(self) && single_byte(other)) {
+            diff_field(&mut d, "endian", Some(&self.endian), Some(&other.endian));
+        }
+        diff_field(&mut d, "content", self.content.as_ref(), other.content.as_ref());
+        diff_field(&mut d, "min", self.min.as_ref(), other.min.as_ref());
+        diff_field(&mut d, "max", self.max.as_ref(), other.max.as_ref());
+        diff_field(&mut d, "old min", self.old_min.as_ref(), other.old_min.as_ref());
+        diff_field(&mut d, "old max", self.old_max.as_ref(), other.old_max.as_ref());
+        diff_field(&mut d, "data file", self.data_file.as_ref(), other.data_file.as_ref());
+        diff_field(&mut d, "line skip", self.line_skip.as_ref(), other.line_skip.as_ref());
+        diff_field(&mut d, "byte skip", self.byte_skip.as_ref(), other.byte_skip.as_ref());
+        diff_field(&mut d, "sample units", self.sample_units.as_ref(), other.sample_units.as_ref());
+
+        diff_field(&mut d, "sizes", Some(&self.sizes), Some(&other.sizes));
+        diff_field(&mut d, "spacings", self.spacings.as_ref(), other.spacings.as_ref());
+        diff_field(&mut d, "thicknesses", self.thicknesses.as_ref(), other.thicknesses.as_ref());
+        diff_field(&mut d, "axis mins", self.axis_mins.as_ref(), other.axis_mins.as_ref());
+        diff_field(&mut d, "axis maxs", self.axis_maxs.as_ref(), other.axis_maxs.as_ref());
+        diff_field(&mut d, "centerings", self.centerings.as_ref(), other.centerings.as_ref());
+        diff_field(&mut d, "labels", self.labels.as_ref(), other.labels.as_ref());
+        diff_field(&mut d, "units", self.units.as_ref(), other.units.as_ref());
+        diff_field(&mut d, "kinds", self.kinds.as_ref(), other.kinds.as_ref());
+
+        diff_field(&mut d, "space", self.space.as_ref(), other.space.as_ref());
+        diff_field(&mut d, "space dimension", self.space_dimension.as_ref(), other.space_dimension.as_ref());
+        diff_field(&mut d, "space units", self.space_units.as_ref(), other.space_units.as_ref());
+        diff_field(&mut d, "space origin", self.space_origin.as_ref(), other.space_origin.as_ref());
+        diff_field(&mut d, "space directions", self.space_directions.as_ref(), other.space_directions.as_ref());
+        diff_field(&mut d, "measurement frame", self.measurement_frame.as_ref(), other.measurement_frame.as_ref());
+
+        let mut keys:Vec<&String> = self.key_vals.keys().chain(other.key_vals.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let (left, right) = (self.key_vals.get(key), other.key_vals.get(key));
+            if left != right {
+                d.push(FieldDiff {
+                    field: key.clone(),
+                    left: left.map(|v| format!("{key}{v}")),
+                    right: right.map(|v| format!("{key}{v}")),
+                });
+            }
+        }
+
+        if self.comments != other.comments {
+            d.push(FieldDiff {
+                field: "comments".to_string(),
+                left: Some(self.comments.join("\n")),
+                right: Some(other.comments.join("\n")),
+            });
+        }
+        d
+    }
+}
+
+/// headers are equal when 'diff' finds no differences
+impl PartialEq for NRRD {
+    fn eq(&self, other:&Self) -> bool {
+        self.diff(other).is_empty()
+    }
+}
+
 impl Display for NRRD {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 
@@ -643,6 +4397,10 @@ impl Display for NRRD {
             writeln!(f,"{space_directions}")?;
         }
 
+        if let Some(measurement_frame) = &self.measurement_frame {
+            writeln!(f,"{measurement_frame}")?;
+        }
+
         let mut keyvals:Vec<(String,Value)> = self.key_vals.iter().map(|(key,value)| (key.clone(),value.clone()) ).collect();
         keyvals.sort_by_key(|(a,_)|a.clone());
         for (key,val) in keyvals {
@@ -658,6 +4416,40 @@ impl Display for NRRD {
 }
 
 
+/// joins relative data file paths to the directory of the header
+fn resolve_data_paths(datafile:&DataFile, header_path:&Path) -> Vec<PathBuf> {
+    resolve_data_paths_in(datafile, header_path.parent().unwrap())
+}
+
+/// joins relative data file paths to a base directory
+fn resolve_data_paths_in(datafile:&DataFile, base_dir:&Path) -> Vec<PathBuf> {
+    datafile.paths().into_iter().map(|p|{
+        if p.is_relative() {
+            base_dir.join(p)
+        }else {
+            p
+        }
+    }).collect()
+}
+
+/// returns the paths if they all exist, otherwise an error listing the missing ones
+fn existing_paths(paths:Vec<PathBuf>) -> Result<Vec<PathBuf>,NrrdError> {
+    let missing:Vec<_> = paths.iter().filter(|p| !p.exists()).cloned().collect();
+    if missing.is_empty() {
+        Ok(paths)
+    }else {
+        Err(NrrdError::MissingDataFiles(missing))
+    }
+}
+
+fn check_len(field:&'static str, expected:usize, found:usize) -> Result<(),NrrdError> {
+    if expected != found {
+        Err(NrrdError::FieldLength {field, expected, found})
+    }else {
+        Ok(())
+    }
+}
+
 fn read_header_def<T:HeaderDef + FromStr>(header_lines: &mut Vec<&str>) -> Option<T> {
     let found = header_lines.iter().enumerate().find_map(|(i,x)|{
         if T::matches(x) {
@@ -712,19 +4504,104 @@ fn read_data_file(header_lines: &mut Vec<&str>) -> Option<DataFile> {
     found.map(|(_,df)| df)
 }
 
-fn read_key_values(header_lines: &mut Vec<&str>) -> HashMap<String, Value> {
+/// returns the key-value pairs, keeping the last value of duplicated keys, along with every value
+/// of the duplicated keys
+fn read_key_values(header_lines: &mut Vec<&str>) -> (HashMap<String, Value>, HashMap<String, Vec<Value>>) {
     let mut keyvals = HashMap::<String,Value>::new();
+    let mut duplicates = HashMap::<String,Vec<Value>>::new();
     header_lines.retain(|x| {
         if Value::matches_key_value(x) {
             let key =Value::key(x);
             let value = Value::from_str(x).expect("failed to parse value");
-            keyvals.insert(key, value);
+            if let Some(previous) = keyvals.insert(key.clone(), value.clone()) {
+                duplicates.entry(key).or_insert_with(|| vec![previous]).push(value);
+            }
             false
         }else {
             true
         }
     });
-    keyvals
+    (keyvals, duplicates)
+}
+
+/// returns an error if the magic line isn't a supported 'NRRDxxxx' version
+fn check_magic(lines:&[&str]) -> Result<(),NrrdError> {
+    match lines.iter().find(|l| Magic::matches(l)) {
+        Some(line) if Magic::from_str(line).is_err() => {
+            Err(NrrdError::InvalidValue {field: "magic", value: line.to_string()})
+        }
+        _=> Ok(()),
+    }
+}
+
+/// returns an error naming the fields that appear more than once. Only the lines up to the data
+/// file field are checked, since the files of a LIST follow it
+fn check_duplicate_fields(lines:&[&str]) -> Result<(),NrrdError> {
+    let data_file = HeaderLine::Field(DataFile::patterns()[0]);
+    let tags = header_line_tags(lines);
+    let n = tags.iter().position(|t| *t == data_file).map_or(tags.len(), |i| i + 1);
+
+    let mut seen = vec![];
+    let mut duplicates = vec![];
+    for tag in &tags[..n] {
+        if let HeaderLine::Field(pattern) = tag {
+            let field = pattern.trim_end_matches(": ");
+            if seen.contains(&field) {
+                if !duplicates.contains(&field) {
+                    duplicates.push(field);
+                }
+            }else {
+                seen.push(field);
+            }
+        }
+    }
+
+    if duplicates.is_empty() {
+        Ok(())
+    }else {
+        Err(NrrdError::DuplicateFields(duplicates))
+    }
+}
+
+/// returns the field pattern of a header line if it is a field of type T
+fn field_tag<T:HeaderDef>(line:&str) -> Option<&'static str> {
+    T::matches(line).then(|| T::patterns()[0])
+}
+
+/// identifies each header line. Lines following the data file field (the files of a LIST) are
+/// tagged as the data file. Unrecognized lines are skipped
+fn header_line_tags(lines:&[&str]) -> Vec<HeaderLine> {
+    let fields:[fn(&str) -> Option<&'static str>;30] = [
+        field_tag::<Magic>, field_tag::<Dimension>, field_tag::<DType>, field_tag::<BlockSize>,
+        field_tag::<Encoding>, field_tag::<Endian>, field_tag::<Content>, field_tag::<Min>,
+        field_tag::<Max>, field_tag::<OldMin>, field_tag::<OldMax>, field_tag::<LineSkip>,
+        field_tag::<ByteSkip>, field_tag::<SampleUnits>, field_tag::<Sizes>, field_tag::<Spacings>,
+        field_tag::<Thicknesses>, field_tag::<AxisMins>, field_tag::<AxisMaxs>, field_tag::<Centerings>,
+        field_tag::<Labels>, field_tag::<Units>, field_tag::<Kinds>, field_tag::<Space>,
+        field_tag::<SpaceDimension>, field_tag::<SpaceUnits>, field_tag::<SpaceOrigin>,
+        field_tag::<SpaceDirections>, field_tag::<MeasurementFrame>, field_tag::<DataFile>,
+    ];
+
+    let mut tags = Vec::with_capacity(lines.len());
+    let mut n_comments = 0;
+    for (i,line) in lines.iter().enumerate() {
+        if Comment::matches(line) {
+            // empty comments are dropped when parsing
+            if Comment::from_str(line).is_ok() {
+                tags.push(HeaderLine::Comment(n_comments));
+                n_comments += 1;
+            }
+        }else if Value::matches_key_value(line) {
+            tags.push(HeaderLine::KeyValue(Value::key(line)));
+        }else if let Some(pattern) = fields.iter().find_map(|tag| tag(line)) {
+            tags.push(HeaderLine::Field(pattern));
+            if pattern == DataFile::patterns()[0] {
+                tags.extend((i + 1..lines.len()).map(|_| HeaderLine::Field(pattern)));
+                break;
+            }
+        }
+    }
+    tags
 }
 
 fn read_comments(header_lines: &mut Vec<&str>) -> Vec<String> {
@@ -0,0 +1,284 @@
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use crate::error::NrrdError;
+use crate::header_defs::{DType, NRRDType, Space};
+use crate::NRRD;
+
+/// size of a NIfTI-1 header in bytes
+const NIFTI_HEADER_SIZE: usize = 348;
+
+/// data offset of a single-file (.nii) image: the header followed by 4 bytes of empty extension flags
+const NIFTI_VOX_OFFSET: usize = 352;
+
+/// 'scanner-based anatomical coordinates' transform code
+const NIFTI_XFORM_SCANNER_ANAT: i16 = 1;
+
+/// returns the NIfTI-1 datatype code for a data type
+fn nifti_datatype(dtype:DType) -> Option<i16> {
+    match dtype {
+        DType::uint8 => Some(2),
+        DType::int16 => Some(4),
+        DType::int32 => Some(8),
+        DType::f32 => Some(16),
+        DType::f64 => Some(64),
+        DType::int8 => Some(256),
+        DType::uint16 => Some(512),
+        DType::uint32 => Some(768),
+        DType::int64 => Some(1024),
+        DType::uint64 => Some(1280),
+        _=> None,
+    }
+}
+
+/// returns the NIfTI-1 spatial unit code for a space unit
+fn nifti_space_units(units:&str) -> u8 {
+    match units {
+        "m" => 1,
+        "mm" => 2,
+        "um" | "micron" | "microns" => 3,
+        _=> 0,
+    }
+}
+
+/// returns the signs that take each world axis of the space to RAS+, the convention NIfTI uses for
+/// its transforms
+fn ras_signs(space:Space) -> Option<[f64;3]> {
+    match space {
+        Space::RAS | Space::RAST | Space::scanner_xyz | Space::scanner_xyz_time => Some([1.,1.,1.]),
+        Space::LAS | Space::LAST => Some([-1.,1.,1.]),
+        Space::LPS | Space::LPST => Some([-1.,-1.,1.]),
+        _=> None,
+    }
+}
+
+/// a NIfTI-1 image built from a NRRD, ready to be written as a single .nii file
+#[derive(Debug,Clone)]
+pub struct NiftiImage {
+    /// size of each dimension, fastest first
+    pub dims: Vec<usize>,
+    /// NIfTI-1 datatype code
+    pub datatype: i16,
+    /// bits per voxel
+    pub bitpix: i16,
+    /// voxel size of each dimension
+    pub pixdim: Vec<f64>,
+    /// rows of the voxel-to-RAS+ transform, or None if the NRRD has no orientation
+    pub srow: Option<[[f64;4];3]>,
+    /// NIfTI-1 spatial unit code
+    pub space_units: u8,
+    /// voxel data in native byte order
+    data: Vec<u8>,
+}
+
+impl NiftiImage {
+
+    /// returns the voxel data bytes
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// returns the quaternion parameters (b, c, d) and qfac of the rotation in the transform,
+    /// following the method of the NIfTI-1 reference library
+    pub fn quatern(&self) -> Option<([f64;3], f64)> {
+        let srow = self.srow?;
+
+        // normalized columns of the rotation/scaling part
+        let mut r = [[0.;3];3];
+        for col in 0..3 {
+            let norm = (0..3).map(|row| srow[row][col].powi(2)).sum::<f64>().sqrt();
+            let norm = if norm == 0. {1.} else {norm};
+            for row in 0..3 {
+                r[row][col] = srow[row][col] / norm;
+            }
+        }
+
+        // a left-handed rotation is stored as a right-handed one with the third axis flipped
+        let det = r[0][0] * (r[1][1] * r[2][2] - r[1][2] * r[2][1])
+            - r[0][1] * (r[1][0] * r[2][2] - r[1][2] * r[2][0])
+            + r[0][2] * (r[1][0] * r[2][1] - r[1][1] * r[2][0]);
+        let qfac = if det < 0. {
+            (0..3).for_each(|row| r[row][2] = -r[row][2]);
+            -1.
+        }else {
+            1.
+        };
+
+        let trace = r[0][0] + r[1][1] + r[2][2] + 1.;
+        let (a, mut b, mut c, mut d);
+        if trace > 0.5 {
+            a = 0.5 * trace.sqrt();
+            b = 0.25 * (r[2][1] - r[1][2]) / a;
+            c = 0.25 * (r[0][2] - r[2][0]) / a;
+            d = 0.25 * (r[1][0] - r[0][1]) / a;
+        }else {
+            let xd = 1. + r[0][0] - (r[1][1] + r[2][2]);
+            let yd = 1. + r[1][1] - (r[0][0] + r[2][2]);
+            let zd = 1. + r[2][2] - (r[0][0] + r[1][1]);
+            if xd > 1. {
+                b = 0.5 * xd.sqrt();
+                c = 0.25 * (r[0][1] + r[1][0]) / b;
+                d = 0.25 * (r[0][2] + r[2][0]) / b;
+                a = 0.25 * (r[2][1] - r[1][2]) / b;
+            }else if yd > 1. {
+                c = 0.5 * yd.sqrt();
+                b = 0.25 * (r[0][1] + r[1][0]) / c;
+                d = 0.25 * (r[1][2] + r[2][1]) / c;
+                a = 0.25 * (r[0][2] - r[2][0]) / c;
+            }else {
+                d = 0.5 * zd.sqrt();
+                b = 0.25 * (r[0][2] + r[2][0]) / d;
+                c = 0.25 * (r[1][2] + r[2][1]) / d;
+                a = 0.25 * (r[1][0] - r[0][1]) / d;
+            }
+            if a < 0. {
+                b = -b;
+                c = -c;
+                d = -d;
+            }
+        }
+        Some(([b,c,d], qfac))
+    }
+
+    /// encodes the 348-byte header in native byte order
+    fn header_bytes(&self) -> Vec<u8> {
+        let mut h = vec![0u8;NIFTI_HEADER_SIZE];
+        let mut put = |offset:usize, bytes:&[u8]| h[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        put(0, &(NIFTI_HEADER_SIZE as i32).to_ne_bytes());
+        // 'regular' must be 'r'
+        put(38, b"r");
+
+        let mut dim = [1i16;8];
+        dim[0] = self.dims.len() as i16;
+        self.dims.iter().enumerate().for_each(|(i,&d)| dim[i + 1] = d as i16);
+        for (i,d) in dim.iter().enumerate() {
+            put(40 + 2 * i, &d.to_ne_bytes());
+        }
+
+        put(70, &self.datatype.to_ne_bytes());
+        put(72, &self.bitpix.to_ne_bytes());
+
+        let quatern = self.quatern();
+        let mut pixdim = [1f32;8];
+        pixdim[0] = quatern.map(|(_,qfac)| qfac as f32).unwrap_or(1.);
+        self.pixdim.iter().enumerate().for_each(|(i,&p)| pixdim[i + 1] = p as f32);
+        for (i,p) in pixdim.iter().enumerate() {
+            put(76 + 4 * i, &p.to_ne_bytes());
+        }
+
+        put(108, &(NIFTI_VOX_OFFSET as f32).to_ne_bytes());
+        // scl_slope of 1 and scl_inter of 0 store the values unscaled
+        put(112, &1f32.to_ne_bytes());
+        put(123, &[self.space_units]);
+
+        put(148, b"written by nrrd-rs");
+
+        if let (Some(srow), Some((q, _))) = (self.srow, quatern) {
+            put(252, &NIFTI_XFORM_SCANNER_ANAT.to_ne_bytes());
+            put(254, &NIFTI_XFORM_SCANNER_ANAT.to_ne_bytes());
+            for (i,x) in q.iter().chain([srow[0][3], srow[1][3], srow[2][3]].iter()).enumerate() {
+                put(256 + 4 * i, &(*x as f32).to_ne_bytes());
+            }
+            for (row,values) in srow.iter().enumerate() {
+                for (col,x) in values.iter().enumerate() {
+                    put(280 + 16 * row + 4 * col, &(*x as f32).to_ne_bytes());
+                }
+            }
+        }
+
+        put(344, b"n+1\0");
+        h
+    }
+
+    /// writes the image as a single .nii file
+    pub fn write(&self, filepath:impl AsRef<Path>) -> io::Result<()> {
+        let mut f = File::create(filepath)?;
+        f.write_all(&self.header_bytes())?;
+        // no extensions
+        f.write_all(&[0u8;NIFTI_VOX_OFFSET - NIFTI_HEADER_SIZE])?;
+        f.write_all(&self.data)?;
+        Ok(())
+    }
+}
+
+impl NRRD {
+
+    /// builds a NIfTI-1 image from the data. The spatial axes must be the first (fastest) axes, with
+    /// at most 3 of them, and any remaining axes (such as time) follow.
+    ///
+    /// Orientation: NIfTI transforms always map voxel indices to RAS+ world coordinates (x increases
+    /// to the right, y anterior, z superior). NRRD space directions are expressed in the header's
+    /// 'space', so for LPS (the DICOM/ITK convention) the x and y components of the directions and
+    /// origin are negated, and for LAS the x components are. RAS and scanner-xyz are taken as-is.
+    /// The voxel axes are not reordered or flipped, so a left-handed index frame is kept and
+    /// reported through qfac = -1. Other spaces are rejected. Without space directions or a space
+    /// origin the image has no transform and the voxel sizes come from 'spacings'
+    pub fn to_nifti<T:NRRDType>(&self, data:&[T]) -> Result<NiftiImage,NrrdError> {
+
+        if self.dtype.is_block() {
+            return Err(NrrdError::Unsupported("block data can't be stored in NIfTI".to_string()));
+        }
+        let datatype = nifti_datatype(T::dtype()).ok_or_else(|| {
+            NrrdError::Unsupported(format!("data type '{}' has no NIfTI equivalent", T::dtype().name()))
+        })?;
+
+        let n = self.sizes.n_elements();
+        if data.len() != n {
+            return Err(NrrdError::DataLength {expected: n, found: data.len()});
+        }
+
+        let dims = self.shape().to_vec();
+        if dims.is_empty() || dims.len() > 7 {
+            return Err(NrrdError::Unsupported(format!("NIfTI supports 1 to 7 dimensions, not {}", dims.len())));
+        }
+
+        let spatial = self.spatial_axes();
+        if spatial.len() > 3 || spatial.iter().enumerate().any(|(i,&a)| i != a) {
+            return Err(NrrdError::Unsupported(format!(
+                "spatial axes must be the first axes for NIfTI, found {spatial:?}"
+            )));
+        }
+
+        let mut pixdim:Vec<f64> = (0..dims.len()).map(|axis|{
+            self.spacings.as_ref()
+                .and_then(|s| s.get(axis))
+                .unwrap_or(1.)
+        }).collect();
+
+        let srow = match (self.affine(), self.space) {
+            (Some(affine), Some(space)) => {
+                let signs = ras_signs(space).ok_or_else(|| {
+                    NrrdError::Unsupported(format!("{space} can't be mapped to RAS"))
+                })?;
+                let mut srow = [[0.;4];3];
+                for row in 0..3 {
+                    for col in 0..4 {
+                        srow[row][col] = signs[row] * affine[row][col];
+                    }
+                }
+                // voxel sizes are the lengths of the direction vectors
+                for (axis,p) in pixdim.iter_mut().take(3).enumerate() {
+                    *p = (0..3).map(|row| srow[row][axis].powi(2)).sum::<f64>().sqrt();
+                }
+                Some(srow)
+            }
+            _=> None,
+        };
+
+        let space_units = self.space_units.as_ref()
+            .and_then(|u| u.values().first().map(|u| nifti_space_units(u)))
+            .unwrap_or(0);
+
+        Ok(NiftiImage {
+            dims,
+            datatype,
+            bitpix: (T::dtype().size() * 8) as i16,
+            pixdim,
+            srow,
+            space_units,
+            data: bytemuck::cast_slice(data).to_vec(),
+        })
+    }
+}
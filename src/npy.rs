@@ -0,0 +1,189 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use regex::Regex;
+use crate::header_defs::{DType, Endian, NRRDType};
+use crate::NRRD;
+
+/// magic string that starts every .npy file
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+/// the .npy preamble + header dict is padded to a multiple of this many bytes
+const NPY_ALIGN: usize = 64;
+
+/// returns the numpy type string (without the byte order character) for a data type
+fn npy_type_str(dtype:DType) -> Option<&'static str> {
+    match dtype {
+        DType::int8 => Some("i1"),
+        DType::uint8 => Some("u1"),
+        DType::int16 => Some("i2"),
+        DType::uint16 => Some("u2"),
+        DType::int32 => Some("i4"),
+        DType::uint32 => Some("u4"),
+        DType::int64 => Some("i8"),
+        DType::uint64 => Some("u8"),
+        DType::f32 => Some("f4"),
+        DType::f64 => Some("f8"),
+        #[cfg(feature = "f16")]
+        DType::f16 => Some("f2"),
+        DType::block => None,
+    }
+}
+
+/// returns the numpy 'descr' string for a data type with the given byte order
+fn npy_descr(dtype:DType, endian:Endian) -> Option<String> {
+    let t = npy_type_str(dtype)?;
+    let order = if dtype.size() == 1 {
+        '|'
+    }else {
+        match endian {
+            Endian::Big => '>',
+            Endian::Little => '<',
+        }
+    };
+    Some(format!("{order}{t}"))
+}
+
+/// returns the data type and byte order for a numpy 'descr' string
+fn parse_npy_descr(descr:&str) -> Option<(DType, Endian)> {
+    let (order, t) = descr.split_at_checked(1)?;
+    let endian = match order {
+        "<" => Endian::Little,
+        ">" => Endian::Big,
+        "|" | "=" => Endian::native(),
+        _=> return None,
+    };
+    let dtype = match t {
+        "i1" => DType::int8,
+        "u1" | "b1" => DType::uint8,
+        "i2" => DType::int16,
+        "u2" => DType::uint16,
+        "i4" => DType::int32,
+        "u4" => DType::uint32,
+        "i8" => DType::int64,
+        "u8" => DType::uint64,
+        "f4" => DType::f32,
+        "f8" => DType::f64,
+        #[cfg(feature = "f16")]
+        "f2" => DType::f16,
+        _=> return None,
+    };
+    Some((dtype, endian))
+}
+
+/// builds the padded .npy header dictionary for a C-ordered array
+fn npy_header(descr:&str, c_shape:&[usize]) -> Vec<u8> {
+    let shape = match c_shape {
+        [n] => format!("({n},)"),
+        _=> format!("({})", c_shape.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", ")),
+    };
+    let mut dict = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+    // magic (6) + version (2) + header length (2) + dict + newline must be aligned
+    let preamble = NPY_MAGIC.len() + 4;
+    let pad = (NPY_ALIGN - (preamble + dict.len() + 1) % NPY_ALIGN) % NPY_ALIGN;
+    dict.push_str(&" ".repeat(pad));
+    dict.push('\n');
+    dict.into_bytes()
+}
+
+/// writes the data to a NumPy .npy (version 1.0) file. NRRD stores the fastest axis first, which is
+/// the same memory layout as a C-ordered numpy array with the axes reversed, so the data is written
+/// as-is with the shape reversed. Data is written in native byte order
+pub fn write_npy<T:NRRDType>(filepath:impl AsRef<Path>, data:&[T], h:&NRRD) -> io::Result<()> {
+
+    let n = h.sizes.n_elements();
+    if data.len() != n {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("data length ({}) does not match number of elements in header ({n})", data.len())
+        ));
+    }
+
+    let descr = npy_descr(T::dtype(), Endian::native()).ok_or_else(||{
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} cannot be written to npy", T::dtype()))
+    })?;
+
+    let c_shape:Vec<usize> = h.shape().iter().rev().cloned().collect();
+    let header = npy_header(&descr, &c_shape);
+
+    let mut f = File::create(filepath)?;
+    f.write_all(NPY_MAGIC)?;
+    f.write_all(&[1, 0])?;
+    f.write_all(&(header.len() as u16).to_le_bytes())?;
+    f.write_all(&header)?;
+    f.write_all(bytemuck::cast_slice(data))?;
+    Ok(())
+}
+
+/// reads a NumPy .npy file into its raw data bytes and a NRRD header describing them. The numpy
+/// shape is reversed for C-ordered arrays so that the fastest axis comes first, as NRRD expects.
+/// Fortran-ordered arrays already store the first axis fastest, so their shape is kept as-is
+pub fn read_npy(filepath:impl AsRef<Path>) -> io::Result<(Vec<u8>, NRRD)> {
+
+    let invalid = |msg:String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut f = File::open(filepath)?;
+    let mut preamble = [0u8;8];
+    f.read_exact(&mut preamble)?;
+    if &preamble[0..6] != NPY_MAGIC {
+        return Err(invalid("not a npy file".to_string()));
+    }
+
+    // version 1.0 uses a 2-byte header length, versions 2.0 and 3.0 use 4 bytes
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0u8;2];
+            f.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8;4];
+            f.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        v => return Err(invalid(format!("unsupported npy version {v}"))),
+    };
+
+    let mut header = vec![0u8;header_len];
+    f.read_exact(&mut header)?;
+    let header = String::from_utf8(header).map_err(|e| invalid(e.to_string()))?;
+
+    let descr = Regex::new(r"'descr':\s*'([^']*)'").unwrap()
+        .captures(&header)
+        .map(|c| c[1].to_string())
+        .ok_or_else(|| invalid("npy header is missing 'descr'".to_string()))?;
+
+    let fortran_order = Regex::new(r"'fortran_order':\s*(True|False)").unwrap()
+        .captures(&header)
+        .map(|c| &c[1] == "True")
+        .ok_or_else(|| invalid("npy header is missing 'fortran_order'".to_string()))?;
+
+    let mut dims = Regex::new(r"'shape':\s*\(([^)]*)\)").unwrap()
+        .captures(&header)
+        .ok_or_else(|| invalid("npy header is missing 'shape'".to_string()))?[1]
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<usize>().map_err(|e| invalid(format!("invalid npy shape entry {s}: {e}"))))
+        .collect::<io::Result<Vec<usize>>>()?;
+
+    if !fortran_order {
+        dims.reverse();
+    }
+
+    let (dtype, endian) = parse_npy_descr(&descr)
+        .ok_or_else(|| invalid(format!("unsupported npy dtype {descr}")))?;
+
+    let mut h = NRRD::new_from_type_dims(dtype, &dims);
+    h.endian = endian;
+
+    let mut bytes = vec![];
+    f.read_to_end(&mut bytes)?;
+    let expected = dims.iter().product::<usize>() * dtype.size();
+    if bytes.len() != expected {
+        return Err(invalid(format!("expected {expected} data bytes in npy file but found {}", bytes.len())));
+    }
+
+    Ok((bytes, h))
+}
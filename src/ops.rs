@@ -0,0 +1,744 @@
+use num_traits::{Bounded, FromPrimitive, NumCast, ToPrimitive};
+use crate::error::NrrdError;
+use crate::header_defs::{Centering, DType, Dimension, Kind, Kinds, Labels, NRRDType, NrrdVec, OldMax, OldMin, PerAxis, SampleUnits, Sizes, SpaceOrigin};
+use crate::NRRD;
+use crate::volume::strides;
+
+/// magnitude and phase volumes with their headers
+type MagPhase = ((Vec<f32>, NRRD), (Vec<f32>, NRRD));
+
+/// tolerance used to decide if two volumes share a grid when comparing them
+const GRID_TOL:f64 = 1e-6;
+
+/// element-wise differences between two volumes
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct DiffStats {
+    /// largest absolute difference
+    pub max_abs: f64,
+    /// root mean square of the differences (the Frobenius norm over the square root of the number
+    /// of elements)
+    pub rms: f64,
+    /// number of elements that are not equal
+    pub n_differing: usize,
+}
+
+/// compares two volumes on the same grid element by element. Fails if the grids differ or the
+/// data doesn't match the headers
+pub fn volume_diff<T:NRRDType + FromPrimitive + ToPrimitive>(a:&[T], ha:&NRRD, b:&[T], hb:&NRRD) -> Result<DiffStats, NrrdError> {
+
+    for (data,h) in [(a,ha),(b,hb)] {
+        let n = h.sizes.n_elements();
+        if data.len() != n {
+            return Err(NrrdError::DataLength {expected: n, found: data.len()});
+        }
+    }
+
+    if !ha.same_grid(hb, GRID_TOL) {
+        return Err(NrrdError::GridMismatch);
+    }
+
+    let mut max_abs = 0f64;
+    let mut sum_sq = 0f64;
+    let mut n_differing = 0;
+    for (x,y) in a.iter().zip(b) {
+        let d = (x.to_f64().unwrap_or(f64::NAN) - y.to_f64().unwrap_or(f64::NAN)).abs();
+        if d != 0. {
+            n_differing += 1;
+        }
+        max_abs = max_abs.max(d);
+        sum_sq += d * d;
+    }
+
+    let rms = if a.is_empty() {0.} else {(sum_sq / a.len() as f64).sqrt()};
+    Ok(DiffStats {max_abs, rms, n_differing})
+}
+
+/// joins volumes along an axis, as teem's 'unu join' does. The volumes must have the data type T,
+/// the same space and space directions, and the same sizes on every other axis. Joining along
+/// axis 'dimension' stacks the volumes on a new last axis of kind 'list'. The header of the first
+/// volume is used for the result, with the size of the joined axis summed and its axis max (if set)
+/// cleared, since it no longer describes the data
+pub fn concat<T:NRRDType>(headers:&[NRRD], datas:&[&[T]], axis:usize) -> Result<(Vec<T>, NRRD), NrrdError> {
+
+    if headers.len() != datas.len() {
+        return Err(NrrdError::DataLength {expected: headers.len(), found: datas.len()});
+    }
+    let Some(first) = headers.first() else {
+        return Err(NrrdError::InvalidValue {field: "headers", value: "no volumes to join".to_string()});
+    };
+    let shape = first.shape();
+    if axis > shape.len() {
+        return Err(NrrdError::InvalidValue {field: "axis", value: axis.to_string()});
+    }
+
+    for (h,data) in headers.iter().zip(datas) {
+        if h.dtype != T::dtype() {
+            return Err(NrrdError::TypeMismatch {expected: h.dtype, found: T::dtype()});
+        }
+        let n = h.sizes.n_elements();
+        if data.len() != n {
+            return Err(NrrdError::DataLength {expected: n, found: data.len()});
+        }
+        let other_axes_match = h.shape().len() == shape.len()
+            && h.shape().iter().zip(shape).enumerate().all(|(i,(a,b))| i == axis || a == b);
+        if !other_axes_match || h.space != first.space || h.space_directions != first.space_directions {
+            return Err(NrrdError::GridMismatch);
+        }
+    }
+
+    // each volume contributes a run of 'inner * size' elements for every index of the slower axes
+    let inner:usize = shape[..axis].iter().product();
+    let outer:usize = shape.get(axis + 1..).map(|s| s.iter().product()).unwrap_or(1);
+    let runs:Vec<usize> = headers.iter().map(|h| inner * h.shape().get(axis).copied().unwrap_or(1)).collect();
+
+    let mut out = Vec::with_capacity(datas.iter().map(|d| d.len()).sum());
+    for k in 0..outer {
+        for (data,&run) in datas.iter().zip(&runs) {
+            out.extend_from_slice(&data[k * run..(k + 1) * run]);
+        }
+    }
+
+    let mut h = first.clone();
+    h.min = None;
+    h.max = None;
+    h.data_file = None;
+    if axis == shape.len() {
+        h.append_axis(headers.len(), Kind::list, None)?;
+    }else {
+        let mut new_shape = shape.to_vec();
+        new_shape[axis] = headers.iter().map(|h| h.shape()[axis]).sum();
+        h.sizes = Sizes::new(&new_shape);
+        if let Some(maxs) = h.axis_maxs.as_mut() {
+            maxs.entries_mut()[axis] = f64::NAN;
+        }
+    }
+    Ok((out, h))
+}
+
+/// removes the entry of an axis from an optional per-axis field
+fn remove_entry<F:PerAxis>(field:&mut Option<F>, axis:usize) {
+    if let Some(field) = field {
+        field.remove_axis(axis);
+    }
+}
+
+/// adds an entry for a new last axis to an optional per-axis field
+fn push_entry<F:PerAxis>(field:&mut Option<F>, entry:F::Entry) {
+    if let Some(field) = field {
+        field.push_axis(entry);
+    }
+}
+
+/// reorders the entries of an optional per-axis field
+fn permute_entries<F:PerAxis>(field:&mut Option<F>, order:&[usize]) {
+    if let Some(field) = field {
+        field.permute_axes(order);
+    }
+}
+
+/// how samples are combined when downsampling
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum DownsampleMode {
+    /// keep every n-th sample, starting with the first
+    Subsample,
+    /// average non-overlapping blocks of n samples
+    Average,
+}
+
+/// keeps every 'factor'-th sample along an axis, dropping any trailing partial block
+fn subsample_along_axis<T:Copy>(data:&[T], shape:&[usize], axis:usize, factor:usize) -> Vec<T> {
+    let inner:usize = shape[..axis].iter().product();
+    let n = shape[axis];
+    let n_out = n / factor;
+    let mut out = Vec::with_capacity(data.len() / n * n_out);
+    for block in data.chunks_exact(inner * n) {
+        for j in 0..n_out {
+            out.extend_from_slice(&block[j * factor * inner..(j * factor + 1) * inner]);
+        }
+    }
+    out
+}
+
+/// averages blocks of 'factor' samples along an axis, dropping any trailing partial block
+fn mean_along_axis(data:&[f64], shape:&[usize], axis:usize, factor:usize) -> Vec<f64> {
+    let inner:usize = shape[..axis].iter().product();
+    let n = shape[axis];
+    let n_out = n / factor;
+    let outer:usize = shape[axis + 1..].iter().product();
+    let mut out = Vec::with_capacity(inner * n_out * outer);
+    for k in 0..outer {
+        for j in 0..n_out {
+            for i in 0..inner {
+                let sum:f64 = (0..factor).map(|m| data[i + (j * factor + m) * inner + k * inner * n]).sum();
+                out.push(sum / factor as f64);
+            }
+        }
+    }
+    out
+}
+
+impl NRRD {
+
+    /// removes an axis from the sizes and every per-axis field, and decrements the dimension
+    pub(crate) fn remove_axis_fields(&mut self, axis:usize) {
+        self.sizes.remove_axis(axis);
+        remove_entry(&mut self.spacings, axis);
+        remove_entry(&mut self.thicknesses, axis);
+        remove_entry(&mut self.axis_mins, axis);
+        remove_entry(&mut self.axis_maxs, axis);
+        remove_entry(&mut self.centerings, axis);
+        remove_entry(&mut self.labels, axis);
+        remove_entry(&mut self.units, axis);
+        remove_entry(&mut self.kinds, axis);
+        remove_entry(&mut self.space_directions, axis);
+        self.dimension = Dimension::new(self.sizes.shape().len());
+    }
+
+    /// adds a last (slowest) axis of 'size' samples, such as the axis of a stack of volumes. Per-axis
+    /// fields that are set get an empty entry for it: NaN spacing, thickness and axis min/max, no
+    /// centering, an empty unit and no space direction, as stack axes are non-spatial. The kind is
+    /// always set, with existing axes of unknown kind marked 'none', and the label is set if given.
+    /// Fails and leaves the header unchanged if the per-axis fields don't agree with the new
+    /// dimension
+    pub fn append_axis(&mut self, size:usize, kind:Kind, label:Option<&str>) -> Result<(), NrrdError> {
+        let mut h = self.clone();
+        let dim = h.dimension.dim();
+
+        h.sizes.push_axis(size);
+        push_entry(&mut h.spacings, f64::NAN);
+        push_entry(&mut h.thicknesses, f64::NAN);
+        push_entry(&mut h.axis_mins, f64::NAN);
+        push_entry(&mut h.axis_maxs, f64::NAN);
+        push_entry(&mut h.centerings, Centering::None);
+        push_entry(&mut h.units, String::new());
+        push_entry(&mut h.space_directions, None);
+        h.kinds.get_or_insert_with(|| Kinds::new(Kind::none, dim)).push_axis(kind);
+        match (h.labels.as_mut(), label) {
+            (Some(labels), label) => labels.push_axis(label.unwrap_or_default().to_string()),
+            (None, Some(label)) => {
+                let mut labels = vec![""; dim];
+                labels.push(label);
+                h.labels = Some(Labels::from_list(&labels));
+            }
+            (None, None) => {}
+        }
+        h.dimension = Dimension::new(dim + 1);
+
+        h.validate()?;
+        *self = h;
+        Ok(())
+    }
+
+    /// reorders the sizes and every per-axis field so that new axis i is old axis 'order[i]'
+    pub(crate) fn permute_axis_fields(&mut self, order:&[usize]) {
+        self.sizes.permute_axes(order);
+        permute_entries(&mut self.spacings, order);
+        permute_entries(&mut self.thicknesses, order);
+        permute_entries(&mut self.axis_mins, order);
+        permute_entries(&mut self.axis_maxs, order);
+        permute_entries(&mut self.centerings, order);
+        permute_entries(&mut self.labels, order);
+        permute_entries(&mut self.units, order);
+        permute_entries(&mut self.kinds, order);
+        permute_entries(&mut self.space_directions, order);
+    }
+
+    /// returns the axis of kind 'complex' holding the real and imaginary parts, if there is one
+    pub fn complex_axis(&self) -> Option<usize> {
+        let kinds = self.kinds.as_ref()?;
+        kinds.kinds.iter().zip(self.shape()).position(|(k,&s)| *k == Kind::complex && s == 2)
+    }
+
+    /// splits complex data into magnitude and phase (radians) volumes. The real and imaginary parts
+    /// are the first and second entries along the complex axis. Both outputs are f32 with the complex
+    /// axis dropped from every per-axis field, and the remaining geometry is kept
+    pub fn complex_to_mag_phase<T:NRRDType + ToPrimitive>(&self, data:&[T]) -> Result<MagPhase, NrrdError> {
+
+        let expected = self.sizes.n_elements();
+        if data.len() != expected {
+            return Err(NrrdError::DataLength { expected, found: data.len() });
+        }
+
+        let axis = self.complex_axis().ok_or(NrrdError::MissingComplexAxis)?;
+
+        // elements before the complex axis are contiguous, so each re/im pair is 'inner' apart
+        let inner:usize = self.shape()[..axis].iter().product();
+        let n_out = expected / 2;
+        let mut mag = Vec::with_capacity(n_out);
+        let mut phase = Vec::with_capacity(n_out);
+
+        for block in data.chunks_exact(2 * inner) {
+            let (re, im) = block.split_at(inner);
+            for (re, im) in re.iter().zip(im) {
+                let re = re.to_f64().unwrap_or(f64::NAN);
+                let im = im.to_f64().unwrap_or(f64::NAN);
+                mag.push(re.hypot(im) as f32);
+                phase.push(im.atan2(re) as f32);
+            }
+        }
+
+        let mut h = self.clone();
+        h.remove_axis_fields(axis);
+        h.dtype = DType::f32;
+        h.block_size = None;
+        h.min = None;
+        h.max = None;
+        h.old_min = None;
+        h.old_max = None;
+        h.data_file = None;
+
+        Ok(((mag, h.clone()), (phase, h)))
+    }
+
+    /// rescales the data values from the header's sample units to 'target' units and updates the
+    /// sample units. See 'SampleUnits::scale_to' for the conversions that are supported
+    pub fn convert_sample_units<T:NRRDType + FromPrimitive + ToPrimitive>(&mut self, data:&mut [T], target:&str) -> Result<(), NrrdError> {
+
+        let target = SampleUnits::new(target)?;
+        let from = self.sample_units.as_ref().ok_or(NrrdError::MissingField("sample units"))?;
+        let scale = from.scale_to(&target).ok_or_else(|| NrrdError::IncompatibleUnits {
+            from: from.units().to_string(),
+            to: target.units().to_string(),
+        })?;
+
+        for x in data.iter_mut() {
+            let v = x.to_f64().unwrap_or(f64::NAN) * scale;
+            *x = T::from_f64(v).ok_or_else(|| NrrdError::InvalidValue {field: "sample units", value: v.to_string()})?;
+        }
+
+        self.sample_units = Some(target);
+        Ok(())
+    }
+
+    /// returns the axes that are resampled by spatial operations: the axes with a space direction,
+    /// otherwise the axes of a spatial kind, otherwise all axes
+    pub(crate) fn resample_axes(&self) -> Vec<usize> {
+        if self.space_directions.is_some() {
+            return self.spatial_axes();
+        }
+        if let Some(kinds) = &self.kinds {
+            return kinds.kinds.iter().enumerate().filter(|(_,k)| k.is_spatial()).map(|(i,_)| i).collect();
+        }
+        (0..self.dimension.dim()).collect()
+    }
+
+    /// returns the header describing data downsampled by an integer factor per axis. Sample
+    /// spacing is scaled by the factor. When averaging, the first sample, and with it the space
+    /// origin, moves to the center of the first block, otherwise it stays where it is. The axis min
+    /// of a cell-centered axis is the edge of its first cell, so it moves by half a new step less
+    /// than the first sample
+    pub(crate) fn downsampled_header(&self, factors:&[usize], mode:DownsampleMode) -> NRRD {
+
+        let mut h = self.clone();
+        h.min = None;
+        h.max = None;
+        h.data_file = None;
+
+        for (axis,&f) in factors.iter().enumerate().filter(|(_,f)| **f > 1) {
+            let f64_factor = f as f64;
+            let sample_shift = match mode {
+                DownsampleMode::Average => (f64_factor - 1.) / 2.,
+                DownsampleMode::Subsample => 0.,
+            };
+            // the first sample sits 'offset' old steps past the axis min before and 'offset * f' after
+            let offset = self.sample_offset(axis);
+            let min_shift = sample_shift + offset - offset * f64_factor;
+            let n_out = self.shape()[axis] / f;
+            h.sizes.entries_mut()[axis] = n_out;
+
+            if let Some(dir) = self.space_direction(axis) {
+                if let Some(origin) = &mut h.space_origin {
+                    let shifted:Vec<f64> = origin.coords().iter().zip(dir).map(|(o,d)| o + sample_shift * d).collect();
+                    *origin = SpaceOrigin::new(&shifted);
+                }
+                let scaled:Vec<f64> = dir.iter().map(|d| d * f64_factor).collect();
+                h.space_directions.as_mut().unwrap().entries_mut()[axis] = Some(NrrdVec::new(&scaled));
+            }
+
+            if let Some(thicknesses) = &mut h.thicknesses {
+                thicknesses.entries_mut()[axis] *= f64_factor;
+            }
+
+            let Some(spacings) = &mut h.spacings else {
+                continue
+            };
+            let spacing = spacings.values()[axis];
+            spacings.entries_mut()[axis] = spacing * f64_factor;
+
+            // axis mins follow the first sample, or the outer edge of the first cell
+            let cell = h.centerings.as_ref().is_some_and(|c| c.values()[axis] == Centering::Cell);
+            if let Some(mins) = &mut h.axis_mins {
+                let min = &mut mins.entries_mut()[axis];
+                *min += min_shift * spacing;
+                let min = *min;
+                if let Some(maxs) = &mut h.axis_maxs {
+                    maxs.entries_mut()[axis] = if cell {
+                        min + n_out as f64 * spacing * f64_factor
+                    }else {
+                        min + (n_out as f64 - 1.) * spacing * f64_factor
+                    };
+                }
+            }
+        }
+        h
+    }
+
+    /// averages non-overlapping blocks of samples, with an integer factor per axis. Trailing
+    /// samples that don't fill a block are dropped
+    pub(crate) fn downsample_mean<T:NRRDType + FromPrimitive + ToPrimitive>(&self, data:&[T], factors:&[usize]) -> (Vec<T>, NRRD) {
+        assert_eq!(data.len(), self.sizes.n_elements(), "data length must match number of elements in header");
+        assert_eq!(factors.len(), self.dimension.dim(), "there must be one factor per axis");
+        assert!(factors.iter().all(|&f| f > 0), "factors must be at least 1");
+
+        let mut shape = self.shape().to_vec();
+        let mut values:Vec<f64> = data.iter().map(|x| x.to_f64().unwrap_or(f64::NAN)).collect();
+        for (axis,&f) in factors.iter().enumerate().filter(|(_,f)| **f > 1) {
+            values = mean_along_axis(&values, &shape, axis, f);
+            shape[axis] /= f;
+        }
+
+        let float = T::dtype().is_float();
+        let out = values.into_iter()
+            .map(|v| T::from_f64(if float {v} else {v.round()}).expect("failed to convert value"))
+            .collect();
+        (out, self.downsampled_header(factors, DownsampleMode::Average))
+    }
+
+    /// returns the header of a region starting at index 'min' with 'size' samples along each axis.
+    /// The space origin moves to the first sample of the region and the axis mins and maxs are
+    /// shifted to match. Value ranges (min/max) and the data file are dropped
+    pub(crate) fn cropped_header(&self, min:&[usize], size:&[usize]) -> NRRD {
+
+        let mut h = self.clone();
+        h.min = None;
+        h.max = None;
+        h.data_file = None;
+        h.sizes.entries_mut().copy_from_slice(size);
+
+        for (axis,(&offset,&n)) in min.iter().zip(size).enumerate() {
+            let offset = offset as f64;
+
+            if let (Some(dir), Some(origin)) = (self.space_direction(axis), &mut h.space_origin) {
+                let shifted:Vec<f64> = origin.coords().iter().zip(dir).map(|(o,d)| o + offset * d).collect();
+                *origin = SpaceOrigin::new(&shifted);
+            }
+
+            // the sample step comes from the spacing, or failing that from the old axis extent
+            let cell = self.centerings.as_ref().is_some_and(|c| c.values()[axis] == Centering::Cell);
+            let old_n = self.shape()[axis] as f64;
+            let step = self.spacings.as_ref().and_then(|s| s.get(axis)).or_else(|| {
+                let min = self.axis_mins.as_ref()?.values()[axis];
+                let max = self.axis_maxs.as_ref()?.values()[axis];
+                let n_steps = if cell {old_n} else {old_n - 1.};
+                (n_steps > 0.).then(|| (max - min) / n_steps)
+            });
+            let Some(step) = step.filter(|s| !s.is_nan()) else {
+                continue
+            };
+
+            // the min moves up past the samples dropped at the start, the max down past the
+            // samples dropped at the end
+            if let Some(mins) = &mut h.axis_mins {
+                mins.entries_mut()[axis] += offset * step;
+            }
+            if let Some(maxs) = &mut h.axis_maxs {
+                maxs.entries_mut()[axis] -= (old_n - offset - n as f64) * step;
+            }
+        }
+        h
+    }
+
+    /// extracts the region starting at index 'min' with 'size' samples along each axis, returning
+    /// its data and header. The world-space position of every sample is kept: the space origin
+    /// moves to the first sample of the region and the axis mins and maxs are updated. Panics if
+    /// the region doesn't fit in the volume
+    pub fn crop<T:Copy>(&self, data:&[T], min:&[usize], size:&[usize]) -> (Vec<T>, NRRD) {
+        let shape = self.shape();
+        assert_eq!(data.len(), self.sizes.n_elements(), "data length must match number of elements in header");
+        assert_eq!(min.len(), shape.len(), "there must be one min index per axis");
+        assert_eq!(size.len(), shape.len(), "there must be one size per axis");
+        assert!(
+            min.iter().zip(size).zip(shape).all(|((&m,&n),&s)| n > 0 && m + n <= s),
+            "region at {min:?} with size {size:?} doesn't fit in shape {shape:?}"
+        );
+
+        // copy runs along the fastest axis, stepping the index of the other axes
+        let strides = strides(shape);
+        let n_runs:usize = size[1..].iter().product();
+        let mut out = Vec::with_capacity(size.iter().product());
+        let mut idx = vec![0;shape.len()];
+        for _ in 0..n_runs {
+            let start:usize = idx.iter().zip(min).zip(&strides).map(|((i,m),s)| (i + m) * s).sum();
+            out.extend_from_slice(&data[start..start + size[0]]);
+            for axis in 1..shape.len() {
+                idx[axis] += 1;
+                if idx[axis] < size[axis] {
+                    break;
+                }
+                idx[axis] = 0;
+            }
+        }
+
+        (out, self.cropped_header(min, size))
+    }
+
+    /// removes all axes of size 1 (such as 'stub' axes), dropping their entries from every per-axis
+    /// field. The data order doesn't change, so the data is only copied. A volume with every axis of
+    /// size 1 keeps its first axis, as a header needs at least one
+    pub fn squeeze<T:Copy>(&self, data:&[T]) -> (Vec<T>, NRRD) {
+        assert_eq!(data.len(), self.sizes.n_elements(), "data length must match number of elements in header");
+        let mut h = self.clone();
+        for axis in (0..self.shape().len()).rev() {
+            if h.shape()[axis] == 1 && h.shape().len() > 1 {
+                h.remove_axis_fields(axis);
+            }
+        }
+        (data.to_vec(), h)
+    }
+
+    /// removes a single axis of size 1, dropping its entries from every per-axis field. Panics if
+    /// the axis doesn't exist, isn't size 1, or is the only axis
+    pub fn squeeze_axis<T:Copy>(&self, data:&[T], axis:usize) -> (Vec<T>, NRRD) {
+        let shape = self.shape();
+        assert_eq!(data.len(), self.sizes.n_elements(), "data length must match number of elements in header");
+        assert!(axis < shape.len(), "axis {axis} is out of range for {} axes", shape.len());
+        assert_eq!(shape[axis], 1, "only an axis of size 1 can be squeezed");
+        assert!(shape.len() > 1, "the only axis can't be squeezed");
+        let mut h = self.clone();
+        h.remove_axis_fields(axis);
+        (data.to_vec(), h)
+    }
+
+    /// reorders the axes of the data so that new axis i is old axis 'order[i]', as teem's
+    /// 'unu permute' does. Every per-axis field is reordered with the data, so each sample keeps
+    /// its world-space position. Panics if 'order' is not a permutation of the axes
+    pub fn permute_axes<T:Copy>(&self, data:&[T], order:&[usize]) -> (Vec<T>, NRRD) {
+        let shape = self.shape();
+        assert_eq!(data.len(), self.sizes.n_elements(), "data length must match number of elements in header");
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        assert!(sorted.into_iter().eq(0..shape.len()), "axis order {order:?} is not a permutation of 0..{}", shape.len());
+
+        // walk the output in memory order, stepping through the input with the permuted strides
+        let old_strides = strides(shape);
+        let new_shape:Vec<usize> = order.iter().map(|&a| shape[a]).collect();
+        let new_strides:Vec<usize> = order.iter().map(|&a| old_strides[a]).collect();
+        let mut out = Vec::with_capacity(data.len());
+        let mut idx = vec![0;shape.len()];
+        let mut offset = 0;
+        for _ in 0..data.len() {
+            out.push(data[offset]);
+            for axis in 0..new_shape.len() {
+                idx[axis] += 1;
+                offset += new_strides[axis];
+                if idx[axis] < new_shape[axis] {
+                    break;
+                }
+                offset -= idx[axis] * new_strides[axis];
+                idx[axis] = 0;
+            }
+        }
+
+        let mut h = self.clone();
+        h.permute_axis_fields(order);
+        h.data_file = None;
+        (out, h)
+    }
+
+    /// reverses the data along an axis, keeping the world-space position of every sample: the
+    /// space origin moves to the old last sample and the axis's space direction is negated. The
+    /// spacing is negated and the axis min and max are swapped to match. Panics if the axis is out
+    /// of range
+    pub fn flip_axis<T:Copy>(&self, data:&[T], axis:usize) -> (Vec<T>, NRRD) {
+        let shape = self.shape();
+        assert_eq!(data.len(), self.sizes.n_elements(), "data length must match number of elements in header");
+        assert!(axis < shape.len(), "axis {axis} is out of range for dimension {}", shape.len());
+
+        // each run of the axes before 'axis' is contiguous, so runs are copied in reverse order
+        let inner:usize = shape[..axis].iter().product();
+        let n = shape[axis];
+        let mut out = Vec::with_capacity(data.len());
+        for block in data.chunks_exact(inner * n) {
+            for run in block.chunks_exact(inner).rev() {
+                out.extend_from_slice(run);
+            }
+        }
+
+        let mut h = self.clone();
+        h.data_file = None;
+        if let Some(dir) = self.space_direction(axis) {
+            // the last sample becomes the first, and the origin with it
+            let steps = n as f64 - 1.;
+            if let Some(origin) = &mut h.space_origin {
+                let shifted:Vec<f64> = origin.coords().iter().zip(dir).map(|(o,d)| o + steps * d).collect();
+                *origin = SpaceOrigin::new(&shifted);
+            }
+            let flipped:Vec<f64> = dir.iter().map(|d| -d + 0.).collect();
+            h.space_directions.as_mut().unwrap().entries_mut()[axis] = Some(NrrdVec::new(&flipped));
+        }
+        if let Some(spacings) = &mut h.spacings {
+            spacings.entries_mut()[axis] *= -1.;
+        }
+        if let (Some(mins), Some(maxs)) = (&mut h.axis_mins, &mut h.axis_maxs) {
+            std::mem::swap(&mut mins.entries_mut()[axis], &mut maxs.entries_mut()[axis]);
+        }
+        (out, h)
+    }
+
+    /// counts the data values in 'bins' equal-width bins spanning 'range', or the range of the data
+    /// if it is None. Values outside the range are counted in the first or last bin and NaNs are
+    /// skipped. The last bin includes the top of the range
+    pub fn histogram<T:ToPrimitive>(&self, data:&[T], bins:usize, range:Option<(f64,f64)>) -> Vec<u64> {
+        assert_eq!(data.len(), self.sizes.n_elements(), "data length must match number of elements in header");
+
+        let mut counts = vec![0u64;bins];
+        if bins == 0 {
+            return counts;
+        }
+
+        let values = || data.iter().map(|x| x.to_f64().unwrap_or(f64::NAN)).filter(|x| !x.is_nan());
+        let range = range.or_else(|| values().fold(None, |range, x| match range {
+            None => Some((x,x)),
+            Some((min,max)) => Some((f64::min(min,x), f64::max(max,x))),
+        }));
+        let Some((min,max)) = range else {
+            return counts;
+        };
+
+        let width = (max - min) / bins as f64;
+        for x in values() {
+            let bin = if width > 0. {((x - min) / width).floor()} else {0.};
+            counts[bin.clamp(0., (bins - 1) as f64) as usize] += 1;
+        }
+        counts
+    }
+
+    /// reduces the size of each axis by an integer factor, either keeping every n-th sample or
+    /// averaging blocks of samples (computed in f64 and rounded for integer types). Trailing samples
+    /// that don't fill a block are dropped. Space directions, spacings and thicknesses are scaled by
+    /// the factor and the space origin follows the first output sample. Panics if there isn't one
+    /// factor of at least 1 per axis
+    pub fn downsample<T:NRRDType + FromPrimitive + ToPrimitive>(&self, data:&[T], factors:&[usize], mode:DownsampleMode) -> (Vec<T>, NRRD) {
+        match mode {
+            DownsampleMode::Average => self.downsample_mean(data, factors),
+            DownsampleMode::Subsample => {
+                assert_eq!(data.len(), self.sizes.n_elements(), "data length must match number of elements in header");
+                assert_eq!(factors.len(), self.dimension.dim(), "there must be one factor per axis");
+                assert!(factors.iter().all(|&f| f > 0), "factors must be at least 1");
+
+                let mut shape = self.shape().to_vec();
+                let mut out = data.to_vec();
+                for (axis,&f) in factors.iter().enumerate().filter(|(_,f)| **f > 1) {
+                    out = subsample_along_axis(&out, &shape, axis, f);
+                    shape[axis] /= f;
+                }
+                (out, self.downsampled_header(factors, mode))
+            }
+        }
+    }
+
+    /// converts the data to type U. Without 'rescale' the values are cast, with floating point
+    /// values truncated toward zero, and values that don't fit in U are an error. With
+    /// 'rescale' = (lo, hi), U must be an integer type: lo maps to the smallest value of U, hi to
+    /// the largest, and values in between are scaled linearly and rounded, with values outside the
+    /// range clamped. The range is stored in 'old min' and 'old max' so that 'dequantize' can
+    /// undo the scaling. Min and max are dropped either way
+    pub fn convert_dtype<T:NRRDType + ToPrimitive, U:NRRDType + NumCast + Bounded>(&self, data:&[T], rescale:Option<(f64,f64)>) -> Result<(Vec<U>, NRRD), NrrdError> {
+
+        let n = self.sizes.n_elements();
+        if data.len() != n {
+            return Err(NrrdError::DataLength {expected: n, found: data.len()});
+        }
+
+        let out_of_range = |index| NrrdError::OutOfRange {index, from: T::dtype(), to: U::dtype()};
+
+        let mut h = self.clone();
+        h.dtype = U::dtype();
+        h.block_size = None;
+        h.min = None;
+        h.max = None;
+        h.data_file = None;
+
+        let out = match rescale {
+            None => data.iter().enumerate()
+                .map(|(i,&x)| <U as NumCast>::from(x).ok_or_else(|| out_of_range(i)))
+                .collect::<Result<Vec<U>,_>>()?,
+            Some((lo,hi)) => {
+                if U::dtype().is_float() {
+                    return Err(NrrdError::Unsupported(format!("values can only be rescaled to integer types, not '{}'", U::dtype().name())));
+                }
+                let (u_min, u_max) = (U::min_value().to_f64().unwrap(), U::max_value().to_f64().unwrap());
+                let scale = if hi > lo {(u_max - u_min) / (hi - lo)} else {0.};
+                let out = data.iter().enumerate().map(|(i,&x)| {
+                    let x = x.to_f64().filter(|x| !x.is_nan()).ok_or_else(|| out_of_range(i))?;
+                    let q = (u_min + (x - lo) * scale).round().clamp(u_min, u_max);
+                    // the largest 64-bit integers round up to a float past the top of the type
+                    if q >= u_max {
+                        return Ok(U::max_value());
+                    }
+                    <U as NumCast>::from(q).ok_or_else(|| out_of_range(i))
+                }).collect::<Result<Vec<U>,_>>()?;
+                h.old_min = Some(OldMin::new(lo));
+                h.old_max = Some(OldMax::new(hi));
+                out
+            }
+        };
+        Ok((out, h))
+    }
+
+    /// undoes the rescaling of 'convert_dtype', mapping the full range of the integer type T back
+    /// to the range stored in 'old min' and 'old max' and converting to U. The old range is
+    /// removed from the header
+    pub fn dequantize<T:NRRDType + ToPrimitive + Bounded, U:NRRDType + FromPrimitive>(&self, data:&[T]) -> Result<(Vec<U>, NRRD), NrrdError> {
+
+        let n = self.sizes.n_elements();
+        if data.len() != n {
+            return Err(NrrdError::DataLength {expected: n, found: data.len()});
+        }
+        if T::dtype().is_float() {
+            return Err(NrrdError::Unsupported(format!("'{}' data is not quantized", T::dtype().name())));
+        }
+        let lo = self.old_min.ok_or(NrrdError::MissingField("old min"))?.value();
+        let hi = self.old_max.ok_or(NrrdError::MissingField("old max"))?.value();
+
+        let (t_min, t_max) = (T::min_value().to_f64().unwrap(), T::max_value().to_f64().unwrap());
+        let scale = (hi - lo) / (t_max - t_min);
+        let out = data.iter().enumerate().map(|(i,&q)| {
+            let x = lo + (q.to_f64().unwrap() - t_min) * scale;
+            U::from_f64(x).ok_or(NrrdError::OutOfRange {index: i, from: T::dtype(), to: U::dtype()})
+        }).collect::<Result<Vec<U>,_>>()?;
+
+        let mut h = self.clone();
+        h.dtype = U::dtype();
+        h.old_min = None;
+        h.old_max = None;
+        h.data_file = None;
+        Ok((out, h))
+    }
+
+    /// builds a multi-resolution pyramid. Level 0 is a copy of the data and each following level
+    /// halves the size of every spatial axis (see 'resample_axes') by averaging pairs of samples,
+    /// with spacings scaled to match. Fewer than 'levels' levels are returned if a spatial axis
+    /// would shrink below one sample
+    pub fn pyramid<T:NRRDType + FromPrimitive + ToPrimitive>(&self, data:&[T], levels:usize) -> Vec<(Vec<T>, NRRD)> {
+
+        let axes = self.resample_axes();
+        let mut pyramid:Vec<(Vec<T>, NRRD)> = Vec::with_capacity(levels);
+        if levels == 0 {
+            return pyramid;
+        }
+        pyramid.push((data.to_vec(), self.clone()));
+
+        while pyramid.len() < levels {
+            let (data,h) = pyramid.last().unwrap();
+            if axes.iter().any(|&a| h.shape()[a] < 2) {
+                break;
+            }
+            let mut factors = vec![1;h.dimension.dim()];
+            axes.iter().for_each(|&a| factors[a] = 2);
+            let next = h.downsample_mean(data, &factors);
+            pyramid.push(next);
+        }
+        pyramid
+    }
+}
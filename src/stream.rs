@@ -0,0 +1,268 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use num_traits::FromPrimitive;
+use crate::error::NrrdError;
+use crate::header_defs::{DataFile, Encoding, NRRDType};
+use crate::{decode_payload, io, prepare_write_header, read_data, supports_reading, supports_writing, NRRD};
+
+/// destination of the encoded data bytes
+enum Sink {
+    Raw(BufWriter<File>),
+    Gz(GzEncoder<BufWriter<File>>),
+    Bz(BzEncoder<BufWriter<File>>),
+}
+
+impl Sink {
+    fn new(f:File, encoding:Encoding) -> Sink {
+        let f = BufWriter::new(f);
+        match encoding {
+            Encoding::rawgz => Sink::Gz(GzEncoder::new(f, flate2::Compression::default())),
+            Encoding::rawbz2 => Sink::Bz(BzEncoder::new(f, bzip2::Compression::fast())),
+            _=> Sink::Raw(f),
+        }
+    }
+
+    fn write_all(&mut self, bytes:&[u8]) -> std::io::Result<()> {
+        match self {
+            Sink::Raw(w) => w.write_all(bytes),
+            Sink::Gz(w) => w.write_all(bytes),
+            Sink::Bz(w) => w.write_all(bytes),
+        }
+    }
+
+    /// writes any remaining compressed data and flushes the file
+    fn finish(self) -> std::io::Result<()> {
+        let mut f = match self {
+            Sink::Raw(w) => w,
+            Sink::Gz(w) => w.finish()?,
+            Sink::Bz(w) => w.finish()?,
+        };
+        f.flush()
+    }
+}
+
+/// writes a volume chunk by chunk so the whole array never has to be in memory. The header is
+/// written when the writer is created and chunks are encoded as they arrive. Chunks are written in
+/// native endianness, in file order (fastest axis first)
+pub struct NrrdWriter<T:NRRDType> {
+    sink: Sink,
+    written: usize,
+    expected: usize,
+    _dtype: PhantomData<T>,
+}
+
+impl<T:NRRDType> NrrdWriter<T> {
+
+    /// creates the output for data described by the reference header. A path with the 'nhdr'
+    /// extension is written as a detached header + data file, anything else as an attached .nrrd
+    pub fn new(filepath:impl AsRef<Path>, ref_header:&NRRD, encoding:Encoding) -> Result<NrrdWriter<T>,NrrdError> {
+
+        if !supports_writing(encoding) {
+            return Err(NrrdError::UnsupportedEncoding(encoding));
+        }
+
+        let mut h = prepare_write_header::<T>(ref_header, encoding);
+        if !h.dtype.is_standard() {
+            return Err(NrrdError::NonStandardType(h.dtype));
+        }
+
+        let filepath = filepath.as_ref();
+        let attached = filepath.extension().map(|e| e != "nhdr").unwrap_or(true);
+
+        let f = if attached {
+            h.data_file = None;
+            let mut f = File::create(filepath.with_extension("nrrd"))?;
+            f.write_all(h.to_string().as_bytes())?;
+            writeln!(&mut f)?;
+            f
+        }else {
+            let ext = encoding.file_ext();
+            let df = Path::new(filepath.file_name().unwrap()).with_extension(ext);
+            h.data_file = Some(DataFile::SingleFile {filename: df});
+            File::create(filepath)?.write_all(h.to_string().as_bytes())?;
+            File::create(filepath.with_extension(ext))?
+        };
+
+        Ok(NrrdWriter {
+            sink: Sink::new(f, encoding),
+            written: 0,
+            expected: h.sizes.n_elements(),
+            _dtype: PhantomData,
+        })
+    }
+
+    /// encodes the next chunk of data. Fails if the chunk would go past the end of the volume
+    pub fn write_chunk(&mut self, data:&[T]) -> Result<(),NrrdError> {
+        if self.written + data.len() > self.expected {
+            return Err(NrrdError::DataLength {expected: self.expected, found: self.written + data.len()});
+        }
+        self.sink.write_all(bytemuck::cast_slice(data))?;
+        self.written += data.len();
+        Ok(())
+    }
+
+    /// returns the number of elements written so far
+    pub fn elements_written(&self) -> usize {
+        self.written
+    }
+
+    /// flushes the encoder and checks that the whole volume was written
+    pub fn finish(self) -> Result<(),NrrdError> {
+        self.sink.finish()?;
+        if self.written != self.expected {
+            return Err(NrrdError::DataLength {expected: self.expected, found: self.written});
+        }
+        Ok(())
+    }
+}
+
+/// reads a volume one slice at a time, where a slice is one index of the last (slowest) axis. Data
+/// is decoded lazily as the iterator advances, reading forward through the data files in order
+pub struct NrrdReader<T:NRRDType + FromPrimitive> {
+    header: NRRD,
+    /// data files that haven't been opened yet
+    files: VecDeque<PathBuf>,
+    /// decoded stream of the data file being read
+    current: Option<Box<dyn Read>>,
+    /// number of bytes in each data file
+    file_bytes: usize,
+    slice_bytes: usize,
+    remaining: usize,
+    _dtype: PhantomData<T>,
+}
+
+impl<T:NRRDType + FromPrimitive> NrrdReader<T> {
+
+    /// reads the header and prepares to read the data slice by slice
+    pub fn open(filepath:impl AsRef<Path>) -> Result<(NRRD, NrrdReader<T>),NrrdError> {
+
+        let mut f = File::open(&filepath)?;
+        let (header_bytes,_) = io::read_until_blank(&mut f)?;
+        let header_str = String::from_utf8_lossy(&header_bytes);
+        let mut header_lines = header_str.lines().collect::<Vec<&str>>();
+        let h = NRRD::from_lines_full(&mut header_lines);
+
+        if h.dtype.is_block() {
+            return Err(NrrdError::TypeMismatch {expected: h.dtype, found: T::dtype()});
+        }
+        if !supports_reading(h.encoding) {
+            return Err(NrrdError::UnsupportedEncoding(h.encoding));
+        }
+        if h.encoding.is_compressed() && h.byte_skip.as_ref().is_some_and(|bs| bs.read_tail()) {
+            return Err(NrrdError::Unsupported("byte skip -1 needs the whole compressed stream decoded".to_string()));
+        }
+
+        let shape = h.shape();
+        let n_slices = shape.last().cloned().unwrap_or(0);
+        let n_elements = h.sizes.n_elements();
+        let slice_bytes = n_elements.checked_div(n_slices).unwrap_or(0) * h.element_size();
+
+        let (files, current, file_elements) = if h.data_file.is_some() {
+            let files:VecDeque<_> = h.resolved_data_paths(filepath.as_ref())?.into();
+            let file_elements = h.elements_per_file(files.len())?;
+            (files, None, file_elements)
+        }else {
+            (VecDeque::new(), Some(f), n_elements)
+        };
+
+        let mut reader = NrrdReader {
+            header: h.clone(),
+            files,
+            current: None,
+            file_bytes: file_elements * h.element_size(),
+            slice_bytes,
+            remaining: n_slices,
+            _dtype: PhantomData,
+        };
+        if let Some(f) = current {
+            reader.current = Some(reader.decoder(f)?);
+        }
+        Ok((h, reader))
+    }
+
+    /// returns the number of slices that haven't been read yet
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// wraps a data file positioned at the start of its data in a decoder for the encoding,
+    /// applying the line and byte skips
+    fn decoder(&self, mut f:File) -> Result<Box<dyn Read>,NrrdError> {
+        let h = &self.header;
+        let line_skip = h.line_skip.as_ref().map(|ls| ls.to_skip()).unwrap_or(0);
+        let (byte_skip,read_tail) = h.byte_skip.as_ref().map(|bs| (bs.to_skip(),bs.read_tail())).unwrap_or((0,false));
+
+        io::skip_lines(&mut f, line_skip);
+
+        // text has no fixed width per value, so a text file is decoded as a whole
+        if matches!(h.encoding, Encoding::txt | Encoding::hex) {
+            let mut bytes = vec![0u8;self.file_bytes];
+            let n = read_data(&mut f, h, &mut bytes, byte_skip, read_tail)?;
+            bytes.truncate(n);
+            return Ok(Box::new(std::io::Cursor::new(bytes)));
+        }
+
+        let mut dec:Box<dyn Read> = match h.encoding {
+            Encoding::rawgz => Box::new(MultiGzDecoder::new(BufReader::new(f))),
+            Encoding::rawbz2 => Box::new(MultiBzDecoder::new(BufReader::new(f))),
+            _=> {
+                if read_tail {
+                    f.seek(SeekFrom::End(-(self.file_bytes as i64)))?;
+                }
+                Box::new(BufReader::new(f))
+            }
+        };
+        if !read_tail {
+            std::io::copy(&mut (&mut dec).take(byte_skip as u64), &mut std::io::sink())?;
+        }
+        Ok(dec)
+    }
+
+    /// fills the buffer from the data files, moving on to the next file when one runs out
+    fn fill(&mut self, buf:&mut [u8]) -> Result<(),NrrdError> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = match self.current.as_mut() {
+                Some(r) => r.read(&mut buf[filled..])?,
+                None => 0,
+            };
+            if n > 0 {
+                filled += n;
+                continue;
+            }
+            let Some(next) = self.files.pop_front() else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("data ended after {filled} of {} bytes of a slice", buf.len())
+                ).into());
+            };
+            self.current = Some(self.decoder(File::open(next)?)?);
+        }
+        Ok(())
+    }
+}
+
+impl<T:NRRDType + FromPrimitive> Iterator for NrrdReader<T> {
+    type Item = Result<Vec<T>,NrrdError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let mut bytes = vec![0u8;self.slice_bytes];
+        if let Err(e) = self.fill(&mut bytes) {
+            // nothing sensible can follow a failed read
+            self.remaining = 0;
+            return Some(Err(e));
+        }
+        Some(Ok(decode_payload(bytes, &self.header)))
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::Path;
+use num_traits::FromPrimitive;
+use crate::header_defs::{Encoding, NRRDType};
+use crate::{read_nrrd_to_checked, NrrdError, NRRD};
+
+/// returns the flat-buffer stride of each axis. NRRD stores the fastest axis first, so the first
+/// axis has a stride of 1
+pub fn strides(shape:&[usize]) -> Vec<usize> {
+    let mut strides = Vec::with_capacity(shape.len());
+    let mut s = 1;
+    for size in shape {
+        strides.push(s);
+        s *= size;
+    }
+    strides
+}
+
+/// a header and its data, read and written together.
+///
+/// Indices list one position per axis in header order, and the first axis varies fastest in the
+/// data: '[x,y,z]' is at 'x + y * nx + z * nx * ny'. This is the reverse of row-major (C order)
+/// arrays such as ndarray, where the last index varies fastest, so a volume with sizes '4 3 2' has
+/// the ndarray shape '[2,3,4]'. The strides of the axes are computed once, so random access is cheap
+#[derive(Debug,Clone,PartialEq)]
+pub struct Volume<T> {
+    header: NRRD,
+    data: Vec<T>,
+    strides: Vec<usize>,
+}
+
+impl<T:NRRDType> Volume<T> {
+
+    /// bundles the data with its header, failing if the number of elements doesn't match
+    pub fn new(header:NRRD, data:Vec<T>) -> Result<Volume<T>,NrrdError> {
+        let expected = header.sizes.n_elements();
+        if data.len() != expected {
+            return Err(NrrdError::DataLength {expected, found: data.len()});
+        }
+        Ok(Volume::from_parts(header, data))
+    }
+
+    fn from_parts(header:NRRD, data:Vec<T>) -> Volume<T> {
+        let strides = strides(header.shape());
+        Volume {header, data, strides}
+    }
+
+    /// reads the nrrd, converting the data to T. Fails if a value doesn't fit in T
+    pub fn open(filepath:impl AsRef<Path>) -> Result<Volume<T>,NrrdError> where T:FromPrimitive {
+        let (data,header) = read_nrrd_to_checked(filepath)?;
+        Ok(Volume::from_parts(header, data))
+    }
+
+    /// writes the volume, either attached (.nrrd) or detached (.nhdr + data file). The data type
+    /// written is T
+    pub fn save(&self, filepath:impl AsRef<Path>, attached:bool, encoding:Encoding) -> Result<(),NrrdError> {
+        self.header.write(filepath, &self.data, attached, encoding)
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        self.header.shape()
+    }
+
+    /// returns the flat offset of an index, or None if it has the wrong number of entries or is out of
+    /// bounds. The first axis is the fastest-varying one
+    pub fn offset(&self, idx:&[usize]) -> Option<usize> {
+        let shape = self.shape();
+        if idx.len() != shape.len() || idx.iter().zip(shape).any(|(i,s)| i >= s) {
+            return None;
+        }
+        Some(idx.iter().zip(&self.strides).map(|(i,s)| i * s).sum())
+    }
+
+    /// returns the flat-buffer stride of each axis
+    pub fn strides(&self) -> &[usize] {
+        &self.strides
+    }
+
+    /// returns the value at the index, or None if the index is out of bounds
+    pub fn get(&self, idx:&[usize]) -> Option<&T> {
+        self.offset(idx).map(|i| &self.data[i])
+    }
+
+    /// returns a mutable reference to the value at the index, or None if the index is out of bounds
+    pub fn get_mut(&mut self, idx:&[usize]) -> Option<&mut T> {
+        self.offset(idx).map(|i| &mut self.data[i])
+    }
+
+    /// replaces the value at the index and returns the old one, or returns None and changes nothing
+    /// if the index is out of bounds
+    pub fn set(&mut self, idx:&[usize], value:T) -> Option<T> {
+        self.get_mut(idx).map(|x| std::mem::replace(x, value))
+    }
+
+    /// iterates over the index and value of each element in data order, so the first index entry
+    /// changes fastest
+    pub fn iter_indexed(&self) -> impl Iterator<Item = (Vec<usize>, &T)> + '_ {
+        let shape = self.shape();
+        let mut idx = vec![0;shape.len()];
+        self.data.iter().map(move |x|{
+            let current = idx.clone();
+            for (i,size) in idx.iter_mut().zip(shape) {
+                *i += 1;
+                if *i < *size {
+                    break;
+                }
+                *i = 0;
+            }
+            (current, x)
+        })
+    }
+
+    pub fn header(&self) -> &NRRD {
+        &self.header
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+
+    /// returns the underlying data and header
+    pub fn into_inner(self) -> (Vec<T>, NRRD) {
+        (self.data, self.header)
+    }
+}